@@ -0,0 +1,27 @@
+// SPDX-FileCopyrightText: Copyright (c) 2017-2022 slowtec GmbH <post@slowtec.de>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Asynchronous ASCII client example
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    use tokio_serial::{DataBits, Parity, SerialStream};
+
+    use tokio_modbus::prelude::*;
+
+    let tty_path = "/dev/ttyUSB0";
+    let slave = Slave(0x17);
+
+    // ASCII mode uses 7 data bits with even parity by default
+    let builder = tokio_serial::new(tty_path, 9600)
+        .data_bits(DataBits::Seven)
+        .parity(Parity::Even);
+    let port = SerialStream::open(&builder).unwrap();
+
+    let mut ctx = ascii::connect_slave(port, slave).await?;
+    println!("Reading a sensor value");
+    let rsp = ctx.read_holding_registers(0x082B, 2).await?;
+    println!("Sensor value is: {:?}", rsp);
+
+    Ok(())
+}
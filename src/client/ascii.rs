@@ -0,0 +1,34 @@
+// SPDX-FileCopyrightText: Copyright (c) 2017-2022 slowtec GmbH <post@slowtec.de>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! ASCII client connections
+
+use super::*;
+
+use crate::service;
+
+use std::io::Error;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// Connect to no particular Modbus slave device for sending
+/// broadcast messages.
+pub async fn connect<T>(transport: T) -> Result<Context, Error>
+where
+    T: AsyncRead + AsyncWrite + Debug + Unpin + Send + 'static,
+{
+    connect_slave(transport, Slave::broadcast()).await
+}
+
+/// Connect to any kind of Modbus slave device.
+pub async fn connect_slave<T>(transport: T, slave: Slave) -> Result<Context, Error>
+where
+    T: AsyncRead + AsyncWrite + Debug + Unpin + Send + 'static,
+{
+    let client = service::ascii::connect_slave(transport, slave).await?;
+
+    Ok(Context {
+        client: Box::new(client),
+        timeout: None,
+        retry_policy: None,
+    })
+}
@@ -0,0 +1,412 @@
+// SPDX-FileCopyrightText: Copyright (c) 2017-2022 slowtec GmbH <post@slowtec.de>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Splitting of oversized reads and writes
+
+use std::{error, fmt};
+
+use crate::{
+    frame::{Address, Coil, Quantity, Word},
+    Error,
+};
+
+use super::{Context, Reader as _, Writer as _};
+
+// [MODBUS Application Protocol Specification V1.1b3](https://modbus.org/docs/Modbus_Application_Protocol_V1_1b3.pdf)
+pub(super) const MAX_READ_COILS: Quantity = 2000;
+pub(super) const MAX_READ_REGISTERS: Quantity = 125;
+const MAX_WRITE_COILS: Quantity = 1968;
+const MAX_WRITE_REGISTERS: Quantity = 123;
+
+/// The maximum number of items per request.
+///
+/// Defaults to the maximum quantities that are allowed by the
+/// specification. Some devices support less.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkLimits {
+    read_coils: Quantity,
+    read_registers: Quantity,
+    write_coils: Quantity,
+    write_registers: Quantity,
+}
+
+impl Default for ChunkLimits {
+    fn default() -> Self {
+        Self {
+            read_coils: MAX_READ_COILS,
+            read_registers: MAX_READ_REGISTERS,
+            write_coils: MAX_WRITE_COILS,
+            write_registers: MAX_WRITE_REGISTERS,
+        }
+    }
+}
+
+impl ChunkLimits {
+    /// Limit the number of coils or discrete inputs per read request.
+    #[must_use]
+    pub fn with_max_read_coils(self, max_quantity: Quantity) -> Self {
+        Self {
+            read_coils: max_quantity.clamp(1, MAX_READ_COILS),
+            ..self
+        }
+    }
+
+    /// Limit the number of holding or input registers per read request.
+    #[must_use]
+    pub fn with_max_read_registers(self, max_quantity: Quantity) -> Self {
+        Self {
+            read_registers: max_quantity.clamp(1, MAX_READ_REGISTERS),
+            ..self
+        }
+    }
+
+    /// Limit the number of coils per write request.
+    #[must_use]
+    pub fn with_max_write_coils(self, max_quantity: Quantity) -> Self {
+        Self {
+            write_coils: max_quantity.clamp(1, MAX_WRITE_COILS),
+            ..self
+        }
+    }
+
+    /// Limit the number of holding registers per write request.
+    #[must_use]
+    pub fn with_max_write_registers(self, max_quantity: Quantity) -> Self {
+        Self {
+            write_registers: max_quantity.clamp(1, MAX_WRITE_REGISTERS),
+            ..self
+        }
+    }
+}
+
+/// A chunk of a split read or write failed.
+#[derive(Debug)]
+pub struct ChunkError {
+    /// The index of the chunk, starting with 0.
+    pub chunk: usize,
+
+    /// The address of the first item of the chunk.
+    pub address: Address,
+
+    /// The number of items of the chunk.
+    pub quantity: Quantity,
+
+    /// The cause of the failure.
+    pub error: Error,
+}
+
+impl fmt::Display for ChunkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Chunk {} ({} item(s) at address {}) failed: {}",
+            self.chunk, self.quantity, self.address, self.error
+        )
+    }
+}
+
+impl error::Error for ChunkError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+impl From<ChunkError> for Error {
+    fn from(from: ChunkError) -> Self {
+        from.error
+    }
+}
+
+/// A client context that splits reads and writes of many items into
+/// multiple requests, which are executed one after another.
+///
+/// Writes are not atomic. If a chunk fails, all previous chunks have
+/// already been written.
+#[derive(Debug)]
+pub struct ChunkedContext {
+    context: Context,
+    limits: ChunkLimits,
+}
+
+impl ChunkedContext {
+    /// Split requests of the context according to the limits.
+    #[must_use]
+    pub fn new(context: Context, limits: ChunkLimits) -> Self {
+        Self { context, limits }
+    }
+
+    /// The limits of requests.
+    #[must_use]
+    pub fn limits(&self) -> ChunkLimits {
+        self.limits
+    }
+
+    /// The underlying context, e.g. for other requests.
+    pub fn get_mut(&mut self) -> &mut Context {
+        &mut self.context
+    }
+
+    /// Unwrap the underlying context.
+    #[must_use]
+    pub fn into_inner(self) -> Context {
+        self.context
+    }
+
+    /// Read multiple coils (0x01)
+    pub async fn read_coils(
+        &mut self,
+        addr: Address,
+        cnt: Quantity,
+    ) -> Result<Vec<Coil>, ChunkError> {
+        let mut coils = Vec::with_capacity(cnt.into());
+        for (chunk, (addr, cnt)) in chunks(addr, cnt.into(), self.limits.read_coils)?.enumerate() {
+            let res = self.context.read_coils(addr, cnt).await;
+            coils.extend(chunk_result(res, chunk, addr, cnt)?);
+        }
+        Ok(coils)
+    }
+
+    /// Read multiple discrete inputs (0x02)
+    pub async fn read_discrete_inputs(
+        &mut self,
+        addr: Address,
+        cnt: Quantity,
+    ) -> Result<Vec<Coil>, ChunkError> {
+        let mut coils = Vec::with_capacity(cnt.into());
+        for (chunk, (addr, cnt)) in chunks(addr, cnt.into(), self.limits.read_coils)?.enumerate() {
+            let res = self.context.read_discrete_inputs(addr, cnt).await;
+            coils.extend(chunk_result(res, chunk, addr, cnt)?);
+        }
+        Ok(coils)
+    }
+
+    /// Read multiple holding registers (0x03)
+    pub async fn read_holding_registers(
+        &mut self,
+        addr: Address,
+        cnt: Quantity,
+    ) -> Result<Vec<Word>, ChunkError> {
+        let mut words = Vec::with_capacity(cnt.into());
+        for (chunk, (addr, cnt)) in
+            chunks(addr, cnt.into(), self.limits.read_registers)?.enumerate()
+        {
+            let res = self.context.read_holding_registers(addr, cnt).await;
+            words.extend(chunk_result(res, chunk, addr, cnt)?);
+        }
+        Ok(words)
+    }
+
+    /// Read multiple input registers (0x04)
+    pub async fn read_input_registers(
+        &mut self,
+        addr: Address,
+        cnt: Quantity,
+    ) -> Result<Vec<Word>, ChunkError> {
+        let mut words = Vec::with_capacity(cnt.into());
+        for (chunk, (addr, cnt)) in
+            chunks(addr, cnt.into(), self.limits.read_registers)?.enumerate()
+        {
+            let res = self.context.read_input_registers(addr, cnt).await;
+            words.extend(chunk_result(res, chunk, addr, cnt)?);
+        }
+        Ok(words)
+    }
+
+    /// Write multiple coils (0x0F)
+    pub async fn write_multiple_coils(
+        &mut self,
+        addr: Address,
+        coils: &[Coil],
+    ) -> Result<(), ChunkError> {
+        let mut offset = 0;
+        for (chunk, (addr, cnt)) in chunks(addr, coils.len(), self.limits.write_coils)?.enumerate()
+        {
+            let end = offset + usize::from(cnt);
+            let res = self
+                .context
+                .write_multiple_coils(addr, &coils[offset..end])
+                .await;
+            chunk_result(res, chunk, addr, cnt)?;
+            offset = end;
+        }
+        Ok(())
+    }
+
+    /// Write multiple holding registers (0x10)
+    pub async fn write_multiple_registers(
+        &mut self,
+        addr: Address,
+        words: &[Word],
+    ) -> Result<(), ChunkError> {
+        let mut offset = 0;
+        for (chunk, (addr, cnt)) in
+            chunks(addr, words.len(), self.limits.write_registers)?.enumerate()
+        {
+            let end = offset + usize::from(cnt);
+            let res = self
+                .context
+                .write_multiple_registers(addr, &words[offset..end])
+                .await;
+            chunk_result(res, chunk, addr, cnt)?;
+            offset = end;
+        }
+        Ok(())
+    }
+}
+
+impl From<Context> for ChunkedContext {
+    fn from(context: Context) -> Self {
+        Self::new(context, ChunkLimits::default())
+    }
+}
+
+fn chunk_result<T>(
+    res: Result<T, Error>,
+    chunk: usize,
+    address: Address,
+    quantity: Quantity,
+) -> Result<T, ChunkError> {
+    res.map_err(|error| ChunkError {
+        chunk,
+        address,
+        quantity,
+        error,
+    })
+}
+
+/// Split the items into consecutive chunks of at most `max_quantity`
+/// items. The items must not exceed the address space.
+#[allow(clippy::cast_possible_truncation)]
+fn chunks(
+    address: Address,
+    quantity: usize,
+    max_quantity: Quantity,
+) -> Result<impl Iterator<Item = (Address, Quantity)>, ChunkError> {
+    let end = usize::from(address) + quantity;
+    if end > usize::from(Address::MAX) + 1 {
+        return Err(ChunkError {
+            chunk: 0,
+            address,
+            quantity: Quantity::try_from(quantity).unwrap_or(Quantity::MAX),
+            error: Error::InvalidRequest(format!(
+                "{} item(s) at address {} exceed the address space",
+                quantity, address
+            )),
+        });
+    }
+    let max_quantity = usize::from(max_quantity);
+    Ok((usize::from(address)..end)
+        .step_by(max_quantity)
+        .map(move |start| {
+            let quantity = max_quantity.min(end - start);
+            // Both fit into 16 bits, because end <= 0x10000
+            (start as Address, quantity as Quantity)
+        }))
+}
+
+#[cfg(test)]
+mod tests {
+    use async_trait::async_trait;
+
+    use super::*;
+    use crate::{
+        client::Client,
+        frame::{Exception, ExceptionResponse, Request, Response},
+        slave::{Slave, SlaveContext},
+    };
+
+    /// Holding registers that fail beyond the given address
+    #[derive(Debug)]
+    struct RegisterMock {
+        registers: Vec<Word>,
+        requests: Vec<Request>,
+    }
+
+    impl RegisterMock {
+        fn new(len: usize) -> Self {
+            Self {
+                registers: (0..len).map(|i| i as Word).collect(),
+                requests: Vec::new(),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl Client for RegisterMock {
+        async fn call(&mut self, request: Request) -> Result<Response, Error> {
+            self.requests.push(request.clone());
+            let (addr, cnt) = match &request {
+                Request::ReadHoldingRegisters(addr, cnt) => (*addr, *cnt),
+                Request::WriteMultipleRegisters(addr, words) => (*addr, words.len() as Quantity),
+                _ => unimplemented!(),
+            };
+            let range = usize::from(addr)..usize::from(addr) + usize::from(cnt);
+            if range.end > self.registers.len() {
+                return Err(ExceptionResponse {
+                    function: 0x03,
+                    exception: Exception::IllegalDataAddress,
+                }
+                .into());
+            }
+            match request {
+                Request::WriteMultipleRegisters(_, words) => {
+                    self.registers[range].copy_from_slice(&words);
+                    Ok(Response::WriteMultipleRegisters(addr, cnt))
+                }
+                _ => Ok(Response::ReadHoldingRegisters(
+                    self.registers[range].to_vec(),
+                )),
+            }
+        }
+    }
+
+    impl SlaveContext for RegisterMock {
+        fn set_slave(&mut self, _: Slave) {}
+    }
+
+    fn chunked_context(mock: RegisterMock, limits: ChunkLimits) -> ChunkedContext {
+        ChunkedContext::new(Context::from(Box::new(mock) as Box<dyn Client>), limits)
+    }
+
+    #[test]
+    fn split_into_chunks() {
+        let chunks = chunks(0xFFF0, 16, 5).unwrap().collect::<Vec<_>>();
+        assert_eq!(chunks, [(0xFFF0, 5), (0xFFF5, 5), (0xFFFA, 5), (0xFFFF, 1)]);
+        assert_eq!(super::chunks(0x0000, 0, 5).unwrap().count(), 0);
+        assert!(super::chunks(0xFFF0, 17, 5).is_err());
+    }
+
+    #[tokio::test]
+    async fn read_and_write_in_chunks() {
+        let mut ctx = chunked_context(RegisterMock::new(300), ChunkLimits::default());
+        let words = ctx.read_holding_registers(10, 260).await.unwrap();
+        assert_eq!(words, (10..270).collect::<Vec<_>>());
+
+        let words = vec![0xFFFF; 200];
+        ctx.write_multiple_registers(50, &words).await.unwrap();
+        assert_eq!(ctx.read_holding_registers(50, 200).await.unwrap(), words);
+    }
+
+    #[tokio::test]
+    async fn report_failed_chunk() {
+        let limits = ChunkLimits::default().with_max_read_registers(10);
+        let mut ctx = chunked_context(RegisterMock::new(25), limits);
+        let err = ctx.read_holding_registers(0, 30).await.unwrap_err();
+        assert_eq!(err.chunk, 2);
+        assert_eq!(err.address, 20);
+        assert_eq!(err.quantity, 10);
+        assert_eq!(
+            err.error.exception().unwrap().exception,
+            Exception::IllegalDataAddress
+        );
+    }
+
+    #[test]
+    fn clamp_limits_to_specification() {
+        let limits = ChunkLimits::default()
+            .with_max_read_registers(1000)
+            .with_max_write_coils(0);
+        assert_eq!(limits.read_registers, MAX_READ_REGISTERS);
+        assert_eq!(limits.write_coils, 1);
+    }
+}
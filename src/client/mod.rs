@@ -3,29 +3,88 @@
 
 //! Modbus clients
 
-use std::{
-    fmt::Debug,
-    io::{Error, ErrorKind},
-};
+use std::{fmt::Debug, io::ErrorKind, time::Duration};
 
 use async_trait::async_trait;
 
-use crate::{frame::*, slave::*};
+use crate::{frame::*, slave::*, Error};
+
+mod retry;
+pub use self::retry::{Backoff, RetryPolicy};
+
+#[cfg(any(feature = "rtu", feature = "tcp"))]
+mod reconnect;
+#[cfg(any(feature = "rtu", feature = "tcp"))]
+pub use self::reconnect::{ConnectionState, ReconnectPolicy};
+
+mod shared;
+pub use self::shared::SharedClient;
+
+mod chunked;
+pub use self::chunked::{ChunkError, ChunkLimits, ChunkedContext};
+
+mod planner;
+pub use self::planner::{ReadItem, ReadPlanner, ReadValues, RegisterType};
 
 #[cfg(feature = "sync")]
 pub mod sync;
 
+#[cfg(feature = "ascii")]
+pub mod ascii;
+
 #[cfg(feature = "rtu")]
 pub mod rtu;
 
 #[cfg(feature = "tcp")]
 pub mod tcp;
 
+#[cfg(feature = "tls")]
+pub mod tls;
+
+#[cfg(feature = "udp")]
+pub mod udp;
+
+/// The handling of requests that are sent to a broadcast address,
+/// e.g. [`Slave::broadcast()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Broadcast {
+    /// The requests are answered like requests to any other slave,
+    /// e.g. by a Modbus TCP device that ignores the unit id.
+    Disabled,
+
+    /// The requests to the `broadcast_ids` are received by all slaves
+    /// and not answered.
+    ///
+    /// Requests that write data do not wait for a response. Instead
+    /// they return after the `turnaround_delay`, which allows the
+    /// slaves to process the request. Requests that read data are
+    /// rejected with [`Error::InvalidRequest`].
+    Enabled {
+        /// The addresses of broadcasts, e.g. [`BroadcastIds::SERIAL_LINE`].
+        broadcast_ids: BroadcastIds,
+
+        /// The delay after sending a request.
+        turnaround_delay: Duration,
+    },
+}
+
 /// Transport independent asynchronous client trait
 #[async_trait]
 pub trait Client: SlaveContext + Send + Debug {
     /// Invoke a Modbus function
     async fn call(&mut self, request: Request) -> Result<Response, Error>;
+
+    /// Change the handling of requests that are sent to a broadcast address.
+    ///
+    /// Ignored by clients that do not support changing it.
+    fn set_broadcast(&mut self, _broadcast: Broadcast) {}
+
+    /// The counters of bus errors that have been detected while
+    /// decoding frames, if connected to an RTU device.
+    #[cfg(feature = "rtu")]
+    fn rtu_error_counters(&self) -> Option<rtu::ErrorCounters> {
+        None
+    }
 }
 
 /// Asynchronous Modbus reader
@@ -55,6 +114,50 @@ pub trait Reader: Client {
         _: Address,
         _: &[Word],
     ) -> Result<Vec<Word>, Error>;
+
+    /// Read the contents of a FIFO queue of registers (0x18)
+    async fn read_fifo_queue(&mut self, _: Address) -> Result<Vec<Word>, Error>;
+
+    /// Read records of files (0x14)
+    ///
+    /// Returns the registers of each record in the order of the sub-requests.
+    async fn read_file_record(
+        &mut self,
+        _: &[ReadFileRecordSubRequest],
+    ) -> Result<Vec<Vec<Word>>, Error>;
+
+    /// Read the eight exception status outputs of a serial line device (0x07)
+    async fn read_exception_status(&mut self) -> Result<u8, Error>;
+
+    /// Read the status word and the event counter of a serial line device (0x0B)
+    async fn get_comm_event_counter(&mut self) -> Result<(Word, Word), Error>;
+
+    /// Read the communications event log of a serial line device (0x0C)
+    async fn get_comm_event_log(&mut self) -> Result<CommEventLog, Error>;
+
+    /// Read the server id, the run indicator status, and additional,
+    /// device specific data of a serial line device (0x11)
+    async fn report_server_id(&mut self) -> Result<(u8, bool, Vec<u8>), Error>;
+
+    /// Perform a diagnostics sub-function on a serial line device (0x08)
+    ///
+    /// Returns the data of the response, i.e. either the echoed data
+    /// or the requested value like the contents of a counter.
+    async fn diagnostics(
+        &mut self,
+        _: DiagnosticsSubFunction,
+        _: &[Word],
+    ) -> Result<Vec<Word>, Error>;
+
+    /// Read the identification of a device (0x2B / MEI 0x0E)
+    ///
+    /// Starting with the given object, the request is repeated
+    /// until all objects of the requested category have been read.
+    async fn read_device_identification(
+        &mut self,
+        _: ReadDeviceIdCode,
+        _: DeviceIdObject,
+    ) -> Result<DeviceIdentification, Error>;
 }
 
 /// Asynchronous Modbus writer
@@ -71,32 +174,148 @@ pub trait Writer: Client {
 
     /// Write multiple holding registers (0x10)
     async fn write_multiple_registers(&mut self, _: Address, _: &[Word]) -> Result<(), Error>;
+
+    /// Modify a single holding register with an AND and an OR mask (0x16)
+    ///
+    /// Bits that are set in the AND mask are kept, all other
+    /// bits are replaced by the corresponding bits of the OR mask.
+    async fn mask_write_register(&mut self, _: Address, _: Word, _: Word) -> Result<(), Error>;
+
+    /// Write records of files (0x15)
+    async fn write_file_record(&mut self, _: &[WriteFileRecordSubRequest]) -> Result<(), Error>;
 }
 
 /// Asynchronous Modbus client context
 #[derive(Debug)]
 pub struct Context {
     client: Box<dyn Client>,
+    timeout: Option<Duration>,
+    retry_policy: Option<RetryPolicy>,
 }
 
 impl Context {
+    /// Set the default timeout for receiving a response (optional).
+    ///
+    /// Requests fail with [`Error::Timeout`] if no response has
+    /// been received in time. There is no timeout by default.
+    #[must_use]
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        Self {
+            timeout: Some(timeout),
+            ..self
+        }
+    }
+
+    /// Change or disable the default timeout for receiving a response.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// The default timeout for receiving a response.
+    #[must_use]
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// Retry failed requests according to the policy (optional).
+    ///
+    /// The policy applies to all requests, including those of
+    /// [`Reader`] and [`Writer`]. Requests are not retried by default.
+    #[must_use]
+    pub fn with_retry_policy(self, retry_policy: RetryPolicy) -> Self {
+        Self {
+            retry_policy: Some(retry_policy),
+            ..self
+        }
+    }
+
+    /// Change or disable the retry policy.
+    pub fn set_retry_policy(&mut self, retry_policy: Option<RetryPolicy>) {
+        self.retry_policy = retry_policy;
+    }
+
+    /// The retry policy.
+    #[must_use]
+    pub fn retry_policy(&self) -> Option<&RetryPolicy> {
+        self.retry_policy.as_ref()
+    }
+
+    /// Change the handling of requests that are sent to a broadcast
+    /// address (optional).
+    ///
+    /// Broadcasts to [`BroadcastIds::SERIAL_LINE`] are enabled by default
+    /// for RTU and ASCII. They are disabled for TCP, where the unit ids
+    /// 0 and 255 often address the gateway itself.
+    #[must_use]
+    pub fn with_broadcast(mut self, broadcast: Broadcast) -> Self {
+        self.client.set_broadcast(broadcast);
+        self
+    }
+
+    /// Invoke a Modbus function with a timeout that overrides the
+    /// default timeout.
+    pub async fn call_with_timeout(
+        &mut self,
+        request: Request,
+        timeout: Duration,
+    ) -> Result<Response, Error> {
+        self.call_with_policy(request, Some(timeout)).await
+    }
+
+    async fn call_with_policy(
+        &mut self,
+        request: Request,
+        timeout: Option<Duration>,
+    ) -> Result<Response, Error> {
+        match &self.retry_policy {
+            Some(retry_policy) => {
+                retry::call_with_retries(&mut *self.client, retry_policy, request, timeout).await
+            }
+            None => call_with_timeout(&mut *self.client, request, timeout).await,
+        }
+    }
+
     /// Disconnect the client
     pub async fn disconnect(&mut self) -> Result<(), Error> {
         // Disconnecting is expected to fail!
         let res = self.client.call(Request::Disconnect).await;
         match res {
             Ok(_) => unreachable!(),
-            Err(err) => match err.kind() {
-                ErrorKind::NotConnected | ErrorKind::BrokenPipe => Ok(()),
-                _ => Err(err),
-            },
+            Err(Error::Transport(err))
+                if matches!(err.kind(), ErrorKind::NotConnected | ErrorKind::BrokenPipe) =>
+            {
+                Ok(())
+            }
+            Err(err) => Err(err),
         }
     }
 }
 
+/// Invoke a Modbus function and wait for the response until
+/// the timeout expires.
+///
+/// The clients discard the late response of a request that has
+/// timed out when they are called next.
+async fn call_with_timeout(
+    client: &mut dyn Client,
+    request: Request,
+    timeout: Option<Duration>,
+) -> Result<Response, Error> {
+    let Some(timeout) = timeout else {
+        return client.call(request).await;
+    };
+    tokio::time::timeout(timeout, client.call(request))
+        .await
+        .map_err(|_| Error::Timeout)?
+}
+
 impl From<Box<dyn Client>> for Context {
     fn from(client: Box<dyn Client>) -> Self {
-        Self { client }
+        Self {
+            client,
+            timeout: None,
+            retry_policy: None,
+        }
     }
 }
 
@@ -109,7 +328,16 @@ impl From<Context> for Box<dyn Client> {
 #[async_trait]
 impl Client for Context {
     async fn call<'a>(&'a mut self, request: Request) -> Result<Response, Error> {
-        self.client.call(request).await
+        self.call_with_policy(request, self.timeout).await
+    }
+
+    fn set_broadcast(&mut self, broadcast: Broadcast) {
+        self.client.set_broadcast(broadcast);
+    }
+
+    #[cfg(feature = "rtu")]
+    fn rtu_error_counters(&self) -> Option<rtu::ErrorCounters> {
+        self.client.rtu_error_counters()
     }
 }
 
@@ -126,14 +354,14 @@ impl Reader for Context {
         addr: Address,
         cnt: Quantity,
     ) -> Result<Vec<Coil>, Error> {
-        let rsp = self.client.call(Request::ReadCoils(addr, cnt)).await?;
+        let rsp = self.call(Request::ReadCoils(addr, cnt)).await?;
 
         if let Response::ReadCoils(mut coils) = rsp {
             debug_assert!(coils.len() >= cnt.into());
             coils.truncate(cnt.into());
             Ok(coils)
         } else {
-            Err(Error::new(ErrorKind::InvalidData, "unexpected response"))
+            Err(Error::unexpected_response())
         }
     }
 
@@ -142,17 +370,14 @@ impl Reader for Context {
         addr: Address,
         cnt: Quantity,
     ) -> Result<Vec<Coil>, Error> {
-        let rsp = self
-            .client
-            .call(Request::ReadDiscreteInputs(addr, cnt))
-            .await?;
+        let rsp = self.call(Request::ReadDiscreteInputs(addr, cnt)).await?;
 
         if let Response::ReadDiscreteInputs(mut coils) = rsp {
             debug_assert!(coils.len() >= cnt.into());
             coils.truncate(cnt.into());
             Ok(coils)
         } else {
-            Err(Error::new(ErrorKind::InvalidData, "unexpected response"))
+            Err(Error::unexpected_response())
         }
     }
 
@@ -161,18 +386,15 @@ impl Reader for Context {
         addr: Address,
         cnt: Quantity,
     ) -> Result<Vec<Word>, Error> {
-        let rsp = self
-            .client
-            .call(Request::ReadInputRegisters(addr, cnt))
-            .await?;
+        let rsp = self.call(Request::ReadInputRegisters(addr, cnt)).await?;
 
         if let Response::ReadInputRegisters(rsp) = rsp {
             if rsp.len() != cnt.into() {
-                return Err(Error::new(ErrorKind::InvalidData, "invalid response"));
+                return Err(Error::invalid_response());
             }
             Ok(rsp)
         } else {
-            Err(Error::new(ErrorKind::InvalidData, "unexpected response"))
+            Err(Error::unexpected_response())
         }
     }
 
@@ -181,18 +403,15 @@ impl Reader for Context {
         addr: Address,
         cnt: Quantity,
     ) -> Result<Vec<Word>, Error> {
-        let rsp = self
-            .client
-            .call(Request::ReadHoldingRegisters(addr, cnt))
-            .await?;
+        let rsp = self.call(Request::ReadHoldingRegisters(addr, cnt)).await?;
 
         if let Response::ReadHoldingRegisters(rsp) = rsp {
             if rsp.len() != cnt.into() {
-                return Err(Error::new(ErrorKind::InvalidData, "invalid response"));
+                return Err(Error::invalid_response());
             }
             Ok(rsp)
         } else {
-            Err(Error::new(ErrorKind::InvalidData, "unexpected response"))
+            Err(Error::unexpected_response())
         }
     }
 
@@ -204,7 +423,6 @@ impl Reader for Context {
         write_data: &[Word],
     ) -> Result<Vec<Word>, Error> {
         let rsp = self
-            .client
             .call(Request::ReadWriteMultipleRegisters(
                 read_addr,
                 read_cnt,
@@ -215,11 +433,155 @@ impl Reader for Context {
 
         if let Response::ReadWriteMultipleRegisters(rsp) = rsp {
             if rsp.len() != read_cnt.into() {
-                return Err(Error::new(ErrorKind::InvalidData, "invalid response"));
+                return Err(Error::invalid_response());
             }
             Ok(rsp)
         } else {
-            Err(Error::new(ErrorKind::InvalidData, "unexpected response"))
+            Err(Error::unexpected_response())
+        }
+    }
+
+    async fn read_fifo_queue<'a>(&'a mut self, addr: Address) -> Result<Vec<Word>, Error> {
+        let rsp = self.call(Request::ReadFifoQueue(addr)).await?;
+
+        if let Response::ReadFifoQueue(rsp) = rsp {
+            Ok(rsp)
+        } else {
+            Err(Error::unexpected_response())
+        }
+    }
+
+    async fn read_file_record<'a>(
+        &'a mut self,
+        sub_requests: &[ReadFileRecordSubRequest],
+    ) -> Result<Vec<Vec<Word>>, Error> {
+        let rsp = self
+            .call(Request::ReadFileRecord(sub_requests.to_vec()))
+            .await?;
+
+        if let Response::ReadFileRecord(records) = rsp {
+            if records.len() != sub_requests.len()
+                || records
+                    .iter()
+                    .zip(sub_requests)
+                    .any(|(record, sub_request)| {
+                        record.len() != usize::from(sub_request.record_length)
+                    })
+            {
+                return Err(Error::invalid_response());
+            }
+            Ok(records)
+        } else {
+            Err(Error::unexpected_response())
+        }
+    }
+
+    async fn read_exception_status<'a>(&'a mut self) -> Result<u8, Error> {
+        let rsp = self.call(Request::ReadExceptionStatus).await?;
+
+        if let Response::ReadExceptionStatus(status) = rsp {
+            Ok(status)
+        } else {
+            Err(Error::unexpected_response())
+        }
+    }
+
+    async fn get_comm_event_counter<'a>(&'a mut self) -> Result<(Word, Word), Error> {
+        let rsp = self.call(Request::GetCommEventCounter).await?;
+
+        if let Response::GetCommEventCounter(status, event_count) = rsp {
+            Ok((status, event_count))
+        } else {
+            Err(Error::unexpected_response())
+        }
+    }
+
+    async fn get_comm_event_log<'a>(&'a mut self) -> Result<CommEventLog, Error> {
+        let rsp = self.call(Request::GetCommEventLog).await?;
+
+        if let Response::GetCommEventLog(log) = rsp {
+            Ok(log)
+        } else {
+            Err(Error::unexpected_response())
+        }
+    }
+
+    async fn report_server_id<'a>(&'a mut self) -> Result<(u8, bool, Vec<u8>), Error> {
+        let rsp = self.call(Request::ReportServerId).await?;
+
+        if let Response::ReportServerId(server_id, run_indicator, additional_data) = rsp {
+            Ok((server_id, run_indicator, additional_data))
+        } else {
+            Err(Error::unexpected_response())
+        }
+    }
+
+    async fn diagnostics<'a>(
+        &'a mut self,
+        sub_function: DiagnosticsSubFunction,
+        data: &[Word],
+    ) -> Result<Vec<Word>, Error> {
+        let rsp = self
+            .call(Request::Diagnostics(sub_function, data.to_vec()))
+            .await?;
+
+        if let Response::Diagnostics(rsp_sub_function, rsp_data) = rsp {
+            if rsp_sub_function != sub_function
+                || (sub_function == DiagnosticsSubFunction::ReturnQueryData && rsp_data != data)
+            {
+                return Err(Error::invalid_response());
+            }
+            Ok(rsp_data)
+        } else {
+            Err(Error::unexpected_response())
+        }
+    }
+
+    async fn read_device_identification<'a>(
+        &'a mut self,
+        read_device_id_code: ReadDeviceIdCode,
+        object: DeviceIdObject,
+    ) -> Result<DeviceIdentification, Error> {
+        let mut next_object = object;
+        let mut device_id: Option<DeviceIdentification> = None;
+        loop {
+            let rsp = self
+                .call(Request::ReadDeviceIdentification(
+                    read_device_id_code,
+                    next_object,
+                ))
+                .await?;
+
+            let Response::ReadDeviceIdentification(rsp) = rsp else {
+                return Err(Error::unexpected_response());
+            };
+            if rsp.read_device_id_code != read_device_id_code {
+                return Err(Error::invalid_response());
+            }
+            // Individual access always returns a single object
+            let more_follows =
+                rsp.more_follows && read_device_id_code != ReadDeviceIdCode::Specific;
+            if more_follows
+                && (rsp.objects.is_empty() || u8::from(rsp.next_object) <= u8::from(next_object))
+            {
+                return Err(Error::invalid_response());
+            }
+            next_object = rsp.next_object;
+
+            let mut rsp = match device_id.take() {
+                Some(mut device_id) => {
+                    device_id.conformity_level = rsp.conformity_level;
+                    device_id.objects.extend(rsp.objects);
+                    device_id
+                }
+                None => rsp,
+            };
+            if !more_follows {
+                rsp.more_follows = false;
+                rsp.next_object = DeviceIdObject::VendorName;
+                return Ok(rsp);
+            }
+            device_id = Some(rsp);
         }
     }
 }
@@ -227,18 +589,15 @@ impl Reader for Context {
 #[async_trait]
 impl Writer for Context {
     async fn write_single_coil<'a>(&'a mut self, addr: Address, coil: Coil) -> Result<(), Error> {
-        let rsp = self
-            .client
-            .call(Request::WriteSingleCoil(addr, coil))
-            .await?;
+        let rsp = self.call(Request::WriteSingleCoil(addr, coil)).await?;
 
         if let Response::WriteSingleCoil(rsp_addr, rsp_coil) = rsp {
             if rsp_addr != addr || rsp_coil != coil {
-                return Err(Error::new(ErrorKind::InvalidData, "invalid response"));
+                return Err(Error::invalid_response());
             }
             Ok(())
         } else {
-            Err(Error::new(ErrorKind::InvalidData, "unexpected response"))
+            Err(Error::unexpected_response())
         }
     }
 
@@ -249,17 +608,16 @@ impl Writer for Context {
     ) -> Result<(), Error> {
         let cnt = coils.len();
         let rsp = self
-            .client
             .call(Request::WriteMultipleCoils(addr, coils.to_vec()))
             .await?;
 
         if let Response::WriteMultipleCoils(rsp_addr, rsp_cnt) = rsp {
             if rsp_addr != addr || usize::from(rsp_cnt) != cnt {
-                return Err(Error::new(ErrorKind::InvalidData, "invalid response"));
+                return Err(Error::invalid_response());
             }
             Ok(())
         } else {
-            Err(Error::new(ErrorKind::InvalidData, "unexpected response"))
+            Err(Error::unexpected_response())
         }
     }
 
@@ -268,18 +626,15 @@ impl Writer for Context {
         addr: Address,
         data: Word,
     ) -> Result<(), Error> {
-        let rsp = self
-            .client
-            .call(Request::WriteSingleRegister(addr, data))
-            .await?;
+        let rsp = self.call(Request::WriteSingleRegister(addr, data)).await?;
 
         if let Response::WriteSingleRegister(rsp_addr, rsp_word) = rsp {
             if rsp_addr != addr || rsp_word != data {
-                return Err(Error::new(ErrorKind::InvalidData, "invalid response"));
+                return Err(Error::invalid_response());
             }
             Ok(())
         } else {
-            Err(Error::new(ErrorKind::InvalidData, "unexpected response"))
+            Err(Error::unexpected_response())
         }
     }
 
@@ -290,17 +645,54 @@ impl Writer for Context {
     ) -> Result<(), Error> {
         let cnt = data.len();
         let rsp = self
-            .client
             .call(Request::WriteMultipleRegisters(addr, data.to_vec()))
             .await?;
 
         if let Response::WriteMultipleRegisters(rsp_addr, rsp_cnt) = rsp {
             if rsp_addr != addr || usize::from(rsp_cnt) != cnt {
-                return Err(Error::new(ErrorKind::InvalidData, "invalid response"));
+                return Err(Error::invalid_response());
+            }
+            Ok(())
+        } else {
+            Err(Error::unexpected_response())
+        }
+    }
+
+    async fn mask_write_register<'a>(
+        &'a mut self,
+        addr: Address,
+        and_mask: Word,
+        or_mask: Word,
+    ) -> Result<(), Error> {
+        let rsp = self
+            .call(Request::MaskWriteRegister(addr, and_mask, or_mask))
+            .await?;
+
+        if let Response::MaskWriteRegister(rsp_addr, rsp_and_mask, rsp_or_mask) = rsp {
+            if rsp_addr != addr || rsp_and_mask != and_mask || rsp_or_mask != or_mask {
+                return Err(Error::invalid_response());
+            }
+            Ok(())
+        } else {
+            Err(Error::unexpected_response())
+        }
+    }
+
+    async fn write_file_record<'a>(
+        &'a mut self,
+        sub_requests: &[WriteFileRecordSubRequest],
+    ) -> Result<(), Error> {
+        let rsp = self
+            .call(Request::WriteFileRecord(sub_requests.to_vec()))
+            .await?;
+
+        if let Response::WriteFileRecord(rsp_sub_requests) = rsp {
+            if rsp_sub_requests != sub_requests {
+                return Err(Error::invalid_response());
             }
             Ok(())
         } else {
-            Err(Error::new(ErrorKind::InvalidData, "unexpected response"))
+            Err(Error::unexpected_response())
         }
     }
 }
@@ -338,7 +730,8 @@ mod tests {
             *self.last_request.lock().unwrap() = Some(request);
             match self.next_response.as_ref().unwrap() {
                 Ok(response) => Ok(response.clone()),
-                Err(err) => Err(Error::new(err.kind(), format!("{}", err))),
+                // Errors cannot be cloned and are only returned once
+                Err(_) => self.next_response.take().unwrap(),
             }
         }
     }
@@ -357,7 +750,7 @@ mod tests {
         for num_coils in 1usize..8usize {
             let mut client = Box::<ClientMock>::default();
             client.set_next_response(Ok(Response::ReadCoils(response_coils.clone())));
-            let mut context = Context { client };
+            let mut context = Context::from(client as Box<dyn Client>);
             context.set_slave(Slave(1));
             let coils =
                 futures::executor::block_on(context.read_coils(1, num_coils as u16)).unwrap();
@@ -373,7 +766,7 @@ mod tests {
         for num_inputs in 1usize..8usize {
             let mut client = Box::<ClientMock>::default();
             client.set_next_response(Ok(Response::ReadDiscreteInputs(response_inputs.clone())));
-            let mut context = Context { client };
+            let mut context = Context::from(client as Box<dyn Client>);
             context.set_slave(Slave(1));
             let inputs =
                 futures::executor::block_on(context.read_discrete_inputs(1, num_inputs as u16))
@@ -381,4 +774,219 @@ mod tests {
             assert_eq!(&response_inputs[0..num_inputs], &inputs[..]);
         }
     }
+
+    #[test]
+    fn mask_write_register() {
+        let mut client = Box::<ClientMock>::default();
+        client.set_next_response(Ok(Response::MaskWriteRegister(0x04, 0xF2, 0x25)));
+        let mut context = Context::from(client as Box<dyn Client>);
+        futures::executor::block_on(context.mask_write_register(0x04, 0xF2, 0x25)).unwrap();
+
+        // The response must echo the request
+        let res = futures::executor::block_on(context.mask_write_register(0x04, 0xF2, 0x26));
+        assert!(matches!(res, Err(Error::UnexpectedResponse(_))));
+    }
+
+    #[test]
+    fn read_file_record() {
+        let sub_requests = [
+            ReadFileRecordSubRequest {
+                file_number: 4,
+                record_number: 1,
+                record_length: 2,
+            },
+            ReadFileRecordSubRequest {
+                file_number: 3,
+                record_number: 9,
+                record_length: 1,
+            },
+        ];
+        let mut client = Box::<ClientMock>::default();
+        client.set_next_response(Ok(Response::ReadFileRecord(vec![
+            vec![0x0DFE, 0x0020],
+            vec![0x33CD],
+        ])));
+        let mut context = Context::from(client as Box<dyn Client>);
+        let records = futures::executor::block_on(context.read_file_record(&sub_requests)).unwrap();
+        assert_eq!(records, vec![vec![0x0DFE, 0x0020], vec![0x33CD]]);
+
+        // The length of each record must match its sub-request
+        let res = futures::executor::block_on(context.read_file_record(&sub_requests[..1]));
+        assert!(matches!(res, Err(Error::UnexpectedResponse(_))));
+    }
+
+    #[test]
+    fn write_file_record() {
+        let sub_requests = [WriteFileRecordSubRequest {
+            file_number: 4,
+            record_number: 7,
+            record_data: vec![0x06AF, 0x04BE, 0x100D],
+        }];
+        let mut client = Box::<ClientMock>::default();
+        client.set_next_response(Ok(Response::WriteFileRecord(sub_requests.to_vec())));
+        let mut context = Context::from(client as Box<dyn Client>);
+        futures::executor::block_on(context.write_file_record(&sub_requests)).unwrap();
+
+        // The response must echo the request
+        let res = futures::executor::block_on(context.write_file_record(&sub_requests[..0]));
+        assert!(matches!(res, Err(Error::UnexpectedResponse(_))));
+    }
+
+    #[test]
+    fn report_server_id() {
+        let mut client = Box::<ClientMock>::default();
+        client.set_next_response(Ok(Response::ReportServerId(0x42, true, vec![0x01, 0x02])));
+        let mut context = Context::from(client as Box<dyn Client>);
+        let rsp = futures::executor::block_on(context.report_server_id()).unwrap();
+        assert_eq!(rsp, (0x42, true, vec![0x01, 0x02]));
+
+        // Responses of other functions are rejected
+        let res = futures::executor::block_on(context.read_exception_status());
+        assert!(matches!(res, Err(Error::UnexpectedResponse(_))));
+    }
+
+    #[test]
+    fn return_bus_message_count() {
+        let mut client = Box::<ClientMock>::default();
+        client.set_next_response(Ok(Response::Diagnostics(
+            DiagnosticsSubFunction::ReturnBusMessageCount,
+            vec![0x0123],
+        )));
+        let mut context = Context::from(client as Box<dyn Client>);
+        let count = futures::executor::block_on(
+            context.diagnostics(DiagnosticsSubFunction::ReturnBusMessageCount, &[0x0000]),
+        )
+        .unwrap();
+        assert_eq!(count, vec![0x0123]);
+
+        // The query data must be echoed
+        let res = futures::executor::block_on(
+            context.diagnostics(DiagnosticsSubFunction::ReturnQueryData, &[0x0123]),
+        );
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn return_query_data() {
+        let mut client = Box::<ClientMock>::default();
+        client.set_next_response(Ok(Response::Diagnostics(
+            DiagnosticsSubFunction::ReturnQueryData,
+            vec![0x0123, 0x4567, 0x89AB],
+        )));
+        let mut context = Context::from(client as Box<dyn Client>);
+        let data = futures::executor::block_on(context.diagnostics(
+            DiagnosticsSubFunction::ReturnQueryData,
+            &[0x0123, 0x4567, 0x89AB],
+        ))
+        .unwrap();
+        assert_eq!(data, vec![0x0123, 0x4567, 0x89AB]);
+
+        // A truncated echo is rejected
+        let mut client = Box::<ClientMock>::default();
+        client.set_next_response(Ok(Response::Diagnostics(
+            DiagnosticsSubFunction::ReturnQueryData,
+            vec![0x0123],
+        )));
+        let mut context = Context::from(client as Box<dyn Client>);
+        let res = futures::executor::block_on(context.diagnostics(
+            DiagnosticsSubFunction::ReturnQueryData,
+            &[0x0123, 0x4567, 0x89AB],
+        ));
+        assert!(matches!(res, Err(Error::UnexpectedResponse(_))));
+    }
+
+    /// Returns at most two objects per response
+    #[derive(Debug)]
+    struct DeviceIdMock {
+        objects: Vec<(DeviceIdObject, Vec<u8>)>,
+    }
+
+    #[async_trait]
+    impl Client for DeviceIdMock {
+        async fn call<'a>(&'a mut self, request: Request) -> Result<Response, Error> {
+            let Request::ReadDeviceIdentification(read_device_id_code, object) = request else {
+                unimplemented!()
+            };
+            let mut remaining = self
+                .objects
+                .iter()
+                .filter(|(id, _)| *id >= object)
+                .cloned()
+                .collect::<Vec<_>>();
+            let next = remaining.split_off(remaining.len().min(2));
+            Ok(Response::ReadDeviceIdentification(DeviceIdentification {
+                read_device_id_code,
+                conformity_level: ConformityLevel {
+                    category: ReadDeviceIdCode::Regular,
+                    individual_access: false,
+                },
+                more_follows: !next.is_empty(),
+                next_object: next
+                    .first()
+                    .map_or(DeviceIdObject::VendorName, |(id, _)| *id),
+                objects: remaining.into_iter().collect(),
+            }))
+        }
+    }
+
+    impl SlaveContext for DeviceIdMock {
+        fn set_slave(&mut self, _: Slave) {}
+    }
+
+    #[test]
+    fn read_device_identification_until_no_more_follows() {
+        let objects = vec![
+            (DeviceIdObject::VendorName, b"Vendor".to_vec()),
+            (DeviceIdObject::ProductCode, b"P-1".to_vec()),
+            (DeviceIdObject::MajorMinorRevision, b"V1.2".to_vec()),
+            (DeviceIdObject::VendorUrl, b"http://example.com".to_vec()),
+            (DeviceIdObject::ProductName, b"Product".to_vec()),
+        ];
+        let mut context = Context::from(Box::new(DeviceIdMock {
+            objects: objects.clone(),
+        }) as Box<dyn Client>);
+        let device_id = futures::executor::block_on(
+            context
+                .read_device_identification(ReadDeviceIdCode::Regular, DeviceIdObject::VendorName),
+        )
+        .unwrap();
+        assert!(!device_id.more_follows);
+        assert_eq!(device_id.objects, objects.into_iter().collect());
+        assert_eq!(device_id.vendor_name(), Some("Vendor"));
+        assert_eq!(device_id.product_code(), Some("P-1"));
+        assert_eq!(device_id.major_minor_revision(), Some("V1.2"));
+    }
+
+    /// Never responds
+    #[derive(Debug)]
+    struct UnresponsiveMock;
+
+    #[async_trait]
+    impl Client for UnresponsiveMock {
+        async fn call<'a>(&'a mut self, _: Request) -> Result<Response, Error> {
+            futures::future::pending().await
+        }
+    }
+
+    impl SlaveContext for UnresponsiveMock {
+        fn set_slave(&mut self, _: Slave) {}
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn fail_without_response_in_time() {
+        let mut context = Context::from(Box::new(UnresponsiveMock) as Box<dyn Client>)
+            .with_timeout(Duration::from_secs(1));
+        assert_eq!(context.timeout(), Some(Duration::from_secs(1)));
+        let res = context.read_holding_registers(0x00, 1).await;
+        assert!(matches!(res, Err(Error::Timeout)));
+
+        // Override the default timeout
+        context.set_timeout(None);
+        let start = tokio::time::Instant::now();
+        let res = context
+            .call_with_timeout(Request::ReadCoils(0x00, 1), Duration::from_millis(10))
+            .await;
+        assert!(matches!(res, Err(Error::Timeout)));
+        assert_eq!(start.elapsed(), Duration::from_millis(10));
+    }
 }
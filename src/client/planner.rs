@@ -0,0 +1,550 @@
+// SPDX-FileCopyrightText: Copyright (c) 2017-2022 slowtec GmbH <post@slowtec.de>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Coalescing of scattered reads
+
+use std::collections::BTreeSet;
+
+use crate::{
+    frame::{Address, Coil, Exception, ExceptionResponse, Quantity, Request, Response, Word},
+    Error,
+};
+
+use super::{
+    chunked::{MAX_READ_COILS, MAX_READ_REGISTERS},
+    Client,
+};
+
+/// The type of a readable data object.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum RegisterType {
+    /// Read with Read Coils (0x01)
+    Coil,
+
+    /// Read with Read Discrete Inputs (0x02)
+    DiscreteInput,
+
+    /// Read with Read Holding Registers (0x03)
+    HoldingRegister,
+
+    /// Read with Read Input Registers (0x04)
+    InputRegister,
+}
+
+impl RegisterType {
+    const fn max_quantity(self) -> Quantity {
+        match self {
+            Self::Coil | Self::DiscreteInput => MAX_READ_COILS,
+            Self::HoldingRegister | Self::InputRegister => MAX_READ_REGISTERS,
+        }
+    }
+
+    const fn request(self, addr: Address, cnt: Quantity) -> Request {
+        match self {
+            Self::Coil => Request::ReadCoils(addr, cnt),
+            Self::DiscreteInput => Request::ReadDiscreteInputs(addr, cnt),
+            Self::HoldingRegister => Request::ReadHoldingRegisters(addr, cnt),
+            Self::InputRegister => Request::ReadInputRegisters(addr, cnt),
+        }
+    }
+
+    /// Extract the values of an item from the response of a block.
+    fn values(self, rsp: &Response, offset: usize, cnt: Quantity) -> Result<ReadValues, Error> {
+        fn slice<T: Clone>(values: &[T], offset: usize, cnt: Quantity) -> Result<Vec<T>, Error> {
+            values
+                .get(offset..offset + usize::from(cnt))
+                .map(<[T]>::to_vec)
+                .ok_or_else(Error::invalid_response)
+        }
+        match (self, rsp) {
+            (Self::Coil, Response::ReadCoils(coils))
+            | (Self::DiscreteInput, Response::ReadDiscreteInputs(coils)) => {
+                slice(coils, offset, cnt).map(ReadValues::Coils)
+            }
+            (Self::HoldingRegister, Response::ReadHoldingRegisters(words))
+            | (Self::InputRegister, Response::ReadInputRegisters(words)) => {
+                slice(words, offset, cnt).map(ReadValues::Words)
+            }
+            _ => Err(Error::unexpected_response()),
+        }
+    }
+}
+
+/// A range of data objects that should be read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ReadItem {
+    pub register_type: RegisterType,
+    pub address: Address,
+    pub quantity: Quantity,
+}
+
+impl ReadItem {
+    fn end(&self) -> usize {
+        usize::from(self.address) + usize::from(self.quantity)
+    }
+
+    /// Reject items that could not be read with a single request.
+    fn validate(&self) -> Result<(), Error> {
+        let max_quantity = self.register_type.max_quantity();
+        if self.quantity == 0 || self.quantity > max_quantity {
+            return Err(Error::InvalidRequest(format!(
+                "Quantity of {:?} is not within 1..={}",
+                self, max_quantity
+            )));
+        }
+        if self.end() > usize::from(Address::MAX) + 1 {
+            return Err(Error::InvalidRequest(format!(
+                "{:?} exceeds the address space",
+                self
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// The values of a [`ReadItem`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReadValues {
+    /// Coils or discrete inputs
+    Coils(Vec<Coil>),
+
+    /// Holding or input registers
+    Words(Vec<Word>),
+}
+
+/// Items that are read with a single request.
+#[derive(Debug)]
+struct Block {
+    register_type: RegisterType,
+    address: Address,
+    end: usize,
+    /// Indexes of the items, ordered by address
+    items: Vec<usize>,
+    isolated: bool,
+}
+
+impl Block {
+    fn new(index: usize, item: &ReadItem, isolated: bool) -> Self {
+        Self {
+            register_type: item.register_type,
+            address: item.address,
+            end: item.end(),
+            items: vec![index],
+            isolated,
+        }
+    }
+
+    // Blocks never exceed the quantity of a single request
+    #[allow(clippy::cast_possible_truncation)]
+    fn request(&self) -> Request {
+        let cnt = (self.end - usize::from(self.address)) as Quantity;
+        self.register_type.request(self.address, cnt)
+    }
+}
+
+/// Combines reads of scattered items into as few requests as
+/// possible.
+///
+/// Items of the same type are read with a single request if the
+/// addresses in between do not exceed the maximum gap and the
+/// request does not exceed the maximum block size. The values of
+/// the gaps are discarded.
+///
+/// Devices might respond with an `IllegalDataAddress` exception if
+/// a request covers addresses that are not mapped. The planner then
+/// reads the affected items separately and remembers these addresses
+/// as holes, which are never covered by a combined request again.
+///
+/// Holes are only learned from the requests that fail during a read,
+/// i.e. it might take several reads of the same items until no
+/// combined request fails anymore. Each of these reads still returns
+/// the values of all readable items.
+#[derive(Debug, Clone)]
+pub struct ReadPlanner {
+    max_gap: Quantity,
+    max_block_size: Quantity,
+    holes: BTreeSet<(RegisterType, Address)>,
+}
+
+impl ReadPlanner {
+    /// Combine items that are at most `max_gap` addresses apart into
+    /// requests of at most `max_block_size` data objects.
+    ///
+    /// The block size is limited to the maximum quantity that is
+    /// allowed by the specification. Items that exceed the block
+    /// size on their own are read with a single request.
+    #[must_use]
+    pub fn new(max_gap: Quantity, max_block_size: Quantity) -> Self {
+        Self {
+            max_gap,
+            max_block_size: max_block_size.max(1),
+            holes: BTreeSet::new(),
+        }
+    }
+
+    /// The addresses that must not be covered by combined requests.
+    pub fn holes(&self) -> impl Iterator<Item = (RegisterType, Address)> + '_ {
+        self.holes.iter().copied()
+    }
+
+    /// Forget all holes, e.g. after the configuration of the device
+    /// has changed.
+    pub fn clear_holes(&mut self) {
+        self.holes.clear();
+    }
+
+    /// The requests for reading the items.
+    ///
+    /// Fails with [`Error::InvalidRequest`] if the quantity of an item
+    /// is 0 or exceeds the maximum quantity of a single request, or if
+    /// an item exceeds the address space.
+    pub fn plan(&self, items: &[ReadItem]) -> Result<Vec<Request>, Error> {
+        Ok(self.blocks(items)?.iter().map(Block::request).collect())
+    }
+
+    /// Read the items.
+    ///
+    /// The results are in the same order as the items. Exceptions
+    /// are reported per item, all other errors abort the read. Invalid
+    /// items are rejected like by [`Self::plan()`] before sending
+    /// any request.
+    pub async fn read<C>(
+        &mut self,
+        client: &mut C,
+        items: &[ReadItem],
+    ) -> Result<Vec<Result<ReadValues, ExceptionResponse>>, Error>
+    where
+        C: Client + ?Sized,
+    {
+        let mut results = vec![None; items.len()];
+        for block in self.blocks(items)? {
+            let exception = match client.call(block.request()).await {
+                Ok(rsp) => {
+                    for &index in &block.items {
+                        let item = &items[index];
+                        let offset = usize::from(item.address - block.address);
+                        let values = item.register_type.values(&rsp, offset, item.quantity)?;
+                        results[index] = Some(Ok(values));
+                    }
+                    continue;
+                }
+                Err(Error::Exception(exception)) => exception,
+                Err(err) => return Err(err),
+            };
+            if block.items.len() > 1 && exception.exception == Exception::IllegalDataAddress {
+                log::debug!(
+                    "Reading {} items separately after {}",
+                    block.items.len(),
+                    exception
+                );
+                self.read_separately(client, items, &block, &mut results)
+                    .await?;
+                continue;
+            }
+            if exception.exception == Exception::IllegalDataAddress {
+                self.insert_holes(block.register_type, block.address, block.end);
+            }
+            for &index in &block.items {
+                results[index] = Some(Err(exception));
+            }
+        }
+        Ok(results
+            .into_iter()
+            .map(|res| res.expect("all items have been read"))
+            .collect())
+    }
+
+    /// Read the items of a block that failed one by one and learn
+    /// which addresses caused the failure.
+    async fn read_separately<C>(
+        &mut self,
+        client: &mut C,
+        items: &[ReadItem],
+        block: &Block,
+        results: &mut [Option<Result<ReadValues, ExceptionResponse>>],
+    ) -> Result<(), Error>
+    where
+        C: Client + ?Sized,
+    {
+        let mut all_readable = true;
+        for &index in &block.items {
+            let item = &items[index];
+            let req = item.register_type.request(item.address, item.quantity);
+            let res = match client.call(req).await {
+                Ok(rsp) => Ok(item.register_type.values(&rsp, 0, item.quantity)?),
+                Err(Error::Exception(exception)) => {
+                    if exception.exception == Exception::IllegalDataAddress {
+                        all_readable = false;
+                        self.insert_holes(item.register_type, item.address, item.end());
+                    }
+                    Err(exception)
+                }
+                Err(err) => return Err(err),
+            };
+            results[index] = Some(res);
+        }
+        if all_readable {
+            // The gaps between the items contain unmapped addresses
+            let mut end = usize::from(block.address);
+            for &index in &block.items {
+                let item = &items[index];
+                self.insert_holes(block.register_type, end, item.address);
+                end = end.max(item.end());
+            }
+        }
+        Ok(())
+    }
+
+    fn insert_holes(
+        &mut self,
+        register_type: RegisterType,
+        start: impl Into<usize>,
+        end: impl Into<usize>,
+    ) {
+        let end = end.into().min(usize::from(Address::MAX) + 1);
+        for address in start.into()..end {
+            let address = Address::try_from(address).expect("valid address");
+            self.holes.insert((register_type, address));
+        }
+    }
+
+    fn has_holes(&self, register_type: RegisterType, start: usize, end: usize) -> bool {
+        if start >= end {
+            return false;
+        }
+        let (Ok(first), last) = (Address::try_from(start), Address::try_from(end - 1)) else {
+            return false;
+        };
+        let last = last.unwrap_or(Address::MAX);
+        self.holes
+            .range((register_type, first)..=(register_type, last))
+            .next()
+            .is_some()
+    }
+
+    fn can_extend(&self, block: &Block, item: &ReadItem) -> bool {
+        let start = usize::from(item.address);
+        let gap = start.saturating_sub(block.end);
+        let max_block_size = self.max_block_size.min(item.register_type.max_quantity());
+        !block.isolated
+            && block.register_type == item.register_type
+            && gap <= usize::from(self.max_gap)
+            && !self.has_holes(item.register_type, block.end, start)
+            && block.end.max(item.end()) - usize::from(block.address) <= usize::from(max_block_size)
+    }
+
+    fn blocks(&self, items: &[ReadItem]) -> Result<Vec<Block>, Error> {
+        for item in items {
+            item.validate()?;
+        }
+        let mut indexes = (0..items.len()).collect::<Vec<_>>();
+        indexes.sort_by_key(|&index| (items[index].register_type, items[index].address));
+        let mut blocks: Vec<Block> = Vec::new();
+        for index in indexes {
+            let item = &items[index];
+            // Items that contain holes are read on their own
+            let isolated = self.has_holes(item.register_type, item.address.into(), item.end());
+            if let Some(block) = blocks.last_mut() {
+                if !isolated && self.can_extend(block, item) {
+                    block.end = block.end.max(item.end());
+                    block.items.push(index);
+                    continue;
+                }
+            }
+            blocks.push(Block::new(index, item, isolated));
+        }
+        Ok(blocks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_trait::async_trait;
+
+    use super::*;
+    use crate::slave::{Slave, SlaveContext};
+
+    use RegisterType::*;
+
+    /// Responds with the address as value, except for unmapped addresses
+    #[derive(Debug, Default)]
+    struct Device {
+        unmapped: Vec<Address>,
+        requests: Vec<Request>,
+    }
+
+    #[async_trait]
+    impl Client for Device {
+        async fn call(&mut self, request: Request) -> Result<Response, Error> {
+            self.requests.push(request.clone());
+            let (function, addr, cnt) = match request {
+                Request::ReadCoils(addr, cnt) => (0x01, addr, cnt),
+                Request::ReadHoldingRegisters(addr, cnt) => (0x03, addr, cnt),
+                _ => unimplemented!(),
+            };
+            let range = addr..addr + cnt;
+            if self.unmapped.iter().any(|addr| range.contains(addr)) {
+                return Err(ExceptionResponse {
+                    function,
+                    exception: Exception::IllegalDataAddress,
+                }
+                .into());
+            }
+            Ok(match function {
+                0x01 => Response::ReadCoils(range.map(|addr| addr % 2 == 1).collect()),
+                _ => Response::ReadHoldingRegisters(range.collect()),
+            })
+        }
+    }
+
+    impl SlaveContext for Device {
+        fn set_slave(&mut self, _: Slave) {}
+    }
+
+    const fn item(register_type: RegisterType, address: Address, quantity: Quantity) -> ReadItem {
+        ReadItem {
+            register_type,
+            address,
+            quantity,
+        }
+    }
+
+    #[test]
+    fn combine_items_within_gap_and_block_size() {
+        let planner = ReadPlanner::new(2, 10);
+        let items = [
+            item(HoldingRegister, 20, 2),
+            item(Coil, 5, 1),
+            item(HoldingRegister, 0, 2),
+            item(HoldingRegister, 4, 4),
+            item(HoldingRegister, 5, 1),
+            item(HoldingRegister, 10, 2),
+            item(InputRegister, 12, 1),
+        ];
+        assert_eq!(
+            planner.plan(&items).unwrap(),
+            [
+                Request::ReadCoils(5, 1),
+                Request::ReadHoldingRegisters(0, 8),
+                Request::ReadHoldingRegisters(10, 2),
+                Request::ReadHoldingRegisters(20, 2),
+                Request::ReadInputRegisters(12, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn limit_block_size_to_specification() {
+        let planner = ReadPlanner::new(0, Quantity::MAX);
+        let items = [
+            item(HoldingRegister, 0, 100),
+            item(HoldingRegister, 100, 100),
+        ];
+        assert_eq!(planner.plan(&items).unwrap().len(), 2);
+        let items = [item(Coil, 0, 100), item(Coil, 100, 100)];
+        assert_eq!(planner.plan(&items).unwrap(), [Request::ReadCoils(0, 200)]);
+    }
+
+    #[tokio::test]
+    async fn reject_invalid_items() {
+        let mut device = Device::default();
+        let mut planner = ReadPlanner::new(5, 125);
+        for invalid in [
+            item(HoldingRegister, 0, 0),
+            item(HoldingRegister, 0, 126),
+            item(Coil, 0, 2001),
+            item(HoldingRegister, 0xFFFF, 2),
+        ] {
+            let items = [item(HoldingRegister, 0, 1), invalid];
+            assert!(matches!(
+                planner.plan(&items),
+                Err(Error::InvalidRequest(_))
+            ));
+            assert!(matches!(
+                planner.read(&mut device, &items).await,
+                Err(Error::InvalidRequest(_))
+            ));
+        }
+        assert!(device.requests.is_empty());
+        let items = [
+            item(HoldingRegister, 0, 125),
+            item(Coil, 0, 2000),
+            item(InputRegister, 0xFFFF, 1),
+        ];
+        assert!(planner.plan(&items).is_ok());
+    }
+
+    #[tokio::test]
+    async fn map_results_to_items() {
+        let mut device = Device::default();
+        let mut planner = ReadPlanner::new(5, 125);
+        let items = [
+            item(HoldingRegister, 8, 2),
+            item(Coil, 0, 2),
+            item(HoldingRegister, 3, 2),
+        ];
+        let results = planner.read(&mut device, &items).await.unwrap();
+        assert_eq!(
+            results,
+            [
+                Ok(ReadValues::Words(vec![8, 9])),
+                Ok(ReadValues::Coils(vec![false, true])),
+                Ok(ReadValues::Words(vec![3, 4])),
+            ]
+        );
+        assert_eq!(device.requests.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn learn_to_avoid_holes() {
+        let mut device = Device {
+            unmapped: vec![6],
+            ..Default::default()
+        };
+        let mut planner = ReadPlanner::new(5, 125);
+        let items = [item(HoldingRegister, 3, 2), item(HoldingRegister, 8, 2)];
+        let results = planner.read(&mut device, &items).await.unwrap();
+        assert_eq!(
+            results,
+            [
+                Ok(ReadValues::Words(vec![3, 4])),
+                Ok(ReadValues::Words(vec![8, 9])),
+            ]
+        );
+        assert_eq!(
+            planner.holes().collect::<Vec<_>>(),
+            [
+                (HoldingRegister, 5),
+                (HoldingRegister, 6),
+                (HoldingRegister, 7)
+            ]
+        );
+        assert_eq!(
+            planner.plan(&items).unwrap(),
+            [
+                Request::ReadHoldingRegisters(3, 2),
+                Request::ReadHoldingRegisters(8, 2),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn isolate_unreadable_items() {
+        let mut device = Device {
+            unmapped: vec![4],
+            ..Default::default()
+        };
+        let mut planner = ReadPlanner::new(5, 125);
+        let items = [item(HoldingRegister, 3, 2), item(HoldingRegister, 8, 2)];
+        let results = planner.read(&mut device, &items).await.unwrap();
+        assert_eq!(
+            results[0].as_ref().unwrap_err().exception,
+            Exception::IllegalDataAddress
+        );
+        assert_eq!(results[1], Ok(ReadValues::Words(vec![8, 9])));
+
+        device.requests.clear();
+        assert!(planner.read(&mut device, &items).await.unwrap()[0].is_err());
+        assert_eq!(device.requests.len(), 2);
+    }
+}
@@ -0,0 +1,430 @@
+// SPDX-FileCopyrightText: Copyright (c) 2017-2022 slowtec GmbH <post@slowtec.de>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Reconnecting broken connections
+
+use std::{
+    fmt,
+    future::Future,
+    io::{self, ErrorKind},
+    pin::Pin,
+    sync::Arc,
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use tokio::time::Instant;
+
+use crate::{
+    frame::{Request, Response},
+    slave::{Slave, SlaveContext},
+    Error, ErrorClass,
+};
+
+use super::{Backoff, Broadcast, Client};
+
+/// The state of the connection of a reconnecting client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// A connection is being established.
+    Connecting,
+
+    /// The connection has been established.
+    Connected,
+
+    /// The connection has been closed or could not be established.
+    Disconnected,
+}
+
+type StateListener = dyn Fn(ConnectionState) + Send + Sync;
+
+/// Decides when broken connections are re-established.
+///
+/// Connections are re-established on the next request after they
+/// broke. Requests fail without trying to connect until the backoff
+/// delay after a failed attempt has expired.
+///
+/// A connection is considered broken after a transport error or after
+/// a response has not been received within the timeout of the policy.
+/// The timeout of the [`Context`](super::Context) only cancels the
+/// request and keeps the connection.
+#[derive(Clone)]
+pub struct ReconnectPolicy {
+    backoff: Backoff,
+    timeout: Option<Duration>,
+    state_listener: Option<Arc<StateListener>>,
+}
+
+impl ReconnectPolicy {
+    /// Wait after failed attempts to connect.
+    #[must_use]
+    pub fn new(backoff: Backoff) -> Self {
+        Self {
+            backoff,
+            timeout: None,
+            state_listener: None,
+        }
+    }
+
+    /// Reconnect if no response has been received in time (optional).
+    ///
+    /// Requests fail with [`Error::Timeout`] and the connection is
+    /// dropped, e.g. if the peer went silent without closing it.
+    #[must_use]
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        Self {
+            timeout: Some(timeout),
+            ..self
+        }
+    }
+
+    /// Report changes of the connection state (optional).
+    #[must_use]
+    pub fn with_state_listener(
+        self,
+        state_listener: impl Fn(ConnectionState) + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            state_listener: Some(Arc::new(state_listener)),
+            ..self
+        }
+    }
+}
+
+impl Default for ReconnectPolicy {
+    /// Double the delay after each failed attempt, starting
+    /// with 100 ms up to 10 s.
+    fn default() -> Self {
+        Self::new(Backoff::Exponential {
+            initial: Duration::from_millis(100),
+            max: Duration::from_secs(10),
+        })
+    }
+}
+
+impl fmt::Debug for ReconnectPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReconnectPolicy")
+            .field("backoff", &self.backoff)
+            .field("timeout", &self.timeout)
+            .field("state_listener", &self.state_listener.is_some())
+            .finish()
+    }
+}
+
+pub(crate) type ConnectFuture = Pin<Box<dyn Future<Output = io::Result<Box<dyn Client>>> + Send>>;
+
+type ConnectFn = dyn Fn(Slave) -> ConnectFuture + Send + Sync;
+
+/// A client that connects on demand and reconnects after the
+/// connection broke.
+pub(crate) struct Reconnecting {
+    connect: Box<ConnectFn>,
+    policy: ReconnectPolicy,
+    slave: Slave,
+    client: Option<Box<dyn Client>>,
+    failed_attempts: u32,
+    next_attempt: Option<Instant>,
+    broadcast: Option<Broadcast>,
+}
+
+impl Reconnecting {
+    pub(crate) fn new(
+        connect: impl Fn(Slave) -> ConnectFuture + Send + Sync + 'static,
+        policy: ReconnectPolicy,
+        slave: Slave,
+    ) -> Self {
+        Self {
+            connect: Box::new(connect),
+            policy,
+            slave,
+            client: None,
+            failed_attempts: 0,
+            next_attempt: None,
+            broadcast: None,
+        }
+    }
+
+    fn report(&self, state: ConnectionState) {
+        if let Some(state_listener) = &self.policy.state_listener {
+            state_listener(state);
+        }
+    }
+
+    async fn connected_client(&mut self) -> Result<&mut Box<dyn Client>, Error> {
+        if self.client.is_none() {
+            if let Some(next_attempt) = self.next_attempt {
+                if Instant::now() < next_attempt {
+                    return Err(Error::Transport(io::Error::new(
+                        ErrorKind::NotConnected,
+                        "Not connected, waiting before reconnecting",
+                    )));
+                }
+            }
+            self.report(ConnectionState::Connecting);
+            match (self.connect)(self.slave).await {
+                Ok(mut client) => {
+                    if let Some(broadcast) = self.broadcast {
+                        client.set_broadcast(broadcast);
+                    }
+                    self.failed_attempts = 0;
+                    self.next_attempt = None;
+                    self.client = Some(client);
+                    self.report(ConnectionState::Connected);
+                }
+                Err(err) => {
+                    self.failed_attempts = self.failed_attempts.saturating_add(1);
+                    self.next_attempt =
+                        Some(Instant::now() + self.policy.backoff.delay(self.failed_attempts));
+                    self.report(ConnectionState::Disconnected);
+                    return Err(Error::Transport(err));
+                }
+            }
+        }
+        Ok(self.client.as_mut().expect("connected"))
+    }
+
+    fn disconnect(&mut self) {
+        if self.client.take().is_some() {
+            self.report(ConnectionState::Disconnected);
+        }
+    }
+}
+
+impl fmt::Debug for Reconnecting {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Reconnecting")
+            .field("policy", &self.policy)
+            .field("slave", &self.slave)
+            .field("client", &self.client)
+            .field("failed_attempts", &self.failed_attempts)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SlaveContext for Reconnecting {
+    fn set_slave(&mut self, slave: Slave) {
+        self.slave = slave;
+        if let Some(client) = &mut self.client {
+            client.set_slave(slave);
+        }
+    }
+}
+
+#[async_trait]
+impl Client for Reconnecting {
+    async fn call(&mut self, request: Request) -> Result<Response, Error> {
+        if request == Request::Disconnect {
+            self.disconnect();
+            return Err(Error::Transport(ErrorKind::NotConnected.into()));
+        }
+        let timeout = self.policy.timeout;
+        let client = self.connected_client().await?;
+        let res = match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, client.call(request))
+                .await
+                .unwrap_or(Err(Error::Timeout)),
+            None => client.call(request).await,
+        };
+        if let Err(err) = &res {
+            // The transport is unusable after it failed or the peer
+            // did not respond
+            if matches!(err.class(), ErrorClass::Transport | ErrorClass::Timeout) {
+                log::debug!("Connection broke: {}", err);
+                self.disconnect();
+            }
+        }
+        res
+    }
+
+    fn set_broadcast(&mut self, broadcast: Broadcast) {
+        // Applies to all future connections
+        self.broadcast = Some(broadcast);
+        if let Some(client) = &mut self.client {
+            client.set_broadcast(broadcast);
+        }
+    }
+
+    #[cfg(feature = "rtu")]
+    fn rtu_error_counters(&self) -> Option<super::rtu::ErrorCounters> {
+        self.client.as_ref()?.rtu_error_counters()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    };
+
+    use super::*;
+    use crate::client::{Context, Reader as _};
+
+    /// Fails after the given number of calls
+    #[derive(Debug)]
+    struct FragileClient {
+        remaining_calls: usize,
+    }
+
+    #[async_trait]
+    impl Client for FragileClient {
+        async fn call(&mut self, _: Request) -> Result<Response, Error> {
+            if self.remaining_calls == 0 {
+                return Err(Error::Transport(ErrorKind::BrokenPipe.into()));
+            }
+            self.remaining_calls -= 1;
+            Ok(Response::ReadHoldingRegisters(vec![0x0001]))
+        }
+    }
+
+    impl SlaveContext for FragileClient {
+        fn set_slave(&mut self, _: Slave) {}
+    }
+
+    /// Stops responding after the given number of calls
+    #[derive(Debug)]
+    struct SilentClient {
+        remaining_calls: usize,
+    }
+
+    #[async_trait]
+    impl Client for SilentClient {
+        async fn call(&mut self, _: Request) -> Result<Response, Error> {
+            if self.remaining_calls == 0 {
+                return std::future::pending().await;
+            }
+            self.remaining_calls -= 1;
+            Ok(Response::ReadHoldingRegisters(vec![0x0001]))
+        }
+    }
+
+    impl SlaveContext for SilentClient {
+        fn set_slave(&mut self, _: Slave) {}
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reconnect_with_backoff() {
+        let connects = Arc::new(AtomicUsize::new(0));
+        let states = Arc::new(Mutex::new(Vec::new()));
+        let connect = {
+            let connects = Arc::clone(&connects);
+            move |_| -> ConnectFuture {
+                // The second attempt fails
+                let connect = connects.fetch_add(1, Ordering::Relaxed);
+                Box::pin(async move {
+                    if connect == 1 {
+                        return Err(ErrorKind::ConnectionRefused.into());
+                    }
+                    Ok(Box::new(FragileClient { remaining_calls: 1 }) as Box<dyn Client>)
+                })
+            }
+        };
+        let policy = ReconnectPolicy::new(Backoff::Fixed(Duration::from_secs(1)))
+            .with_state_listener({
+                let states = Arc::clone(&states);
+                move |state| states.lock().unwrap().push(state)
+            });
+        let mut context = Context::from(
+            Box::new(Reconnecting::new(connect, policy, Slave(1))) as Box<dyn Client>
+        );
+
+        // Connect on demand
+        assert!(context.read_holding_registers(0x00, 1).await.is_ok());
+        // The connection breaks
+        assert!(context.read_holding_registers(0x00, 1).await.is_err());
+        // Reconnecting fails
+        assert!(context.read_holding_registers(0x00, 1).await.is_err());
+        // No attempt before the backoff delay expired
+        assert!(context.read_holding_registers(0x00, 1).await.is_err());
+        assert_eq!(connects.load(Ordering::Relaxed), 2);
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert!(context.read_holding_registers(0x00, 1).await.is_ok());
+        assert_eq!(connects.load(Ordering::Relaxed), 3);
+
+        use ConnectionState::*;
+        assert_eq!(
+            *states.lock().unwrap(),
+            [
+                Connecting,
+                Connected,
+                Disconnected,
+                Connecting,
+                Disconnected,
+                Connecting,
+                Connected
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reconnect_after_timeout() {
+        let connects = Arc::new(AtomicUsize::new(0));
+        let connect = {
+            let connects = Arc::clone(&connects);
+            move |_| -> ConnectFuture {
+                connects.fetch_add(1, Ordering::Relaxed);
+                Box::pin(async {
+                    Ok(Box::new(SilentClient { remaining_calls: 1 }) as Box<dyn Client>)
+                })
+            }
+        };
+        let policy = ReconnectPolicy::new(Backoff::Fixed(Duration::ZERO))
+            .with_timeout(Duration::from_secs(1));
+        let mut context = Context::from(
+            Box::new(Reconnecting::new(connect, policy, Slave(1))) as Box<dyn Client>
+        );
+
+        assert!(context.read_holding_registers(0x00, 1).await.is_ok());
+        // The peer goes silent
+        let started = Instant::now();
+        assert!(matches!(
+            context.read_holding_registers(0x00, 1).await,
+            Err(Error::Timeout)
+        ));
+        assert_eq!(started.elapsed(), Duration::from_secs(1));
+        assert_eq!(connects.load(Ordering::Relaxed), 1);
+        // The next request uses a new connection
+        assert!(context.read_holding_registers(0x00, 1).await.is_ok());
+        assert_eq!(connects.load(Ordering::Relaxed), 2);
+    }
+
+    #[cfg(feature = "tcp")]
+    #[tokio::test]
+    async fn reconnect_tcp_client() {
+        use tokio::{
+            io::{AsyncReadExt as _, AsyncWriteExt as _},
+            net::TcpListener,
+        };
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let socket_addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            // Each connection answers a single request
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = [0; 12];
+                stream.read_exact(&mut request).await.unwrap();
+                let mut response = request[..4].to_vec();
+                response.extend_from_slice(&[0x00, 0x05, 0x01, 0x03, 0x02, 0x12, 0x34]);
+                stream.write_all(&response).await.unwrap();
+            }
+        });
+
+        let mut context = crate::client::tcp::connect_slave_reconnecting(
+            socket_addr,
+            Slave(1),
+            ReconnectPolicy::new(Backoff::Fixed(Duration::ZERO)),
+        );
+        assert_eq!(
+            context.read_holding_registers(0x00, 1).await.unwrap(),
+            vec![0x1234]
+        );
+        // The server closed the connection
+        assert!(context.read_holding_registers(0x00, 1).await.is_err());
+        assert_eq!(
+            context.read_holding_registers(0x00, 1).await.unwrap(),
+            vec![0x1234]
+        );
+    }
+}
@@ -0,0 +1,395 @@
+// SPDX-FileCopyrightText: Copyright (c) 2017-2022 slowtec GmbH <post@slowtec.de>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Retrying failed requests
+
+use std::time::Duration;
+
+use crate::{
+    frame::{Exception, Request, Response},
+    Error, ErrorClass,
+};
+
+use super::{call_with_timeout, Client};
+
+/// The delay between two attempts of a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backoff {
+    /// Wait the same time before each retry.
+    Fixed(Duration),
+
+    /// Double the delay with each retry, starting with
+    /// `initial` and limited by `max`.
+    Exponential {
+        /// The delay before the first retry.
+        initial: Duration,
+        /// The maximum delay.
+        max: Duration,
+    },
+}
+
+impl Backoff {
+    /// The delay after the given number of failed attempts.
+    pub(super) fn delay(&self, failed_attempts: u32) -> Duration {
+        match *self {
+            Self::Fixed(delay) => delay,
+            Self::Exponential { initial, max } => {
+                let factor = 1_u32
+                    .checked_shl(failed_attempts.saturating_sub(1))
+                    .unwrap_or(u32::MAX);
+                initial.saturating_mul(factor).min(max)
+            }
+        }
+    }
+}
+
+/// Re-polling of requests that the server (slave) could not
+/// process yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct BusyRetry {
+    delay: Duration,
+    max_polls: u32,
+}
+
+/// Decides which failed requests are retried and when.
+///
+/// Only requests that read data are retried by default, because
+/// the server (slave) might have processed a write request even
+/// if the response got lost.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    backoff: Backoff,
+    retryable_errors: Vec<ErrorClass>,
+    retryable_writes: bool,
+    busy_retry: Option<BusyRetry>,
+}
+
+impl RetryPolicy {
+    /// Try each request up to `max_attempts` times without any delay.
+    ///
+    /// Timeouts and protocol violations, e.g. CRC errors, are retried.
+    #[must_use]
+    pub fn new(max_attempts: u32) -> Self {
+        Self {
+            max_attempts: max_attempts.max(1),
+            backoff: Backoff::Fixed(Duration::ZERO),
+            retryable_errors: vec![ErrorClass::Timeout, ErrorClass::ProtocolViolation],
+            retryable_writes: false,
+            busy_retry: None,
+        }
+    }
+
+    /// Wait before retrying a request (optional).
+    #[must_use]
+    pub fn with_backoff(self, backoff: Backoff) -> Self {
+        Self { backoff, ..self }
+    }
+
+    /// Retry requests that failed with one of these classes
+    /// of errors (optional).
+    #[must_use]
+    pub fn with_retryable_errors(self, retryable_errors: &[ErrorClass]) -> Self {
+        Self {
+            retryable_errors: retryable_errors.to_vec(),
+            ..self
+        }
+    }
+
+    /// Also retry requests that write data (optional).
+    #[must_use]
+    pub fn with_retryable_writes(self, retryable_writes: bool) -> Self {
+        Self {
+            retryable_writes,
+            ..self
+        }
+    }
+
+    /// Re-poll the server (slave) up to `max_polls` times after a `delay`
+    /// if it responds with a `ServerDeviceBusy` or `Acknowledge`
+    /// exception (optional).
+    ///
+    /// Re-polls are not counted as attempts. Writes are only re-polled
+    /// on `Acknowledge` if they are retryable, because the server has
+    /// already accepted them.
+    #[must_use]
+    pub fn with_busy_retry(self, delay: Duration, max_polls: u32) -> Self {
+        Self {
+            busy_retry: Some(BusyRetry { delay, max_polls }),
+            ..self
+        }
+    }
+
+    /// The maximum number of attempts per request.
+    #[must_use]
+    pub fn max_attempts(&self) -> u32 {
+        self.max_attempts
+    }
+
+    fn is_retryable(&self, request: &Request) -> bool {
+        request != &Request::Disconnect && (self.retryable_writes || request.is_read_only())
+    }
+
+    /// The delay before re-polling the server after the request failed.
+    fn busy_delay(&self, request: &Request, err: &Error, polls: u32) -> Option<Duration> {
+        let busy_retry = self.busy_retry?;
+        if polls >= busy_retry.max_polls {
+            return None;
+        }
+        match err.exception()?.exception {
+            Exception::ServerDeviceBusy => Some(busy_retry.delay),
+            Exception::Acknowledge if self.is_retryable(request) => Some(busy_retry.delay),
+            _ => None,
+        }
+    }
+
+    /// The delay before retrying the request after it failed.
+    fn retry_delay(&self, request: &Request, err: &Error, attempts: u32) -> Option<Duration> {
+        if attempts >= self.max_attempts
+            || !self.is_retryable(request)
+            || !self.retryable_errors.contains(&err.class())
+        {
+            return None;
+        }
+        Some(self.backoff.delay(attempts))
+    }
+}
+
+/// Invoke a Modbus function and retry it according to the policy.
+pub(super) async fn call_with_retries(
+    client: &mut dyn Client,
+    policy: &RetryPolicy,
+    request: Request,
+    timeout: Option<Duration>,
+) -> Result<Response, Error> {
+    let mut attempts = 0;
+    let mut polls = 0;
+    loop {
+        let err = match call_with_timeout(client, request.clone(), timeout).await {
+            Ok(response) => return Ok(response),
+            Err(err) => err,
+        };
+        let delay = if let Some(delay) = policy.busy_delay(&request, &err, polls) {
+            polls += 1;
+            log::debug!("Re-polling {:?}: {}", request, err);
+            delay
+        } else {
+            attempts += 1;
+            let Some(delay) = policy.retry_delay(&request, &err, attempts) else {
+                return Err(err);
+            };
+            log::debug!("Retrying {:?} (attempt {}): {}", request, attempts, err);
+            delay
+        };
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use async_trait::async_trait;
+
+    use super::*;
+    use crate::{
+        client::{Context, Reader as _, Writer as _},
+        frame::ExceptionResponse,
+        slave::{Slave, SlaveContext},
+    };
+
+    /// Returns the scripted results in order
+    #[derive(Debug, Default)]
+    struct ScriptedMock {
+        results: VecDeque<Result<Response, Error>>,
+        calls: usize,
+    }
+
+    #[async_trait]
+    impl Client for ScriptedMock {
+        async fn call(&mut self, _: Request) -> Result<Response, Error> {
+            self.calls += 1;
+            self.results.pop_front().unwrap()
+        }
+    }
+
+    impl SlaveContext for ScriptedMock {
+        fn set_slave(&mut self, _: Slave) {}
+    }
+
+    fn exception(exception: Exception) -> Error {
+        ExceptionResponse {
+            function: 0x03,
+            exception,
+        }
+        .into()
+    }
+
+    fn crc_error() -> Error {
+        Error::ProtocolViolation("Invalid CRC".to_owned())
+    }
+
+    #[test]
+    fn exponential_backoff() {
+        let backoff = Backoff::Exponential {
+            initial: Duration::from_millis(100),
+            max: Duration::from_millis(500),
+        };
+        assert_eq!(backoff.delay(1), Duration::from_millis(100));
+        assert_eq!(backoff.delay(2), Duration::from_millis(200));
+        assert_eq!(backoff.delay(3), Duration::from_millis(400));
+        assert_eq!(backoff.delay(4), Duration::from_millis(500));
+        assert_eq!(backoff.delay(100), Duration::from_millis(500));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retry_reads_with_backoff() {
+        let mut client = ScriptedMock {
+            results: [
+                Err(Error::Timeout),
+                Err(crc_error()),
+                Ok(Response::ReadHoldingRegisters(vec![0x1234])),
+            ]
+            .into(),
+            ..Default::default()
+        };
+        let policy = RetryPolicy::new(3).with_backoff(Backoff::Fixed(Duration::from_secs(1)));
+        let start = tokio::time::Instant::now();
+        let rsp = call_with_retries(
+            &mut client,
+            &policy,
+            Request::ReadHoldingRegisters(0x00, 1),
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(rsp, Response::ReadHoldingRegisters(vec![0x1234]));
+        assert_eq!(client.calls, 3);
+        assert_eq!(start.elapsed(), Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn give_up_after_max_attempts() {
+        let mut client = ScriptedMock {
+            results: [
+                Err(Error::Timeout),
+                Err(Error::Timeout),
+                Err(Error::Timeout),
+            ]
+            .into(),
+            ..Default::default()
+        };
+        let policy = RetryPolicy::new(2);
+        let res = call_with_retries(&mut client, &policy, Request::ReadCoils(0x00, 1), None).await;
+        assert!(matches!(res, Err(Error::Timeout)));
+        assert_eq!(client.calls, 2);
+    }
+
+    #[tokio::test]
+    async fn do_not_retry_other_errors() {
+        let mut client = ScriptedMock {
+            results: [
+                Err(exception(Exception::IllegalDataAddress)),
+                Ok(Response::ReadCoils(vec![true])),
+            ]
+            .into(),
+            ..Default::default()
+        };
+        let policy = RetryPolicy::new(3);
+        let res = call_with_retries(&mut client, &policy, Request::ReadCoils(0x00, 1), None).await;
+        assert_eq!(res.unwrap_err().class(), ErrorClass::Exception);
+        assert_eq!(client.calls, 1);
+    }
+
+    #[tokio::test]
+    async fn retry_writes_only_if_enabled() {
+        let request = Request::WriteSingleRegister(0x00, 0x01);
+        let results = || {
+            [
+                Err(Error::Timeout),
+                Ok(Response::WriteSingleRegister(0x00, 0x01)),
+            ]
+            .into()
+        };
+
+        let mut client = ScriptedMock {
+            results: results(),
+            ..Default::default()
+        };
+        let policy = RetryPolicy::new(2);
+        let res = call_with_retries(&mut client, &policy, request.clone(), None).await;
+        assert!(matches!(res, Err(Error::Timeout)));
+
+        let mut client = ScriptedMock {
+            results: results(),
+            ..Default::default()
+        };
+        let policy = policy.with_retryable_writes(true);
+        let res = call_with_retries(&mut client, &policy, request, None).await;
+        assert!(res.is_ok());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn re_poll_busy_server() {
+        let mut client = ScriptedMock {
+            results: [
+                Err(exception(Exception::ServerDeviceBusy)),
+                Err(exception(Exception::ServerDeviceBusy)),
+                Err(exception(Exception::ServerDeviceBusy)),
+                Ok(Response::WriteSingleRegister(0x00, 0x01)),
+            ]
+            .into(),
+            ..Default::default()
+        };
+        // Re-polls are not counted as attempts
+        let policy = RetryPolicy::new(1).with_busy_retry(Duration::from_millis(100), 3);
+        let start = tokio::time::Instant::now();
+        let res = call_with_retries(
+            &mut client,
+            &policy,
+            Request::WriteSingleRegister(0x00, 0x01),
+            None,
+        )
+        .await;
+        assert!(res.is_ok());
+        assert_eq!(start.elapsed(), Duration::from_millis(300));
+
+        // Writes that have been acknowledged are not repeated
+        let mut client = ScriptedMock {
+            results: [Err(exception(Exception::Acknowledge))].into(),
+            ..Default::default()
+        };
+        let res = call_with_retries(
+            &mut client,
+            &policy,
+            Request::WriteSingleRegister(0x00, 0x01),
+            None,
+        )
+        .await;
+        assert!(res.is_err());
+        assert_eq!(client.calls, 1);
+    }
+
+    #[tokio::test]
+    async fn apply_policy_to_reader_and_writer() {
+        let client = ScriptedMock {
+            results: [
+                Err(crc_error()),
+                Ok(Response::ReadInputRegisters(vec![0x0001])),
+                Err(crc_error()),
+            ]
+            .into(),
+            ..Default::default()
+        };
+        let mut context = Context::from(Box::new(client) as Box<dyn Client>)
+            .with_retry_policy(RetryPolicy::new(2));
+        assert_eq!(
+            context.read_input_registers(0x00, 1).await.unwrap(),
+            vec![0x0001]
+        );
+        let res = context.write_single_register(0x00, 0x01).await;
+        assert_eq!(res.unwrap_err().class(), ErrorClass::ProtocolViolation);
+    }
+}
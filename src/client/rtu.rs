@@ -5,10 +5,19 @@
 
 use super::*;
 
-use crate::service;
+use crate::{codec, service};
 
 use std::io::Error;
+#[cfg(feature = "tcp")]
+use std::net::SocketAddr;
 use tokio::io::{AsyncRead, AsyncWrite};
+#[cfg(feature = "tcp")]
+use tokio::net::TcpStream;
+
+pub use crate::codec::rtu::{ErrorCounters, FrameTiming, LengthResolver, LengthResolvers};
+
+mod bus;
+pub use self::bus::{Bus, BusConfig, BusHandle};
 
 /// Connect to no particular Modbus slave device for sending
 /// broadcast messages.
@@ -28,5 +37,95 @@ where
 
     Ok(Context {
         client: Box::new(client),
+        timeout: None,
+        retry_policy: None,
     })
 }
+
+/// Connect to any kind of Modbus slave device on demand and reconnect
+/// after the transport broke.
+///
+/// Each (re-)connection opens a new transport by invoking `new_transport`,
+/// e.g. to reopen a serial port that has been unplugged. No transport
+/// is opened until the first request.
+#[must_use]
+pub fn connect_slave_reconnecting<F, T>(
+    new_transport: F,
+    slave: Slave,
+    policy: ReconnectPolicy,
+) -> Context
+where
+    F: Fn() -> Result<T, Error> + Send + Sync + 'static,
+    T: AsyncRead + AsyncWrite + Debug + Unpin + Send + 'static,
+{
+    let client = reconnect::Reconnecting::new(
+        move |slave| -> reconnect::ConnectFuture {
+            let transport = new_transport();
+            Box::pin(async move {
+                let client = service::rtu::connect_slave(transport?, slave).await?;
+                Ok(Box::new(client) as Box<dyn Client>)
+            })
+        },
+        policy,
+        slave,
+    );
+    Context::from(Box::new(client) as Box<dyn Client>)
+}
+
+/// Connect to any kind of Modbus slave device that supports custom or
+/// vendor-specific function codes.
+///
+/// The lengths of responses with these function codes are resolved
+/// by the `length_resolvers`.
+pub async fn connect_slave_with_length_resolvers<T>(
+    transport: T,
+    slave: Slave,
+    length_resolvers: LengthResolvers,
+) -> Result<Context, Error>
+where
+    T: AsyncRead + AsyncWrite + Debug + Unpin + Send + 'static,
+{
+    let codec = codec::rtu::ClientCodec::with_length_resolvers(length_resolvers);
+    let client = service::rtu::connect_slave_with_codec(transport, slave, codec).await?;
+
+    Ok(Context {
+        client: Box::new(client),
+        timeout: None,
+        retry_policy: None,
+    })
+}
+
+/// Connect to any kind of Modbus slave device and delimit the received
+/// frames by silent intervals on the serial line.
+///
+/// Unlike the default framing this does not depend on the expected
+/// length of responses. Invalid frames are dropped as a whole.
+pub async fn connect_slave_with_frame_timing<T>(
+    transport: T,
+    slave: Slave,
+    frame_timing: FrameTiming,
+) -> Result<Context, Error>
+where
+    T: AsyncRead + AsyncWrite + Debug + Unpin + Send + 'static,
+{
+    let frame_boundaries = codec::rtu::FrameBoundaries::default();
+    let transport =
+        codec::rtu::SilenceDelimited::new(transport, frame_timing, frame_boundaries.clone());
+    let codec = codec::rtu::ClientCodec::with_frame_boundaries(frame_boundaries);
+    let client = service::rtu::connect_slave_with_codec(transport, slave, codec).await?;
+
+    Ok(Context {
+        client: Box::new(client),
+        timeout: None,
+        retry_policy: None,
+    })
+}
+
+/// Connect to a Modbus RTU slave device through a TCP connection
+/// that transparently forwards raw RTU frames, e.g. a serial device
+/// server in raw mode.
+#[cfg(feature = "tcp")]
+pub async fn connect_rtu_over_tcp(socket_addr: SocketAddr, slave: Slave) -> Result<Context, Error> {
+    let transport = TcpStream::connect(socket_addr).await?;
+    connect_slave(transport, slave).await
+}
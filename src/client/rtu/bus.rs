@@ -0,0 +1,475 @@
+// SPDX-FileCopyrightText: Copyright (c) 2017-2022 slowtec GmbH <post@slowtec.de>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Sharing a serial line between multiple slave devices
+
+use std::{
+    collections::VecDeque,
+    fmt::Debug,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    sync::mpsc,
+    time::{sleep_until, Instant},
+};
+
+use crate::{
+    client::{Client, Context},
+    codec::{self, rtu::ErrorCounters},
+    frame::{Request, Response},
+    service::{
+        self,
+        actor::{self, REQUEST_QUEUE_LEN},
+    },
+    slave::{Slave, SlaveContext},
+    Error,
+};
+
+/// The timing of transactions on the bus.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BusConfig {
+    inter_frame_delay: Duration,
+    turnaround_delay: Duration,
+    response_timeout: Option<Duration>,
+}
+
+impl BusConfig {
+    /// Wait between the end of a transaction and the next request (optional).
+    ///
+    /// Some devices need some time after responding before they are
+    /// able to receive the next request. There is no delay by default.
+    #[must_use]
+    pub fn with_inter_frame_delay(self, inter_frame_delay: Duration) -> Self {
+        Self {
+            inter_frame_delay,
+            ..self
+        }
+    }
+
+    /// Wait after sending a broadcast before the next request (optional).
+    ///
+    /// The devices need time to process a broadcast, because they do
+    /// not respond. There is no delay by default.
+    #[must_use]
+    pub fn with_turnaround_delay(self, turnaround_delay: Duration) -> Self {
+        Self {
+            turnaround_delay,
+            ..self
+        }
+    }
+
+    /// Stop waiting for a response after the timeout (optional).
+    ///
+    /// Without a timeout a device that does not respond blocks the bus.
+    /// There is no timeout by default.
+    #[must_use]
+    pub fn with_response_timeout(self, response_timeout: Duration) -> Self {
+        Self {
+            response_timeout: Some(response_timeout),
+            ..self
+        }
+    }
+}
+
+/// The handle that has passed a request to the bus task.
+#[derive(Debug, Clone, Copy)]
+struct Target {
+    handle_id: usize,
+    priority: u8,
+    slave: Slave,
+}
+
+type Command = actor::Command<Target>;
+
+/// A Modbus RTU client that is shared by multiple slave devices
+/// on the same serial line.
+///
+/// The bus is owned by a background task that executes the requests
+/// of all handles one after another. Handles with a higher priority
+/// are served first. Handles with the same priority are served in
+/// turns, i.e. one request per handle.
+///
+/// Requests that are broadcast with [`Slave::broadcast()`] and that
+/// write data do not wait for a response. The bus is released after
+/// all handles and the bus itself have been dropped.
+#[derive(Debug)]
+pub struct Bus {
+    command_tx: mpsc::Sender<Command>,
+    next_handle_id: AtomicUsize,
+    error_counters: ErrorCounters,
+}
+
+impl Bus {
+    /// Move the transport into a background task.
+    ///
+    /// Must be invoked within a Tokio runtime.
+    #[must_use]
+    pub fn spawn<T>(transport: T, config: BusConfig) -> Self
+    where
+        T: AsyncRead + AsyncWrite + Debug + Unpin + Send + 'static,
+    {
+        let codec = codec::rtu::ClientCodec::default();
+        let error_counters = codec.error_counters().clone();
+        let (command_tx, command_rx) = mpsc::channel(REQUEST_QUEUE_LEN);
+        tokio::spawn(async move {
+            let Ok(context) =
+                service::rtu::connect_slave_with_codec(transport, Slave::broadcast(), codec).await
+            else {
+                return;
+            };
+            run(context, config, command_rx).await;
+        });
+        Self {
+            command_tx,
+            next_handle_id: AtomicUsize::new(0),
+            error_counters,
+        }
+    }
+
+    /// A new handle for the given slave device.
+    #[must_use]
+    pub fn handle(&self, slave: Slave) -> BusHandle {
+        self.handle_with_priority(slave, 0)
+    }
+
+    /// A new handle for the given slave device that is served before
+    /// all handles with a lower priority.
+    #[must_use]
+    pub fn handle_with_priority(&self, slave: Slave, priority: u8) -> BusHandle {
+        BusHandle {
+            command_tx: self.command_tx.clone(),
+            target: Target {
+                handle_id: self.next_handle_id.fetch_add(1, Ordering::Relaxed),
+                priority,
+                slave,
+            },
+            error_counters: self.error_counters.clone(),
+        }
+    }
+
+    /// The counters of bus errors that have been detected while
+    /// decoding frames.
+    #[must_use]
+    pub fn error_counters(&self) -> &ErrorCounters {
+        &self.error_counters
+    }
+}
+
+/// A handle of a [`Bus`] for a single slave device.
+///
+/// Clones of a handle share their turns with the original handle.
+/// Convert a handle into a [`Context`] for using the
+/// [`Reader`](crate::client::Reader) and [`Writer`](crate::client::Writer) API.
+#[derive(Debug, Clone)]
+pub struct BusHandle {
+    command_tx: mpsc::Sender<Command>,
+    target: Target,
+    error_counters: ErrorCounters,
+}
+
+impl BusHandle {
+    /// Invoke a Modbus function.
+    ///
+    /// Unlike [`Client::call`] this does not require exclusive access
+    /// to the handle.
+    pub async fn call(&self, request: Request) -> Result<Response, Error> {
+        actor::call(&self.command_tx, self.target, request).await
+    }
+}
+
+impl SlaveContext for BusHandle {
+    fn set_slave(&mut self, slave: Slave) {
+        self.target.slave = slave;
+    }
+}
+
+#[async_trait]
+impl Client for BusHandle {
+    async fn call(&mut self, request: Request) -> Result<Response, Error> {
+        BusHandle::call(self, request).await
+    }
+
+    fn rtu_error_counters(&self) -> Option<ErrorCounters> {
+        Some(self.error_counters.clone())
+    }
+}
+
+impl From<BusHandle> for Context {
+    fn from(handle: BusHandle) -> Self {
+        Self::from(Box::new(handle) as Box<dyn Client>)
+    }
+}
+
+/// The pending requests of a handle.
+#[derive(Debug)]
+struct Queue {
+    handle_id: usize,
+    priority: u8,
+    commands: VecDeque<Command>,
+}
+
+/// Selects the next request among all handles.
+#[derive(Debug, Default)]
+struct Scheduler {
+    /// Non-empty queues ordered by handle id
+    queues: Vec<Queue>,
+    last_handle_id: Option<usize>,
+}
+
+impl Scheduler {
+    fn is_empty(&self) -> bool {
+        self.queues.is_empty()
+    }
+
+    fn push(&mut self, command: Command) {
+        let index = match self
+            .queues
+            .binary_search_by_key(&command.target.handle_id, |queue| queue.handle_id)
+        {
+            Ok(index) => index,
+            Err(index) => {
+                let queue = Queue {
+                    handle_id: command.target.handle_id,
+                    priority: command.target.priority,
+                    commands: VecDeque::new(),
+                };
+                self.queues.insert(index, queue);
+                index
+            }
+        };
+        self.queues[index].commands.push_back(command);
+    }
+
+    /// The next request of the handle with the highest priority that
+    /// follows the previously served handle.
+    fn pop(&mut self) -> Option<Command> {
+        let priority = self.queues.iter().map(|queue| queue.priority).max()?;
+        let is_next = |queue: &Queue| {
+            queue.priority == priority
+                && self
+                    .last_handle_id
+                    .is_none_or(|last_handle_id| queue.handle_id > last_handle_id)
+        };
+        let index = self.queues.iter().position(is_next).or_else(|| {
+            self.queues
+                .iter()
+                .position(|queue| queue.priority == priority)
+        })?;
+        let queue = &mut self.queues[index];
+        let command = queue.commands.pop_front();
+        self.last_handle_id = Some(queue.handle_id);
+        if queue.commands.is_empty() {
+            self.queues.remove(index);
+        }
+        command
+    }
+}
+
+/// Execute requests until all handles and the bus have been dropped.
+async fn run<T>(
+    mut context: service::rtu::Context<T>,
+    config: BusConfig,
+    mut command_rx: mpsc::Receiver<Command>,
+) where
+    T: AsyncRead + AsyncWrite + Debug + Unpin + Send + 'static,
+{
+    let mut scheduler = Scheduler::default();
+    let mut idle_at = Instant::now();
+    loop {
+        if scheduler.is_empty() {
+            let Some(command) = command_rx.recv().await else {
+                break;
+            };
+            scheduler.push(command);
+        }
+        while let Ok(command) = command_rx.try_recv() {
+            scheduler.push(command);
+        }
+        let Some(command) = scheduler.pop() else {
+            continue;
+        };
+        if command.is_cancelled() {
+            continue;
+        }
+        let Command {
+            target: Target { slave, .. },
+            request,
+            response_tx,
+        } = command;
+        sleep_until(idle_at).await;
+        context.set_slave(slave);
+        let broadcast_response = slave
+            .is_broadcast()
+            .then(|| request.broadcast_response())
+            .flatten();
+        let (res, delay) = if let Some(res) = broadcast_response {
+            // Broadcasts are not answered and need time to be processed
+            let res = context.send(request).await.map(|()| res);
+            (res, config.turnaround_delay.max(config.inter_frame_delay))
+        } else {
+            let res = match config.response_timeout {
+                Some(timeout) => tokio::time::timeout(timeout, context.call(request))
+                    .await
+                    .unwrap_or(Err(Error::Timeout)),
+                None => context.call(request).await,
+            };
+            (res, config.inter_frame_delay)
+        };
+        idle_at = Instant::now() + delay;
+        let _ = response_tx.send(res);
+    }
+    log::debug!("Releasing the bus after all handles have been dropped");
+    let _ = context.call(Request::Disconnect).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::{sink::SinkExt as _, stream::StreamExt as _};
+    use tokio::{io::DuplexStream, sync::oneshot};
+    use tokio_util::codec::Framed;
+
+    use super::*;
+    use crate::frame::{
+        rtu::{Header, RequestAdu, ResponseAdu},
+        ResponsePdu,
+    };
+
+    type Server = Framed<DuplexStream, codec::rtu::ServerCodec>;
+
+    fn spawn_bus(config: BusConfig) -> (Bus, Server) {
+        let (client_io, server_io) = tokio::io::duplex(1024);
+        let bus = Bus::spawn(client_io, config);
+        let server = Framed::new(server_io, codec::rtu::ServerCodec::default());
+        (bus, server)
+    }
+
+    /// Receive the next request and respond with the slave id
+    async fn respond(server: &mut Server) -> Slave {
+        let RequestAdu { hdr, .. } = server.next().await.unwrap().unwrap();
+        send_response(server, hdr).await;
+        Slave(hdr.slave_id)
+    }
+
+    async fn send_response(server: &mut Server, hdr: Header) {
+        let pdu = ResponsePdu(Ok(Response::ReadHoldingRegisters(vec![hdr
+            .slave_id
+            .into()])));
+        server.send(ResponseAdu { hdr, pdu }).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn serve_handles_by_priority_and_in_turns() {
+        let (bus, mut server) = spawn_bus(BusConfig::default());
+        let first = bus.handle(Slave(1));
+        let second = bus.handle(Slave(2));
+        let urgent = bus.handle_with_priority(Slave(3), 1);
+
+        let (received_tx, received_rx) = oneshot::channel();
+        let (proceed_tx, proceed_rx) = oneshot::channel();
+        let server = tokio::spawn(async move {
+            // Keep the bus busy until all other requests have been queued
+            let request = server.next().await.unwrap().unwrap();
+            received_tx.send(()).unwrap();
+            proceed_rx.await.unwrap();
+            let pdu = ResponsePdu(Ok(Response::ReadHoldingRegisters(vec![0x0001])));
+            let hdr = request.hdr;
+            server.send(ResponseAdu { hdr, pdu }).await.unwrap();
+            let mut slaves = vec![Slave(hdr.slave_id)];
+            for _ in 0..5 {
+                slaves.push(respond(&mut server).await);
+            }
+            slaves
+        });
+
+        // Clones share their turns
+        let second_clone = second.clone();
+        let request = || Request::ReadHoldingRegisters(0x00, 1);
+        let busy = first.call(request());
+        tokio::pin!(busy);
+        tokio::select! {
+            _ = &mut busy => unreachable!(),
+            _ = received_rx => {}
+        }
+        let _ = tokio::join!(
+            busy,
+            first.call(request()),
+            second.call(request()),
+            urgent.call(request()),
+            second_clone.call(request()),
+            first.call(request()),
+            async { proceed_tx.send(()).unwrap() },
+        );
+        assert_eq!(
+            server.await.unwrap(),
+            [1, 3, 1, 2, 1, 2].map(Slave).to_vec()
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn do_not_wait_for_response_to_broadcast() {
+        let config = BusConfig::default().with_turnaround_delay(Duration::from_millis(100));
+        let (bus, mut server) = spawn_bus(config);
+        let broadcast = bus.handle(Slave::broadcast());
+        let single = bus.handle(Slave(1));
+
+        let start = Instant::now();
+        let rsp = broadcast
+            .call(Request::WriteSingleRegister(0x01, 0x03))
+            .await
+            .unwrap();
+        assert_eq!(rsp, Response::WriteSingleRegister(0x01, 0x03));
+        assert_eq!(start.elapsed(), Duration::ZERO);
+
+        let RequestAdu { hdr, .. } = server.next().await.unwrap().unwrap();
+        assert_eq!(hdr, Header { slave_id: 0 });
+        let (rsp, _) = tokio::join!(
+            single.call(Request::ReadHoldingRegisters(0x00, 1)),
+            respond(&mut server)
+        );
+        assert!(rsp.is_ok());
+        // The next request waits for the turnaround delay
+        assert_eq!(start.elapsed(), Duration::from_millis(100));
+
+        let res = broadcast.call(Request::ReadHoldingRegisters(0x00, 1)).await;
+        assert!(matches!(res, Err(Error::InvalidRequest(_))));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn release_bus_after_timeout() {
+        let config = BusConfig::default().with_response_timeout(Duration::from_secs(1));
+        let (bus, mut server) = spawn_bus(config);
+        let handle = bus.handle(Slave(1));
+
+        let res = handle.call(Request::ReadHoldingRegisters(0x00, 1)).await;
+        assert!(matches!(res, Err(Error::Timeout)));
+        server.next().await.unwrap().unwrap();
+        let (rsp, _) = tokio::join!(
+            handle.call(Request::ReadHoldingRegisters(0x00, 1)),
+            respond(&mut server)
+        );
+        assert!(rsp.is_ok());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn skip_late_response_of_other_slave_after_timeout() {
+        let config = BusConfig::default().with_response_timeout(Duration::from_secs(1));
+        let (bus, mut server) = spawn_bus(config);
+        let first = bus.handle(Slave(1));
+        let second = bus.handle(Slave(2));
+
+        let res = first.call(Request::ReadHoldingRegisters(0x00, 1)).await;
+        assert!(matches!(res, Err(Error::Timeout)));
+        let RequestAdu { hdr: late_hdr, .. } = server.next().await.unwrap().unwrap();
+        let (rsp, _) = tokio::join!(second.call(Request::ReadHoldingRegisters(0x00, 1)), async {
+            let RequestAdu { hdr, .. } = server.next().await.unwrap().unwrap();
+            // The first slave responds after the next request has been sent
+            send_response(&mut server, late_hdr).await;
+            send_response(&mut server, hdr).await;
+        });
+        assert_eq!(rsp.unwrap(), Response::ReadHoldingRegisters(vec![2]));
+    }
+}
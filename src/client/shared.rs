@@ -0,0 +1,205 @@
+// SPDX-FileCopyrightText: Copyright (c) 2017-2022 slowtec GmbH <post@slowtec.de>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Sharing a client between tasks
+
+use async_trait::async_trait;
+use tokio::sync::mpsc;
+
+use crate::{
+    frame::{Request, Response},
+    service::actor::{self, Command, REQUEST_QUEUE_LEN},
+    slave::{Slave, SlaveContext},
+    Error,
+};
+
+use super::{Client, Context};
+
+/// A cloneable handle of a client that is owned by a background task.
+///
+/// Requests of all handles are executed one after another in the
+/// order they have been received. Each handle addresses its own
+/// slave device. Convert a handle into a [`Context`] for using the
+/// [`Reader`](super::Reader) and [`Writer`](super::Writer) API.
+///
+/// The client is disconnected after the last handle has been dropped.
+#[derive(Debug, Clone)]
+pub struct SharedClient {
+    command_tx: mpsc::Sender<Command<Slave>>,
+    slave: Slave,
+    #[cfg(feature = "rtu")]
+    rtu_error_counters: Option<super::rtu::ErrorCounters>,
+}
+
+impl SharedClient {
+    /// Move the client into a background task.
+    ///
+    /// The returned handle addresses the given slave device.
+    ///
+    /// Must be invoked within a Tokio runtime.
+    #[must_use]
+    pub fn spawn(context: Context, slave: Slave) -> Self {
+        #[cfg(feature = "rtu")]
+        let rtu_error_counters = context.rtu_error_counters();
+        let (command_tx, command_rx) = mpsc::channel(REQUEST_QUEUE_LEN);
+        tokio::spawn(run(context, command_rx));
+        Self {
+            command_tx,
+            slave,
+            #[cfg(feature = "rtu")]
+            rtu_error_counters,
+        }
+    }
+
+    /// A new handle that addresses another slave device.
+    #[must_use]
+    pub fn with_slave(&self, slave: Slave) -> Self {
+        Self {
+            slave,
+            ..self.clone()
+        }
+    }
+
+    /// Invoke a Modbus function.
+    ///
+    /// Unlike [`Client::call`] this does not require exclusive access
+    /// to the handle.
+    pub async fn call(&self, request: Request) -> Result<Response, Error> {
+        actor::call(&self.command_tx, self.slave, request).await
+    }
+}
+
+impl SlaveContext for SharedClient {
+    fn set_slave(&mut self, slave: Slave) {
+        self.slave = slave;
+    }
+}
+
+#[async_trait]
+impl Client for SharedClient {
+    async fn call(&mut self, request: Request) -> Result<Response, Error> {
+        SharedClient::call(self, request).await
+    }
+
+    #[cfg(feature = "rtu")]
+    fn rtu_error_counters(&self) -> Option<super::rtu::ErrorCounters> {
+        self.rtu_error_counters.clone()
+    }
+}
+
+impl From<SharedClient> for Context {
+    fn from(client: SharedClient) -> Self {
+        Self::from(Box::new(client) as Box<dyn Client>)
+    }
+}
+
+/// Execute requests until all handles have been dropped.
+async fn run(mut context: Context, mut command_rx: mpsc::Receiver<Command<Slave>>) {
+    while let Some(command) = command_rx.recv().await {
+        if command.is_cancelled() {
+            continue;
+        }
+        let Command {
+            target: slave,
+            request,
+            response_tx,
+        } = command;
+        context.set_slave(slave);
+        let res = context.call(request).await;
+        let _ = response_tx.send(res);
+    }
+    log::debug!("Disconnecting after the last handle has been dropped");
+    if let Err(err) = context.disconnect().await {
+        log::warn!("Failed to disconnect: {}", err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::ErrorKind,
+        sync::{Arc, Mutex},
+    };
+
+    use super::*;
+    use crate::client::Reader as _;
+
+    type Calls = Arc<Mutex<Vec<(Slave, Request)>>>;
+
+    /// Records all calls with the addressed slave device
+    #[derive(Debug)]
+    struct RecordingClient {
+        slave: Slave,
+        calls: Calls,
+    }
+
+    #[async_trait]
+    impl Client for RecordingClient {
+        async fn call(&mut self, request: Request) -> Result<Response, Error> {
+            self.calls
+                .lock()
+                .unwrap()
+                .push((self.slave, request.clone()));
+            match request {
+                Request::ReadHoldingRegisters(_, _) => {
+                    Ok(Response::ReadHoldingRegisters(vec![self.slave.0.into()]))
+                }
+                _ => Err(Error::Transport(ErrorKind::NotConnected.into())),
+            }
+        }
+    }
+
+    impl SlaveContext for RecordingClient {
+        fn set_slave(&mut self, slave: Slave) {
+            self.slave = slave;
+        }
+    }
+
+    fn spawn_recording_client(slave: Slave) -> (SharedClient, Calls) {
+        let calls = Calls::default();
+        let client = RecordingClient {
+            slave: Slave(0),
+            calls: Arc::clone(&calls),
+        };
+        let context = Context::from(Box::new(client) as Box<dyn Client>);
+        (SharedClient::spawn(context, slave), calls)
+    }
+
+    #[tokio::test]
+    async fn address_slave_of_each_handle() {
+        let (first, calls) = spawn_recording_client(Slave(1));
+        let second = first.with_slave(Slave(2));
+
+        let (first_rsp, second_rsp) = tokio::join!(
+            first.call(Request::ReadHoldingRegisters(0x00, 1)),
+            second.call(Request::ReadHoldingRegisters(0x00, 1)),
+        );
+        assert_eq!(first_rsp.unwrap(), Response::ReadHoldingRegisters(vec![1]));
+        assert_eq!(second_rsp.unwrap(), Response::ReadHoldingRegisters(vec![2]));
+
+        let mut context = Context::from(second.clone());
+        assert_eq!(
+            context.read_holding_registers(0x00, 1).await.unwrap(),
+            vec![2]
+        );
+        assert_eq!(calls.lock().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn disconnect_after_last_handle_dropped() {
+        let (first, calls) = spawn_recording_client(Slave(1));
+        let second = first.clone();
+        drop(first);
+        assert!(second
+            .call(Request::ReadHoldingRegisters(0x00, 1))
+            .await
+            .is_ok());
+        drop(second);
+
+        // Wait until the client task has dropped the client
+        while Arc::strong_count(&calls) > 1 {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(calls.lock().unwrap().last().unwrap().1, Request::Disconnect);
+    }
+}
@@ -6,6 +6,10 @@
 // TODO: Add missing documentation
 #![allow(missing_docs)]
 
+/// ASCII client connections
+#[cfg(feature = "ascii")]
+pub mod ascii;
+
 /// RTU client connections
 #[cfg(feature = "rtu")]
 pub mod rtu;
@@ -15,14 +19,17 @@ pub mod rtu;
 pub mod tcp;
 
 use super::{
-    Client as AsyncClient, Context as AsyncContext, Reader as AsyncReader, SlaveContext,
-    Writer as AsyncWriter,
+    Client as AsyncClient, Context as AsyncContext, Reader as AsyncReader, RetryPolicy,
+    SlaveContext, Writer as AsyncWriter,
 };
 
 use crate::frame::*;
 use crate::slave::*;
+use crate::Error;
+
+use std::time::Duration;
 
-use std::io::Result;
+type Result<T> = std::result::Result<T, Error>;
 
 /// A transport independent synchronous client trait.
 pub trait Client: SlaveContext {
@@ -42,6 +49,18 @@ pub trait Reader: Client {
         _: Address,
         _: &[Word],
     ) -> Result<Vec<Word>>;
+    fn read_fifo_queue(&mut self, _: Address) -> Result<Vec<Word>>;
+    fn read_file_record(&mut self, _: &[ReadFileRecordSubRequest]) -> Result<Vec<Vec<Word>>>;
+    fn read_exception_status(&mut self) -> Result<u8>;
+    fn get_comm_event_counter(&mut self) -> Result<(Word, Word)>;
+    fn get_comm_event_log(&mut self) -> Result<CommEventLog>;
+    fn report_server_id(&mut self) -> Result<(u8, bool, Vec<u8>)>;
+    fn diagnostics(&mut self, _: DiagnosticsSubFunction, _: &[Word]) -> Result<Vec<Word>>;
+    fn read_device_identification(
+        &mut self,
+        _: ReadDeviceIdCode,
+        _: DeviceIdObject,
+    ) -> Result<DeviceIdentification>;
 }
 
 /// A transport independent synchronous writer trait.
@@ -50,6 +69,8 @@ pub trait Writer: Client {
     fn write_multiple_coils(&mut self, _: Address, _: &[Coil]) -> Result<()>;
     fn write_single_register(&mut self, _: Address, _: Word) -> Result<()>;
     fn write_multiple_registers(&mut self, _: Address, _: &[Word]) -> Result<()>;
+    fn mask_write_register(&mut self, _: Address, _: Word, _: Word) -> Result<()>;
+    fn write_file_record(&mut self, _: &[WriteFileRecordSubRequest]) -> Result<()>;
 }
 
 /// A synchronous Modbus client context.
@@ -59,6 +80,66 @@ pub struct Context {
     async_ctx: AsyncContext,
 }
 
+impl Context {
+    /// Set the default timeout for receiving a response (optional).
+    ///
+    /// Requests fail with [`Error::Timeout`] if no response has
+    /// been received in time. There is no timeout by default.
+    #[must_use]
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        Self {
+            async_ctx: self.async_ctx.with_timeout(timeout),
+            ..self
+        }
+    }
+
+    /// Change or disable the default timeout for receiving a response.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.async_ctx.set_timeout(timeout);
+    }
+
+    /// The default timeout for receiving a response.
+    #[must_use]
+    pub fn timeout(&self) -> Option<Duration> {
+        self.async_ctx.timeout()
+    }
+
+    /// Retry failed requests according to the policy (optional).
+    #[must_use]
+    pub fn with_retry_policy(self, retry_policy: RetryPolicy) -> Self {
+        Self {
+            async_ctx: self.async_ctx.with_retry_policy(retry_policy),
+            ..self
+        }
+    }
+
+    /// Change or disable the retry policy.
+    pub fn set_retry_policy(&mut self, retry_policy: Option<RetryPolicy>) {
+        self.async_ctx.set_retry_policy(retry_policy);
+    }
+
+    /// The retry policy.
+    #[must_use]
+    pub fn retry_policy(&self) -> Option<&RetryPolicy> {
+        self.async_ctx.retry_policy()
+    }
+
+    /// Invoke a Modbus function with a timeout that overrides the
+    /// default timeout.
+    pub fn call_with_timeout(&mut self, req: Request, timeout: Duration) -> Result<Response> {
+        self.core
+            .block_on(self.async_ctx.call_with_timeout(req, timeout))
+    }
+
+    /// The counters of bus errors that have been detected while
+    /// decoding frames, if connected to an RTU device.
+    #[cfg(feature = "rtu")]
+    #[must_use]
+    pub fn rtu_error_counters(&self) -> Option<super::rtu::ErrorCounters> {
+        self.async_ctx.rtu_error_counters()
+    }
+}
+
 impl Client for Context {
     fn call(&mut self, req: Request) -> Result<Response> {
         self.core.block_on(self.async_ctx.call(req))
@@ -103,6 +184,54 @@ impl Reader for Context {
                 .read_write_multiple_registers(read_addr, read_cnt, write_addr, write_data),
         )
     }
+
+    fn read_fifo_queue(&mut self, addr: Address) -> Result<Vec<Word>> {
+        self.core.block_on(self.async_ctx.read_fifo_queue(addr))
+    }
+
+    fn read_file_record(
+        &mut self,
+        sub_requests: &[ReadFileRecordSubRequest],
+    ) -> Result<Vec<Vec<Word>>> {
+        self.core
+            .block_on(self.async_ctx.read_file_record(sub_requests))
+    }
+
+    fn read_exception_status(&mut self) -> Result<u8> {
+        self.core.block_on(self.async_ctx.read_exception_status())
+    }
+
+    fn get_comm_event_counter(&mut self) -> Result<(Word, Word)> {
+        self.core.block_on(self.async_ctx.get_comm_event_counter())
+    }
+
+    fn get_comm_event_log(&mut self) -> Result<CommEventLog> {
+        self.core.block_on(self.async_ctx.get_comm_event_log())
+    }
+
+    fn report_server_id(&mut self) -> Result<(u8, bool, Vec<u8>)> {
+        self.core.block_on(self.async_ctx.report_server_id())
+    }
+
+    fn diagnostics(
+        &mut self,
+        sub_function: DiagnosticsSubFunction,
+        data: &[Word],
+    ) -> Result<Vec<Word>> {
+        self.core
+            .block_on(self.async_ctx.diagnostics(sub_function, data))
+    }
+
+    fn read_device_identification(
+        &mut self,
+        read_device_id_code: ReadDeviceIdCode,
+        object: DeviceIdObject,
+    ) -> Result<DeviceIdentification> {
+        self.core.block_on(
+            self.async_ctx
+                .read_device_identification(read_device_id_code, object),
+        )
+    }
 }
 
 impl Writer for Context {
@@ -125,4 +254,14 @@ impl Writer for Context {
         self.core
             .block_on(self.async_ctx.write_multiple_coils(addr, coils))
     }
+
+    fn mask_write_register(&mut self, addr: Address, and_mask: Word, or_mask: Word) -> Result<()> {
+        self.core
+            .block_on(self.async_ctx.mask_write_register(addr, and_mask, or_mask))
+    }
+
+    fn write_file_record(&mut self, sub_requests: &[WriteFileRecordSubRequest]) -> Result<()> {
+        self.core
+            .block_on(self.async_ctx.write_file_record(sub_requests))
+    }
 }
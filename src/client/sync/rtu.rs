@@ -1,10 +1,17 @@
 // SPDX-FileCopyrightText: Copyright (c) 2017-2022 slowtec GmbH <post@slowtec.de>
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
-use super::{Context, Result};
+use super::Context;
+
+use std::io::Result;
 
 use tokio_serial::{SerialPortBuilder, SerialStream};
 
+#[cfg(feature = "tcp")]
+use std::net::SocketAddr;
+
+#[cfg(feature = "tcp")]
+use crate::client::rtu::connect_rtu_over_tcp as async_connect_rtu_over_tcp;
 use crate::client::rtu::connect_slave as async_connect_slave;
 use crate::slave::Slave;
 
@@ -18,6 +25,7 @@ pub fn connect(builder: &SerialPortBuilder) -> Result<Context> {
 pub fn connect_slave(builder: &SerialPortBuilder, slave: Slave) -> Result<Context> {
     let rt = tokio::runtime::Builder::new_current_thread()
         .enable_io()
+        .enable_time()
         .build()?;
     // SerialStream::open requires a runtime at least on cfg(unix).
     let serial = rt.block_on(async { SerialStream::open(builder) })?;
@@ -28,3 +36,19 @@ pub fn connect_slave(builder: &SerialPortBuilder, slave: Slave) -> Result<Contex
     };
     Ok(sync_ctx)
 }
+
+/// Connect to a Modbus RTU slave device through a TCP connection
+/// that transparently forwards raw RTU frames.
+#[cfg(feature = "tcp")]
+pub fn connect_rtu_over_tcp(socket_addr: SocketAddr, slave: Slave) -> Result<Context> {
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_io()
+        .enable_time()
+        .build()?;
+    let async_ctx = rt.block_on(async_connect_rtu_over_tcp(socket_addr, slave))?;
+    let sync_ctx = Context {
+        core: rt,
+        async_ctx,
+    };
+    Ok(sync_ctx)
+}
@@ -3,12 +3,12 @@
 
 //! TCP client connections
 
-use std::net::SocketAddr;
+use std::{io::Result, net::SocketAddr};
 
 use crate::client::tcp::connect_slave as async_connect_slave;
 use crate::slave::Slave;
 
-use super::{Context, Result};
+use super::Context;
 
 /// Establish a direct connection to a Modbus TCP coupler.
 pub fn connect(socket_addr: SocketAddr) -> Result<Context> {
@@ -21,6 +21,7 @@ pub fn connect(socket_addr: SocketAddr) -> Result<Context> {
 pub fn connect_slave(socket_addr: SocketAddr, slave: Slave) -> Result<Context> {
     let rt = tokio::runtime::Builder::new_current_thread()
         .enable_io()
+        .enable_time()
         .build()?;
     let async_ctx = rt.block_on(async_connect_slave(socket_addr, slave))?;
     let sync_ctx = Context {
@@ -9,6 +9,8 @@ use crate::service;
 
 use std::{future::Future, io::Error, net::SocketAddr};
 
+pub use crate::service::tcp::pipeline::Pipeline;
+
 /// Establish a direct connection to a Modbus TCP coupler.
 pub fn connect(socket_addr: SocketAddr) -> impl Future<Output = Result<Context, Error>> {
     connect_slave(socket_addr, Slave::tcp_device())
@@ -28,6 +30,50 @@ pub fn connect_slave(
 
         Ok(Context {
             client: Box::new(context),
+            timeout: None,
+            retry_policy: None,
         })
     }
 }
+
+/// Connect to a Modbus TCP coupler or gateway for sending multiple
+/// requests without waiting for the responses of previous requests.
+///
+/// At most `max_outstanding` requests are awaiting their responses at
+/// the same time. The returned handle addresses the given slave device.
+/// Use [`Pipeline::with_slave`] for addressing other devices through
+/// the same connection.
+pub async fn connect_slave_pipelined(
+    socket_addr: SocketAddr,
+    slave: Slave,
+    max_outstanding: usize,
+) -> Result<Pipeline, Error> {
+    let transport = tokio::net::TcpStream::connect(socket_addr).await?;
+    Ok(service::tcp::pipeline::attach_slave(
+        transport,
+        slave,
+        max_outstanding,
+    ))
+}
+
+/// Connect to a Modbus device on demand and reconnect after the
+/// connection broke.
+///
+/// No connection is established until the first request. Requests
+/// fail if the connection could not be (re-)established.
+#[must_use]
+pub fn connect_slave_reconnecting(
+    socket_addr: SocketAddr,
+    slave: Slave,
+    policy: ReconnectPolicy,
+) -> Context {
+    let client = reconnect::Reconnecting::new(
+        move |slave| -> reconnect::ConnectFuture {
+            let context_future = service::tcp::connect_slave(socket_addr, slave);
+            Box::pin(async move { Ok(Box::new(context_future.await?) as Box<dyn Client>) })
+        },
+        policy,
+        slave,
+    );
+    Context::from(Box::new(client) as Box<dyn Client>)
+}
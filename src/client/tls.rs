@@ -0,0 +1,111 @@
+// SPDX-FileCopyrightText: Copyright (c) 2017-2022 slowtec GmbH <post@slowtec.de>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! TLS client connections according to the Modbus/TCP Security protocol
+//!
+//! [MODBUS/TCP Security Protocol Specification](https://modbus.org/docs/MB-TCP-Security-v21_2018-07-24.pdf)
+//! secures the regular Modbus TCP messages with mutually authenticated
+//! TLS connections, typically on the well-known port 802.
+
+use super::*;
+
+use crate::service;
+
+use std::{
+    fs::File,
+    io::{BufReader, Error, ErrorKind},
+    net::SocketAddr,
+    path::Path,
+    sync::Arc,
+};
+use tokio::net::TcpStream;
+use tokio_rustls::{
+    rustls::{
+        crypto::ring::default_provider,
+        pki_types::{CertificateDer, PrivateKeyDer, ServerName},
+        ClientConfig, RootCertStore,
+    },
+    TlsConnector,
+};
+
+/// The version of [`rustls`] that is used for configuring TLS connections.
+pub use tokio_rustls::rustls;
+
+/// The well-known port for Modbus/TCP Security.
+pub const DEFAULT_PORT: u16 = 802;
+
+/// Establish a direct, secure connection to a Modbus TCP device.
+///
+/// The `server_name` is sent to the server for SNI and must match
+/// the subject of the server's certificate.
+pub async fn connect(
+    socket_addr: SocketAddr,
+    server_name: ServerName<'static>,
+    config: Arc<ClientConfig>,
+) -> Result<Context, Error> {
+    connect_slave(socket_addr, server_name, config, Slave::tcp_device()).await
+}
+
+/// Connect securely to a physical, broadcast, or custom Modbus device,
+/// probably through a Modbus TCP gateway that is forwarding messages
+/// to/from the corresponding slave device.
+///
+/// The `server_name` is sent to the server for SNI and must match
+/// the subject of the server's certificate. Client certificates
+/// are configured with the `config`, see [`client_config()`].
+pub async fn connect_slave(
+    socket_addr: SocketAddr,
+    server_name: ServerName<'static>,
+    config: Arc<ClientConfig>,
+    slave: Slave,
+) -> Result<Context, Error> {
+    let stream = TcpStream::connect(socket_addr).await?;
+    let transport = TlsConnector::from(config)
+        .connect(server_name, stream)
+        .await?;
+    let client = service::tcp::attach_slave(transport, slave);
+
+    Ok(Context {
+        client: Box::new(client),
+        timeout: None,
+        retry_policy: None,
+    })
+}
+
+/// Create a client configuration for mutually authenticated connections.
+///
+/// The server's certificate is verified against the `root_certs`. The
+/// client authenticates itself with the `cert_chain` and its `key`.
+pub fn client_config(
+    root_certs: impl IntoIterator<Item = CertificateDer<'static>>,
+    cert_chain: Vec<CertificateDer<'static>>,
+    key: PrivateKeyDer<'static>,
+) -> Result<ClientConfig, Error> {
+    let mut roots = RootCertStore::empty();
+    for cert in root_certs {
+        roots.add(cert).map_err(invalid_input)?;
+    }
+    ClientConfig::builder_with_provider(Arc::new(default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(invalid_input)?
+        .with_root_certificates(roots)
+        .with_client_auth_cert(cert_chain, key)
+        .map_err(invalid_input)
+}
+
+/// Load all certificates from a PEM file.
+pub fn load_certs(path: impl AsRef<Path>) -> Result<Vec<CertificateDer<'static>>, Error> {
+    let mut reader = BufReader::new(File::open(path)?);
+    rustls_pemfile::certs(&mut reader).collect()
+}
+
+/// Load the first private key from a PEM file.
+pub fn load_private_key(path: impl AsRef<Path>) -> Result<PrivateKeyDer<'static>, Error> {
+    let mut reader = BufReader::new(File::open(path)?);
+    rustls_pemfile::private_key(&mut reader)?
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "No private key found"))
+}
+
+pub(crate) fn invalid_input(err: rustls::Error) -> Error {
+    Error::new(ErrorKind::InvalidInput, err)
+}
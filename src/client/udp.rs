@@ -0,0 +1,48 @@
+// SPDX-FileCopyrightText: Copyright (c) 2017-2022 slowtec GmbH <post@slowtec.de>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! UDP client connections
+
+use super::*;
+
+use crate::service;
+
+use std::{io::Error, net::SocketAddr, time::Duration};
+
+/// The time to wait for a response if not specified otherwise.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Establish a direct connection to a Modbus UDP device.
+///
+/// Requests time out after 1 second without a response.
+pub async fn connect(socket_addr: SocketAddr) -> Result<Context, Error> {
+    connect_slave(socket_addr, Slave::tcp_device()).await
+}
+
+/// Connect to a physical, broadcast, or custom Modbus device,
+/// probably through a Modbus UDP gateway that is forwarding
+/// messages to/from the corresponding slave device.
+///
+/// Requests time out after 1 second without a response.
+pub async fn connect_slave(socket_addr: SocketAddr, slave: Slave) -> Result<Context, Error> {
+    connect_slave_with_timeout(socket_addr, slave, DEFAULT_TIMEOUT).await
+}
+
+/// Connect to a Modbus device with a custom response timeout.
+///
+/// Lost datagrams are detected by this timeout, i.e. requests
+/// fail with [`Error::Timeout`](crate::Error::Timeout) if no matching
+/// response has been received in time.
+pub async fn connect_slave_with_timeout(
+    socket_addr: SocketAddr,
+    slave: Slave,
+    timeout: Duration,
+) -> Result<Context, Error> {
+    let client = service::udp::connect_slave(socket_addr, slave, timeout).await?;
+
+    Ok(Context {
+        client: Box::new(client),
+        timeout: None,
+        retry_policy: None,
+    })
+}
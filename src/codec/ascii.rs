@@ -0,0 +1,393 @@
+// SPDX-FileCopyrightText: Copyright (c) 2017-2022 slowtec GmbH <post@slowtec.de>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use super::*;
+
+use crate::{frame::ascii::*, slave::SlaveId};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use log::{error, warn};
+use std::io::{Error, ErrorKind, Result};
+use tokio_util::codec::{Decoder, Encoder};
+
+// [MODBUS over Serial Line Specification and Implementation Guide V1.02](http://modbus.org/docs/Modbus_over_serial_line_V1_02.pdf), page 17
+// "The maximum size of a MODBUS ASCII frame is 513 characters."
+const MAX_FRAME_LEN: usize = 513;
+
+const START_OF_FRAME: u8 = b':';
+
+const END_OF_FRAME: [u8; 2] = [b'\r', b'\n'];
+
+#[derive(Debug, Default, Eq, PartialEq)]
+pub(crate) struct FrameDecoder;
+
+impl FrameDecoder {
+    pub(crate) fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<(SlaveId, Bytes)>> {
+        loop {
+            // Everything before the start of a frame is noise
+            match buf.iter().position(|&b| b == START_OF_FRAME) {
+                Some(0) => (),
+                Some(pos) => {
+                    warn!("Dropped {} byte(s): {:X?}", pos, &buf[..pos]);
+                    buf.advance(pos);
+                }
+                None => {
+                    if !buf.is_empty() {
+                        warn!("Dropped {} byte(s): {:X?}", buf.len(), &buf[..]);
+                        buf.clear();
+                    }
+                    return Ok(None);
+                }
+            }
+            debug_assert_eq!(buf.first(), Some(&START_OF_FRAME));
+
+            // A start character inside a frame means that the previous
+            // frame has been truncated. Resynchronize on the new frame.
+            let end = buf.windows(2).position(|w| w == END_OF_FRAME);
+            let restart = buf[1..end.unwrap_or(buf.len())]
+                .iter()
+                .position(|&b| b == START_OF_FRAME);
+            if let Some(pos) = restart {
+                warn!("Dropped truncated frame: {:X?}", &buf[..=pos]);
+                buf.advance(pos + 1);
+                continue;
+            }
+
+            let Some(end) = end else {
+                if buf.len() >= MAX_FRAME_LEN {
+                    error!(
+                        "Giving up to decode frame after receiving {} byte(s) without an end of frame",
+                        buf.len()
+                    );
+                    buf.clear();
+                }
+                // Incomplete frame
+                return Ok(None);
+            };
+
+            let frame = buf.split_to(end + END_OF_FRAME.len());
+            match decode_frame(&frame[1..end]) {
+                Ok(res) => return Ok(Some(res)),
+                Err(err) => {
+                    warn!("Failed to decode frame {:X?}: {}", &frame[..], err);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default, Eq, PartialEq)]
+pub(crate) struct ClientCodec {
+    pub(crate) decoder: FrameDecoder,
+}
+
+#[cfg_attr(not(feature = "server"), allow(dead_code))]
+#[derive(Debug, Default, Eq, PartialEq)]
+pub(crate) struct ServerCodec {
+    pub(crate) decoder: FrameDecoder,
+}
+
+/// Decode the hex encoded contents of a frame between the start
+/// and the end of frame delimiters and verify the trailing LRC.
+fn decode_frame(hex: &[u8]) -> Result<(SlaveId, Bytes)> {
+    // At least slave id, function code, and LRC
+    if hex.len() < 6 || !hex.len().is_multiple_of(2) {
+        return Err(protocol_violation(format!(
+            "Invalid frame length: {}",
+            hex.len()
+        )));
+    }
+    let mut data = BytesMut::with_capacity(hex.len() / 2);
+    for pair in hex.chunks_exact(2) {
+        data.put_u8(hex_to_nibble(pair[0])? << 4 | hex_to_nibble(pair[1])?);
+    }
+    let lrc = data.split_off(data.len() - 1)[0];
+    check_lrc(&data, lrc)?;
+    let slave_id = data.split_to(1)[0];
+    Ok((slave_id, data.freeze()))
+}
+
+fn hex_to_nibble(hex: u8) -> Result<u8> {
+    match hex {
+        b'0'..=b'9' => Ok(hex - b'0'),
+        b'A'..=b'F' => Ok(hex - b'A' + 10),
+        b'a'..=b'f' => Ok(hex - b'a' + 10),
+        _ => Err(protocol_violation(format!(
+            "Invalid hex character: 0x{:0>2X}",
+            hex
+        ))),
+    }
+}
+
+fn put_hex(buf: &mut BytesMut, byte: u8) {
+    const HEX_DIGITS: &[u8; 16] = b"0123456789ABCDEF";
+    buf.put_u8(HEX_DIGITS[usize::from(byte >> 4)]);
+    buf.put_u8(HEX_DIGITS[usize::from(byte & 0x0F)]);
+}
+
+fn calc_lrc(data: &[u8]) -> u8 {
+    data.iter()
+        .fold(0u8, |lrc, x| lrc.wrapping_add(*x))
+        .wrapping_neg()
+}
+
+fn check_lrc(adu_data: &[u8], expected_lrc: u8) -> Result<()> {
+    let actual_lrc = calc_lrc(adu_data);
+    if expected_lrc != actual_lrc {
+        return Err(protocol_violation(format!(
+            "Invalid LRC: expected = 0x{:0>2X}, actual = 0x{:0>2X}",
+            expected_lrc, actual_lrc
+        )));
+    }
+    Ok(())
+}
+
+fn encode_frame(slave_id: SlaveId, pdu_data: &[u8], buf: &mut BytesMut) {
+    buf.reserve((pdu_data.len() + 2) * 2 + 3);
+    buf.put_u8(START_OF_FRAME);
+    put_hex(buf, slave_id);
+    for b in pdu_data {
+        put_hex(buf, *b);
+    }
+    let lrc = calc_lrc(pdu_data).wrapping_sub(slave_id);
+    put_hex(buf, lrc);
+    buf.put_slice(&END_OF_FRAME);
+}
+
+impl Decoder for ClientCodec {
+    type Item = ResponseAdu;
+    type Error = Error;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<ResponseAdu>> {
+        while let Some((slave_id, pdu_data)) = self.decoder.decode(buf)? {
+            let hdr = Header { slave_id };
+            // The frame has already been verified with the LRC. It is
+            // skipped to resynchronize with the bus like an invalid frame.
+            match ResponsePdu::try_from(pdu_data) {
+                Ok(pdu) => return Ok(Some(ResponseAdu { hdr, pdu })),
+                Err(err) => {
+                    warn!("Dropping frame with malformed response PDU: {}", err);
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl Decoder for ServerCodec {
+    type Item = RequestAdu;
+    type Error = Error;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<RequestAdu>> {
+        while let Some((slave_id, pdu_data)) = self.decoder.decode(buf)? {
+            let hdr = Header { slave_id };
+            // The frame has already been verified with the LRC. It is
+            // skipped to resynchronize with the bus like an invalid frame.
+            match RequestPdu::try_from(pdu_data) {
+                Ok(pdu) => {
+                    return Ok(Some(RequestAdu {
+                        hdr,
+                        pdu,
+                        disconnect: false,
+                    }))
+                }
+                Err(err) => {
+                    warn!("Dropping frame with malformed request PDU: {}", err);
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl Encoder<RequestAdu> for ClientCodec {
+    type Error = Error;
+
+    fn encode(&mut self, adu: RequestAdu, buf: &mut BytesMut) -> Result<()> {
+        if adu.disconnect {
+            // The disconnect happens implicitly after letting this request
+            // fail by returning an error. This will drop the attached
+            // transport, e.g. for closing a stale, exclusive connection
+            // to a serial port before trying to reconnect.
+            return Err(Error::new(
+                ErrorKind::NotConnected,
+                "Disconnecting - not an error",
+            ));
+        }
+        let RequestAdu { hdr, pdu, .. } = adu;
+        check_request_len(&pdu.0)?;
+        let pdu_data: Bytes = pdu.into();
+        encode_frame(hdr.slave_id, &pdu_data, buf);
+        Ok(())
+    }
+}
+
+impl Encoder<ResponseAdu> for ServerCodec {
+    type Error = Error;
+
+    fn encode(&mut self, adu: ResponseAdu, buf: &mut BytesMut) -> Result<()> {
+        let ResponseAdu { hdr, pdu } = adu;
+        let pdu_data: Bytes = pdu.into();
+        encode_frame(hdr.slave_id, &pdu_data, buf);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_calc_lrc() {
+        // Example from the serial line specification, page 39
+        let msg = [0x11, 0x03, 0x00, 0x6B, 0x00, 0x03];
+        assert_eq!(calc_lrc(&msg), 0x7E);
+    }
+
+    #[test]
+    fn hex_nibbles() {
+        assert_eq!(hex_to_nibble(b'0').unwrap(), 0x0);
+        assert_eq!(hex_to_nibble(b'9').unwrap(), 0x9);
+        assert_eq!(hex_to_nibble(b'A').unwrap(), 0xA);
+        assert_eq!(hex_to_nibble(b'f').unwrap(), 0xF);
+        assert!(hex_to_nibble(b'G').is_err());
+        assert!(hex_to_nibble(b':').is_err());
+    }
+
+    mod client {
+
+        use super::*;
+
+        #[test]
+        fn encode_read_request() {
+            let mut codec = ClientCodec::default();
+            let mut buf = BytesMut::new();
+            let pdu = Request::ReadHoldingRegisters(0x006B, 3).into();
+            let hdr = Header { slave_id: 0x11 };
+            let adu = RequestAdu {
+                hdr,
+                pdu,
+                disconnect: false,
+            };
+            codec.encode(adu, &mut buf).unwrap();
+            assert_eq!(&buf[..], b":1103006B00037E\r\n");
+        }
+
+        #[test]
+        fn decode_read_response() {
+            let mut codec = ClientCodec::default();
+            let mut buf = BytesMut::from(&b":010304890242C764\r\n:01"[..]);
+            let ResponseAdu { hdr, pdu } = codec.decode(&mut buf).unwrap().unwrap();
+            assert_eq!(&buf[..], b":01");
+            assert_eq!(hdr.slave_id, 0x01);
+            if let Ok(Response::ReadHoldingRegisters(data)) = pdu.into() {
+                assert_eq!(data, vec![0x8902, 0x42C7]);
+            } else {
+                panic!("unexpected response")
+            }
+        }
+
+        #[test]
+        fn decode_lower_case_hex() {
+            let mut codec = ClientCodec::default();
+            let mut buf = BytesMut::from(&b":010304890242c764\r\n"[..]);
+            assert!(codec.decode(&mut buf).unwrap().is_some());
+            assert!(buf.is_empty());
+        }
+
+        #[test]
+        fn decode_partly_received_message() {
+            let mut codec = ClientCodec::default();
+            let mut buf = BytesMut::from(&b":010304890242C7"[..]);
+            assert!(codec.decode(&mut buf).unwrap().is_none());
+            assert_eq!(buf.len(), 15);
+        }
+
+        #[test]
+        fn decode_drop_noise_before_frame() {
+            let mut codec = ClientCodec::default();
+            let mut buf = BytesMut::from(&b"\x00\xFF\r\n:010304890242C764\r\n"[..]);
+            let ResponseAdu { hdr, .. } = codec.decode(&mut buf).unwrap().unwrap();
+            assert_eq!(hdr.slave_id, 0x01);
+            assert!(buf.is_empty());
+        }
+
+        #[test]
+        fn decode_drop_truncated_frame() {
+            let mut codec = ClientCodec::default();
+            let mut buf = BytesMut::from(&b":0103:010304890242C764\r\n"[..]);
+            let ResponseAdu { hdr, .. } = codec.decode(&mut buf).unwrap().unwrap();
+            assert_eq!(hdr.slave_id, 0x01);
+            assert!(buf.is_empty());
+        }
+
+        #[test]
+        fn decode_drop_frame_with_invalid_lrc() {
+            let mut codec = ClientCodec::default();
+            let mut buf = BytesMut::from(&b":010304890242C700\r\n:010304890242C764\r\n"[..]);
+            let ResponseAdu { pdu, .. } = codec.decode(&mut buf).unwrap().unwrap();
+            assert_eq!(
+                pdu,
+                ResponsePdu(Ok(Response::ReadHoldingRegisters(vec![0x8902, 0x42C7])))
+            );
+            assert!(buf.is_empty());
+        }
+
+        #[test]
+        fn decode_drop_frame_with_malformed_pdu() {
+            let mut codec = ClientCodec::default();
+            // The byte count exceeds the data of the frame
+            let mut buf = BytesMut::from(&b":01030489026D\r\n:010304890242C764\r\n"[..]);
+            let ResponseAdu { pdu, .. } = codec.decode(&mut buf).unwrap().unwrap();
+            assert_eq!(
+                pdu,
+                ResponsePdu(Ok(Response::ReadHoldingRegisters(vec![0x8902, 0x42C7])))
+            );
+            assert!(buf.is_empty());
+        }
+
+        #[test]
+        fn decode_exception_message() {
+            let mut codec = ClientCodec::default();
+            let mut buf = BytesMut::from(&b":66820315\r\n"[..]);
+            let ResponseAdu { pdu, .. } = codec.decode(&mut buf).unwrap().unwrap();
+            if let ResponsePdu(Err(err)) = pdu {
+                assert_eq!(format!("{}", err), "Modbus function 2: Illegal data value");
+                assert!(buf.is_empty());
+            } else {
+                panic!("unexpected response")
+            }
+        }
+    }
+
+    mod server {
+
+        use super::*;
+
+        #[test]
+        fn decode_request_and_encode_response() {
+            let mut codec = ServerCodec::default();
+            let mut buf = BytesMut::from(&b":1103006B00037E\r\n"[..]);
+            let RequestAdu { hdr, pdu, .. } = codec.decode(&mut buf).unwrap().unwrap();
+            assert_eq!(hdr.slave_id, 0x11);
+            assert_eq!(pdu.0, Request::ReadHoldingRegisters(0x006B, 3));
+
+            let adu = ResponseAdu {
+                hdr,
+                pdu: Response::ReadHoldingRegisters(vec![0xAE41, 0x5652, 0x4340]).into(),
+            };
+            codec.encode(adu, &mut buf).unwrap();
+            assert_eq!(&buf[..], b":110306AE4156524340CC\r\n");
+        }
+
+        #[test]
+        fn decode_drop_frame_with_malformed_pdu() {
+            let mut codec = ServerCodec::default();
+            // The quantity is missing
+            let mut buf = BytesMut::from(&b":1103006B81\r\n:1103006B00037E\r\n"[..]);
+            let RequestAdu { pdu, .. } = codec.decode(&mut buf).unwrap().unwrap();
+            assert_eq!(pdu.0, Request::ReadHoldingRegisters(0x006B, 3));
+            assert!(buf.is_empty());
+        }
+    }
+}
@@ -2,18 +2,28 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
 #![allow(deprecated)]
+#[cfg(feature = "ascii")]
+pub(crate) mod ascii;
+
 #[cfg(feature = "rtu")]
 pub(crate) mod rtu;
 
-#[cfg(feature = "tcp")]
+#[cfg(any(feature = "tcp", feature = "udp"))]
 pub(crate) mod tcp;
 
-use crate::frame::*;
+use crate::{error::protocol_violation, frame::*};
 
 use byteorder::{BigEndian, ReadBytesExt as _};
 use bytes::{BufMut, Bytes, BytesMut};
+use std::collections::BTreeMap;
 use std::convert::TryFrom;
-use std::io::{self, Cursor, Error, ErrorKind};
+use std::io::{self, Cursor, Error, Read as _};
+
+/// The reference type of all file record sub-requests.
+const FILE_RECORD_REF_TYPE: u8 = 0x06;
+
+/// The MODBUS Encapsulated Interface (MEI) type for Read Device Identification.
+pub(crate) const MEI_READ_DEVICE_ID: u8 = 0x0E;
 
 #[allow(clippy::cast_possible_truncation)]
 fn u16_len(len: usize) -> u16 {
@@ -28,11 +38,41 @@ fn u16_len(len: usize) -> u16 {
 fn u8_len(len: usize) -> u8 {
     // This type conversion should always be safe, because either
     // the caller is responsible to pass a valid usize or the
-    // possible values are limited by the protocol.
+    // possible values are limited by the protocol. The byte counts
+    // of requests are checked by `check_request_len()`.
     debug_assert!(len <= u8::MAX.into());
     len as u8
 }
 
+/// Reject a request with more data than its byte count could hold
+/// before encoding it.
+///
+/// The error of kind `InvalidInput` is converted into
+/// [`crate::Error::InvalidRequest`].
+#[cfg(any(feature = "rtu", feature = "ascii", feature = "tcp", feature = "udp"))]
+pub(crate) fn check_request_len(req: &Request) -> io::Result<()> {
+    use crate::frame::Request::*;
+    let byte_count = match req {
+        WriteMultipleCoils(_, coils) => packed_coils_len(coils.len()),
+        WriteMultipleRegisters(_, words) | ReadWriteMultipleRegisters(_, _, _, words) => {
+            words.len() * 2
+        }
+        ReadFileRecord(sub_requests) => sub_requests.len() * 7,
+        WriteFileRecord(sub_requests) => write_file_record_sub_requests_len(sub_requests),
+        _ => return Ok(()),
+    };
+    if byte_count > u8::MAX.into() {
+        return Err(crate::Error::InvalidRequest(format!(
+            "Byte count {} of {:?} exceeds {}",
+            byte_count,
+            req,
+            u8::MAX
+        ))
+        .into());
+    }
+    Ok(())
+}
+
 impl From<Request> for Bytes {
     fn from(req: Request) -> Bytes {
         let cnt = request_byte_count(&req);
@@ -85,6 +125,38 @@ impl From<Request> for Bytes {
                     data.put_u16(w);
                 }
             }
+            ReadExceptionStatus | GetCommEventCounter | GetCommEventLog | ReportServerId => {}
+            Diagnostics(sub_function, words) => {
+                data.put_u16(sub_function.into());
+                for w in words {
+                    data.put_u16(w);
+                }
+            }
+            ReadFileRecord(sub_requests) => {
+                data.put_u8(u8_len(sub_requests.len() * 7));
+                for sub_request in sub_requests {
+                    data.put_u8(FILE_RECORD_REF_TYPE);
+                    data.put_u16(sub_request.file_number);
+                    data.put_u16(sub_request.record_number);
+                    data.put_u16(sub_request.record_length);
+                }
+            }
+            WriteFileRecord(sub_requests) => {
+                put_write_file_record_sub_requests(&mut data, &sub_requests);
+            }
+            MaskWriteRegister(address, and_mask, or_mask) => {
+                data.put_u16(address);
+                data.put_u16(and_mask);
+                data.put_u16(or_mask);
+            }
+            ReadFifoQueue(address) => {
+                data.put_u16(address);
+            }
+            ReadDeviceIdentification(read_device_id_code, object) => {
+                data.put_u8(MEI_READ_DEVICE_ID);
+                data.put_u8(read_device_id_code.into());
+                data.put_u8(object.into());
+            }
             Custom(_, custom_data) => {
                 for d in custom_data {
                     data.put_u8(d);
@@ -136,6 +208,71 @@ impl From<Response> for Bytes {
                 data.put_u16(address);
                 data.put_u16(word);
             }
+            ReadExceptionStatus(status) => {
+                data.put_u8(status);
+            }
+            GetCommEventCounter(status, event_count) => {
+                data.put_u16(status);
+                data.put_u16(event_count);
+            }
+            GetCommEventLog(log) => {
+                data.put_u8(u8_len(6 + log.events.len()));
+                data.put_u16(log.status);
+                data.put_u16(log.event_count);
+                data.put_u16(log.message_count);
+                data.put_slice(&log.events);
+            }
+            ReportServerId(server_id, run_indicator, additional_data) => {
+                data.put_u8(u8_len(2 + additional_data.len()));
+                data.put_u8(server_id);
+                data.put_u8(if run_indicator { 0xFF } else { 0x00 });
+                data.put_slice(&additional_data);
+            }
+            Diagnostics(sub_function, words) => {
+                data.put_u16(sub_function.into());
+                for w in words {
+                    data.put_u16(w);
+                }
+            }
+            ReadFileRecord(records) => {
+                let byte_count = records.iter().map(|r| 2 + r.len() * 2).sum();
+                data.put_u8(u8_len(byte_count));
+                for record in records {
+                    data.put_u8(u8_len(1 + record.len() * 2));
+                    data.put_u8(FILE_RECORD_REF_TYPE);
+                    for r in record {
+                        data.put_u16(r);
+                    }
+                }
+            }
+            WriteFileRecord(sub_requests) => {
+                put_write_file_record_sub_requests(&mut data, &sub_requests);
+            }
+            MaskWriteRegister(address, and_mask, or_mask) => {
+                data.put_u16(address);
+                data.put_u16(and_mask);
+                data.put_u16(or_mask);
+            }
+            ReadFifoQueue(registers) => {
+                data.put_u16(u16_len(2 + registers.len() * 2));
+                data.put_u16(u16_len(registers.len()));
+                for r in registers {
+                    data.put_u16(r);
+                }
+            }
+            ReadDeviceIdentification(device_id) => {
+                data.put_u8(MEI_READ_DEVICE_ID);
+                data.put_u8(device_id.read_device_id_code.into());
+                data.put_u8(device_id.conformity_level.into());
+                data.put_u8(if device_id.more_follows { 0xFF } else { 0x00 });
+                data.put_u8(device_id.next_object.into());
+                data.put_u8(u8_len(device_id.objects.len()));
+                for (object, value) in device_id.objects {
+                    data.put_u8(object.into());
+                    data.put_u8(u8_len(value.len()));
+                    data.put_slice(&value);
+                }
+            }
             Custom(_, custom_data) => {
                 for d in custom_data {
                     data.put_u8(d);
@@ -182,7 +319,7 @@ impl TryFrom<Bytes> for Request {
                 let quantity = rdr.read_u16::<BigEndian>()?;
                 let byte_count = rdr.read_u8()?;
                 if bytes.len() < 6 + usize::from(byte_count) {
-                    return Err(Error::new(ErrorKind::InvalidData, "Invalid byte count"));
+                    return Err(protocol_violation("Invalid byte count"));
                 }
                 let x = &bytes[6..];
                 WriteMultipleCoils(address, unpack_coils(x, quantity))
@@ -198,7 +335,7 @@ impl TryFrom<Bytes> for Request {
                 let quantity = rdr.read_u16::<BigEndian>()?;
                 let byte_count = rdr.read_u8()?;
                 if bytes.len() < 6 + usize::from(byte_count) {
-                    return Err(Error::new(ErrorKind::InvalidData, "Invalid byte count"));
+                    return Err(protocol_violation("Invalid byte count"));
                 }
                 let mut data = Vec::with_capacity(quantity.into());
                 for _ in 0..quantity {
@@ -213,7 +350,7 @@ impl TryFrom<Bytes> for Request {
                 let write_quantity = rdr.read_u16::<BigEndian>()?;
                 let write_count = rdr.read_u8()?;
                 if bytes.len() < 10 + usize::from(write_count) {
-                    return Err(Error::new(ErrorKind::InvalidData, "Invalid byte count"));
+                    return Err(protocol_violation("Invalid byte count"));
                 }
                 let mut data = Vec::with_capacity(write_quantity.into());
                 for _ in 0..write_quantity {
@@ -221,12 +358,50 @@ impl TryFrom<Bytes> for Request {
                 }
                 ReadWriteMultipleRegisters(read_address, read_quantity, write_address, data)
             }
+            0x07 => ReadExceptionStatus,
+            0x0B => GetCommEventCounter,
+            0x0C => GetCommEventLog,
+            0x11 => ReportServerId,
+            0x08 => {
+                let sub_function = rdr.read_u16::<BigEndian>()?.into();
+                Diagnostics(sub_function, read_diagnostics_data(&mut rdr)?)
+            }
+            0x14 => {
+                let byte_count = rdr.read_u8()?;
+                if byte_count % 7 != 0 || bytes.len() < 2 + usize::from(byte_count) {
+                    return Err(protocol_violation("Invalid byte count"));
+                }
+                let mut sub_requests = Vec::with_capacity(usize::from(byte_count / 7));
+                for _ in 0..byte_count / 7 {
+                    read_file_record_ref_type(&mut rdr)?;
+                    sub_requests.push(ReadFileRecordSubRequest {
+                        file_number: rdr.read_u16::<BigEndian>()?,
+                        record_number: rdr.read_u16::<BigEndian>()?,
+                        record_length: rdr.read_u16::<BigEndian>()?,
+                    });
+                }
+                ReadFileRecord(sub_requests)
+            }
+            0x15 => WriteFileRecord(read_write_file_record_sub_requests(&bytes)?),
+            0x16 => MaskWriteRegister(
+                rdr.read_u16::<BigEndian>()?,
+                rdr.read_u16::<BigEndian>()?,
+                rdr.read_u16::<BigEndian>()?,
+            ),
+            0x18 => ReadFifoQueue(rdr.read_u16::<BigEndian>()?),
+            0x2B if bytes.get(1) == Some(&MEI_READ_DEVICE_ID) => {
+                rdr.set_position(2);
+                ReadDeviceIdentification(
+                    ReadDeviceIdCode::try_from(rdr.read_u8()?)?,
+                    rdr.read_u8()?.into(),
+                )
+            }
             fn_code if fn_code < 0x80 => Custom(fn_code, bytes[1..].into()),
             fn_code => {
-                return Err(Error::new(
-                    ErrorKind::InvalidData,
-                    format!("Invalid function code: 0x{:0>2X}", fn_code),
-                ));
+                return Err(protocol_violation(format!(
+                    "Invalid function code: 0x{:0>2X}",
+                    fn_code
+                )));
             }
         };
         Ok(req)
@@ -237,7 +412,7 @@ impl TryFrom<Bytes> for RequestPdu {
     type Error = Error;
 
     fn try_from(bytes: Bytes) -> Result<Self, Self::Error> {
-        let pdu = Request::try_from(bytes)?.into();
+        let pdu = Request::try_from(bytes).map_err(truncated_pdu)?.into();
         Ok(pdu)
     }
 }
@@ -303,6 +478,99 @@ impl TryFrom<Bytes> for Response {
                 }
                 ReadWriteMultipleRegisters(data)
             }
+            0x07 => ReadExceptionStatus(rdr.read_u8()?),
+            0x0B => GetCommEventCounter(rdr.read_u16::<BigEndian>()?, rdr.read_u16::<BigEndian>()?),
+            0x0C => {
+                let byte_count = rdr.read_u8()?;
+                if byte_count < 6 || bytes.len() < 2 + usize::from(byte_count) {
+                    return Err(protocol_violation("Invalid byte count"));
+                }
+                GetCommEventLog(CommEventLog {
+                    status: rdr.read_u16::<BigEndian>()?,
+                    event_count: rdr.read_u16::<BigEndian>()?,
+                    message_count: rdr.read_u16::<BigEndian>()?,
+                    events: bytes[8..2 + usize::from(byte_count)].into(),
+                })
+            }
+            0x11 => {
+                let byte_count = rdr.read_u8()?;
+                if byte_count < 2 || bytes.len() < 2 + usize::from(byte_count) {
+                    return Err(protocol_violation("Invalid byte count"));
+                }
+                let server_id = rdr.read_u8()?;
+                let run_indicator = rdr.read_u8()? != 0x00;
+                ReportServerId(
+                    server_id,
+                    run_indicator,
+                    bytes[4..2 + usize::from(byte_count)].into(),
+                )
+            }
+            0x08 => {
+                let sub_function = rdr.read_u16::<BigEndian>()?.into();
+                Diagnostics(sub_function, read_diagnostics_data(&mut rdr)?)
+            }
+            0x14 => {
+                let byte_count = rdr.read_u8()?;
+                let end = 2 + u64::from(byte_count);
+                if bytes.len() < 2 + usize::from(byte_count) {
+                    return Err(protocol_violation("Invalid byte count"));
+                }
+                let mut records = Vec::new();
+                while rdr.position() < end {
+                    let len = rdr.read_u8()?;
+                    if len % 2 != 1 || rdr.position() + u64::from(len) > end {
+                        return Err(protocol_violation("Invalid file response length"));
+                    }
+                    read_file_record_ref_type(&mut rdr)?;
+                    let mut record = Vec::with_capacity(usize::from(len / 2));
+                    for _ in 0..len / 2 {
+                        record.push(rdr.read_u16::<BigEndian>()?);
+                    }
+                    records.push(record);
+                }
+                ReadFileRecord(records)
+            }
+            0x15 => WriteFileRecord(read_write_file_record_sub_requests(&bytes)?),
+            0x16 => MaskWriteRegister(
+                rdr.read_u16::<BigEndian>()?,
+                rdr.read_u16::<BigEndian>()?,
+                rdr.read_u16::<BigEndian>()?,
+            ),
+            0x18 => {
+                let byte_count = rdr.read_u16::<BigEndian>()?;
+                let fifo_count = rdr.read_u16::<BigEndian>()?;
+                if usize::from(byte_count) != 2 + usize::from(fifo_count) * 2 {
+                    return Err(protocol_violation("Invalid byte count"));
+                }
+                let mut data = Vec::with_capacity(fifo_count.into());
+                for _ in 0..fifo_count {
+                    data.push(rdr.read_u16::<BigEndian>()?);
+                }
+                ReadFifoQueue(data)
+            }
+            0x2B if bytes.get(1) == Some(&MEI_READ_DEVICE_ID) => {
+                rdr.set_position(2);
+                let read_device_id_code = ReadDeviceIdCode::try_from(rdr.read_u8()?)?;
+                let conformity_level = ConformityLevel::try_from(rdr.read_u8()?)?;
+                let more_follows = rdr.read_u8()? != 0x00;
+                let next_object = rdr.read_u8()?.into();
+                let number_of_objects = rdr.read_u8()?;
+                let mut objects = BTreeMap::new();
+                for _ in 0..number_of_objects {
+                    let object = rdr.read_u8()?.into();
+                    let len = rdr.read_u8()?;
+                    let mut value = vec![0; len.into()];
+                    rdr.read_exact(&mut value)?;
+                    objects.insert(object, value);
+                }
+                ReadDeviceIdentification(DeviceIdentification {
+                    read_device_id_code,
+                    conformity_level,
+                    more_follows,
+                    next_object,
+                    objects,
+                })
+            }
             _ => Custom(fn_code, bytes[1..].into()),
         };
         Ok(rsp)
@@ -316,13 +584,10 @@ impl TryFrom<Bytes> for ExceptionResponse {
         let mut rdr = Cursor::new(&bytes);
         let fn_err_code = rdr.read_u8()?;
         if fn_err_code < 0x80 {
-            return Err(Error::new(
-                ErrorKind::InvalidData,
-                "Invalid exception function code",
-            ));
+            return Err(protocol_violation("Invalid exception function code"));
         }
         let function = fn_err_code - 0x80;
-        let exception = Exception::try_from(rdr.read_u8()?)?;
+        let exception = Exception::from(rdr.read_u8()?);
         Ok(ExceptionResponse {
             function,
             exception,
@@ -330,26 +595,44 @@ impl TryFrom<Bytes> for ExceptionResponse {
     }
 }
 
-impl TryFrom<u8> for Exception {
+impl TryFrom<u8> for ReadDeviceIdCode {
     type Error = Error;
 
     fn try_from(code: u8) -> Result<Self, Self::Error> {
-        use crate::frame::Exception::*;
-        let ex = match code {
-            0x01 => IllegalFunction,
-            0x02 => IllegalDataAddress,
-            0x03 => IllegalDataValue,
-            0x04 => ServerDeviceFailure,
-            0x05 => Acknowledge,
-            0x06 => ServerDeviceBusy,
-            0x08 => MemoryParityError,
-            0x0A => GatewayPathUnavailable,
-            0x0B => GatewayTargetDevice,
+        use crate::frame::ReadDeviceIdCode::*;
+        let code = match code {
+            0x01 => Basic,
+            0x02 => Regular,
+            0x03 => Extended,
+            0x04 => Specific,
             _ => {
-                return Err(Error::new(ErrorKind::InvalidData, "Invalid exception code"));
+                return Err(protocol_violation(format!(
+                    "Invalid read device id code: 0x{:0>2X}",
+                    code
+                )));
             }
         };
-        Ok(ex)
+        Ok(code)
+    }
+}
+
+impl TryFrom<u8> for ConformityLevel {
+    type Error = Error;
+
+    fn try_from(level: u8) -> Result<Self, Self::Error> {
+        let category = match ReadDeviceIdCode::try_from(level & 0x7F) {
+            Ok(ReadDeviceIdCode::Specific) | Err(_) => {
+                return Err(protocol_violation(format!(
+                    "Invalid conformity level: 0x{:0>2X}",
+                    level
+                )));
+            }
+            Ok(category) => category,
+        };
+        Ok(ConformityLevel {
+            category,
+            individual_access: level & 0x80 != 0,
+        })
     }
 }
 
@@ -357,16 +640,109 @@ impl TryFrom<Bytes> for ResponsePdu {
     type Error = Error;
 
     fn try_from(bytes: Bytes) -> Result<Self, Self::Error> {
-        let fn_code = Cursor::new(&bytes).read_u8()?;
+        let fn_code = Cursor::new(&bytes).read_u8().map_err(truncated_pdu)?;
         let pdu = if fn_code < 0x80 || fn_code == 0xfe {
-            Response::try_from(bytes)?.into()
+            Response::try_from(bytes).map_err(truncated_pdu)?.into()
         } else {
-            ExceptionResponse::try_from(bytes)?.into()
+            ExceptionResponse::try_from(bytes)
+                .map_err(truncated_pdu)?
+                .into()
         };
         Ok(pdu)
     }
 }
 
+/// Reading beyond the end of a PDU fails if it has been truncated,
+/// which violates the protocol.
+fn truncated_pdu(err: Error) -> Error {
+    if err.kind() == io::ErrorKind::UnexpectedEof {
+        return protocol_violation("Truncated PDU");
+    }
+    err
+}
+
+fn read_file_record_ref_type(rdr: &mut Cursor<&Bytes>) -> io::Result<()> {
+    let ref_type = rdr.read_u8()?;
+    if ref_type != FILE_RECORD_REF_TYPE {
+        return Err(protocol_violation(format!(
+            "Invalid reference type: 0x{:0>2X}",
+            ref_type
+        )));
+    }
+    Ok(())
+}
+
+/// Read the data of a Diagnostics request or response, which fills
+/// the remainder of the PDU after the function code and the sub-function.
+fn read_diagnostics_data(rdr: &mut Cursor<&Bytes>) -> io::Result<Vec<Word>> {
+    let remaining = rdr.get_ref().len().saturating_sub(3);
+    if !remaining.is_multiple_of(2) {
+        return Err(protocol_violation("Invalid diagnostics data"));
+    }
+    let mut data = Vec::with_capacity(remaining / 2);
+    for _ in 0..remaining / 2 {
+        data.push(rdr.read_u16::<BigEndian>()?);
+    }
+    Ok(data)
+}
+
+fn write_file_record_sub_requests_len(sub_requests: &[WriteFileRecordSubRequest]) -> usize {
+    sub_requests
+        .iter()
+        .map(|sub_request| 7 + sub_request.record_data.len() * 2)
+        .sum()
+}
+
+/// Encode the byte count and all sub-requests of a Write File Record PDU.
+fn put_write_file_record_sub_requests(
+    data: &mut BytesMut,
+    sub_requests: &[WriteFileRecordSubRequest],
+) {
+    data.put_u8(u8_len(write_file_record_sub_requests_len(sub_requests)));
+    for sub_request in sub_requests {
+        data.put_u8(FILE_RECORD_REF_TYPE);
+        data.put_u16(sub_request.file_number);
+        data.put_u16(sub_request.record_number);
+        data.put_u16(u16_len(sub_request.record_data.len()));
+        for r in &sub_request.record_data {
+            data.put_u16(*r);
+        }
+    }
+}
+
+/// Decode the byte count and all sub-requests of a Write File Record PDU.
+fn read_write_file_record_sub_requests(
+    bytes: &Bytes,
+) -> io::Result<Vec<WriteFileRecordSubRequest>> {
+    let mut rdr = Cursor::new(bytes);
+    rdr.set_position(1);
+    let byte_count = rdr.read_u8()?;
+    let end = 2 + u64::from(byte_count);
+    if bytes.len() < 2 + usize::from(byte_count) {
+        return Err(protocol_violation("Invalid byte count"));
+    }
+    let mut sub_requests = Vec::new();
+    while rdr.position() < end {
+        read_file_record_ref_type(&mut rdr)?;
+        let file_number = rdr.read_u16::<BigEndian>()?;
+        let record_number = rdr.read_u16::<BigEndian>()?;
+        let record_length = rdr.read_u16::<BigEndian>()?;
+        if rdr.position() + u64::from(record_length) * 2 > end {
+            return Err(protocol_violation("Invalid record length"));
+        }
+        let mut record_data = Vec::with_capacity(record_length.into());
+        for _ in 0..record_length {
+            record_data.push(rdr.read_u16::<BigEndian>()?);
+        }
+        sub_requests.push(WriteFileRecordSubRequest {
+            file_number,
+            record_number,
+            record_data,
+        });
+    }
+    Ok(sub_requests)
+}
+
 fn bool_to_coil(state: bool) -> u16 {
     if state {
         0xFF00
@@ -379,12 +755,12 @@ fn coil_to_bool(coil: u16) -> io::Result<bool> {
     match coil {
         0xFF00 => Ok(true),
         0x0000 => Ok(false),
-        _ => Err(Error::new(ErrorKind::InvalidData, "Invalid coil value: {}")),
+        _ => Err(protocol_violation("Invalid coil value: {}")),
     }
 }
 
 fn packed_coils_len(bitcount: usize) -> usize {
-    (bitcount + 7) / 8
+    bitcount.div_ceil(8)
 }
 
 fn pack_coils(coils: &[Coil]) -> Vec<u8> {
@@ -405,7 +781,7 @@ fn unpack_coils(bytes: &[u8], count: u16) -> Vec<Coil> {
     res
 }
 
-fn req_to_fn_code(req: &Request) -> u8 {
+pub(crate) fn req_to_fn_code(req: &Request) -> u8 {
     use crate::frame::Request::*;
     match *req {
         ReadCoils(_, _) => 0x01,
@@ -417,12 +793,22 @@ fn req_to_fn_code(req: &Request) -> u8 {
         WriteSingleRegister(_, _) => 0x06,
         WriteMultipleRegisters(_, _) => 0x10,
         ReadWriteMultipleRegisters(_, _, _, _) => 0x17,
+        ReadExceptionStatus => 0x07,
+        Diagnostics(_, _) => 0x08,
+        GetCommEventCounter => 0x0B,
+        GetCommEventLog => 0x0C,
+        ReportServerId => 0x11,
+        ReadFileRecord(_) => 0x14,
+        WriteFileRecord(_) => 0x15,
+        MaskWriteRegister(_, _, _) => 0x16,
+        ReadFifoQueue(_) => 0x18,
+        ReadDeviceIdentification(_, _) => 0x2B,
         Custom(code, _) => code,
         Disconnect => unreachable!(),
     }
 }
 
-fn rsp_to_fn_code(rsp: &Response) -> u8 {
+pub(crate) fn rsp_to_fn_code(rsp: &Response) -> u8 {
     use crate::frame::Response::*;
     match *rsp {
         ReadCoils(_) => 0x01,
@@ -434,6 +820,16 @@ fn rsp_to_fn_code(rsp: &Response) -> u8 {
         WriteSingleRegister(_, _) => 0x06,
         WriteMultipleRegisters(_, _) => 0x10,
         ReadWriteMultipleRegisters(_) => 0x17,
+        ReadExceptionStatus(_) => 0x07,
+        Diagnostics(_, _) => 0x08,
+        GetCommEventCounter(_, _) => 0x0B,
+        GetCommEventLog(_) => 0x0C,
+        ReportServerId(_, _, _) => 0x11,
+        ReadFileRecord(_) => 0x14,
+        WriteFileRecord(_) => 0x15,
+        MaskWriteRegister(_, _, _) => 0x16,
+        ReadFifoQueue(_) => 0x18,
+        ReadDeviceIdentification(_) => 0x2B,
         Custom(code, _) => code,
     }
 }
@@ -447,9 +843,16 @@ fn request_byte_count(req: &Request) -> usize {
         | ReadHoldingRegisters(_, _)
         | WriteSingleRegister(_, _)
         | WriteSingleCoil(_, _) => 5,
+        Diagnostics(_, ref data) => 3 + data.len() * 2,
         WriteMultipleCoils(_, ref coils) => 6 + packed_coils_len(coils.len()),
         WriteMultipleRegisters(_, ref data) => 6 + data.len() * 2,
         ReadWriteMultipleRegisters(_, _, _, ref data) => 10 + data.len() * 2,
+        ReadExceptionStatus | GetCommEventCounter | GetCommEventLog | ReportServerId => 1,
+        ReadFifoQueue(_) => 3,
+        ReadDeviceIdentification(_, _) => 4,
+        MaskWriteRegister(_, _, _) => 7,
+        ReadFileRecord(ref sub_requests) => 2 + sub_requests.len() * 7,
+        WriteFileRecord(ref sub_requests) => 2 + write_file_record_sub_requests_len(sub_requests),
         Custom(_, ref data) => 1 + data.len(),
         Disconnect => unreachable!(),
     }
@@ -463,9 +866,30 @@ fn response_byte_count(rsp: &Response) -> usize {
         | WriteMultipleCoils(_, _)
         | WriteMultipleRegisters(_, _)
         | WriteSingleRegister(_, _) => 5,
+        Diagnostics(_, ref data) => 3 + data.len() * 2,
         ReadInputRegisters(ref data)
         | ReadHoldingRegisters(ref data)
         | ReadWriteMultipleRegisters(ref data) => 2 + data.len() * 2,
+        ReadExceptionStatus(_) => 2,
+        GetCommEventCounter(_, _) => 5,
+        GetCommEventLog(ref log) => 8 + log.events.len(),
+        ReportServerId(_, _, ref additional_data) => 4 + additional_data.len(),
+        MaskWriteRegister(_, _, _) => 7,
+        ReadFifoQueue(ref data) => 5 + data.len() * 2,
+        ReadFileRecord(ref records) => {
+            2 + records
+                .iter()
+                .map(|record| 2 + record.len() * 2)
+                .sum::<usize>()
+        }
+        WriteFileRecord(ref sub_requests) => 2 + write_file_record_sub_requests_len(sub_requests),
+        ReadDeviceIdentification(ref device_id) => {
+            7 + device_id
+                .objects
+                .values()
+                .map(|value| 2 + value.len())
+                .sum::<usize>()
+        }
         Custom(_, ref data) => 1 + data.len(),
     }
 }
@@ -571,6 +995,80 @@ mod tests {
         );
     }
 
+    #[test]
+    fn device_id_object_round_trip() {
+        assert_eq!(ReservedObjectId::new(0x06), None);
+        assert_eq!(ReservedObjectId::new(0x80), None);
+        assert_eq!(PrivateObjectId::new(0x7F), None);
+        assert_eq!(PrivateObjectId::new(0x80).unwrap().id(), 0x80);
+        for id in 0..=u8::MAX {
+            let object = DeviceIdObject::from(id);
+            assert_eq!(u8::from(object), id);
+            let expected = ReservedObjectId::new(id)
+                .map(DeviceIdObject::Reserved)
+                .or_else(|| PrivateObjectId::new(id).map(DeviceIdObject::Private));
+            assert_eq!(
+                expected,
+                matches!(
+                    object,
+                    DeviceIdObject::Reserved(_) | DeviceIdObject::Private(_)
+                )
+                .then_some(object)
+            );
+        }
+    }
+
+    #[test]
+    fn reserved_diagnostics_sub_function_round_trip() {
+        assert_eq!(ReservedSubFunction::new(0x0000), None);
+        assert_eq!(ReservedSubFunction::new(0x0013).unwrap().code(), 0x0013);
+        for code in 0..=0x0100 {
+            let sub_function = DiagnosticsSubFunction::from(code);
+            assert_eq!(u16::from(sub_function), code);
+            assert_eq!(
+                ReservedSubFunction::new(code).map(DiagnosticsSubFunction::Reserved),
+                matches!(sub_function, DiagnosticsSubFunction::Reserved(_)).then_some(sub_function)
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_exception_round_trip() {
+        for code in [0x07, 0x09, 0x0C, 0xFF] {
+            let bytes = Bytes::from(vec![0x83, code]);
+            let ResponsePdu(Err(rsp)) = ResponsePdu::try_from(bytes.clone()).unwrap() else {
+                panic!("unexpected response");
+            };
+            assert_eq!(rsp.function, 0x03);
+            assert_eq!(u8::from(rsp.exception), code);
+            assert_eq!(Bytes::from(rsp), bytes);
+        }
+        assert_eq!(Exception::from(0x07), Exception::NegativeAcknowledge);
+        let Exception::Custom(custom) = Exception::from(0x42) else {
+            panic!("unexpected exception");
+        };
+        assert_eq!(custom.code(), 0x42);
+
+        // Known exception codes are never custom
+        for code in 0..=u8::MAX {
+            let exception = Exception::from(code);
+            assert_eq!(u8::from(exception), code);
+            assert_eq!(
+                matches!(exception, Exception::Custom(_)),
+                !matches!(code, 0x01..=0x08 | 0x0A | 0x0B)
+            );
+        }
+
+        assert_eq!(
+            ExceptionResponse {
+                function: 0x41,
+                exception: Exception::from(0x42),
+            }
+            .to_string(),
+            "Modbus function 65: Unknown exception (0x42)"
+        );
+    }
+
     #[test]
     fn pdu_into_bytes() {
         let req_pdu: Bytes = Request::ReadCoils(0x01, 5).into();
@@ -744,6 +1242,92 @@ mod tests {
             assert_eq!(bytes[13], 0x12);
         }
 
+        #[test]
+        fn serial_line_management() {
+            let bytes: Bytes = Request::ReadExceptionStatus.into();
+            assert_eq!(&bytes[..], &[0x07]);
+            let bytes: Bytes = Request::GetCommEventCounter.into();
+            assert_eq!(&bytes[..], &[0x0B]);
+            let bytes: Bytes = Request::GetCommEventLog.into();
+            assert_eq!(&bytes[..], &[0x0C]);
+            let bytes: Bytes = Request::ReportServerId.into();
+            assert_eq!(&bytes[..], &[0x11]);
+        }
+
+        #[test]
+        fn mask_write_register() {
+            let bytes: Bytes = Request::MaskWriteRegister(0x04, 0xF2, 0x25).into();
+            assert_eq!(&bytes[..], &[0x16, 0x00, 0x04, 0x00, 0xF2, 0x00, 0x25]);
+        }
+
+        #[test]
+        fn read_fifo_queue() {
+            let bytes: Bytes = Request::ReadFifoQueue(0x04DE).into();
+            assert_eq!(&bytes[..], &[0x18, 0x04, 0xDE]);
+        }
+
+        #[test]
+        fn read_file_record() {
+            let bytes: Bytes = Request::ReadFileRecord(vec![
+                ReadFileRecordSubRequest {
+                    file_number: 4,
+                    record_number: 1,
+                    record_length: 2,
+                },
+                ReadFileRecordSubRequest {
+                    file_number: 3,
+                    record_number: 9,
+                    record_length: 2,
+                },
+            ])
+            .into();
+            assert_eq!(
+                &bytes[..],
+                &[
+                    0x14, 0x0E, 0x06, 0x00, 0x04, 0x00, 0x01, 0x00, 0x02, 0x06, 0x00, 0x03, 0x00,
+                    0x09, 0x00, 0x02
+                ]
+            );
+        }
+
+        #[test]
+        fn write_file_record() {
+            let bytes: Bytes = Request::WriteFileRecord(vec![WriteFileRecordSubRequest {
+                file_number: 4,
+                record_number: 7,
+                record_data: vec![0x06AF, 0x04BE, 0x100D],
+            }])
+            .into();
+            assert_eq!(
+                &bytes[..],
+                &[
+                    0x15, 0x0D, 0x06, 0x00, 0x04, 0x00, 0x07, 0x00, 0x03, 0x06, 0xAF, 0x04, 0xBE,
+                    0x10, 0x0D
+                ]
+            );
+        }
+
+        #[test]
+        fn diagnostics() {
+            let bytes: Bytes =
+                Request::Diagnostics(DiagnosticsSubFunction::ReturnQueryData, vec![0xA537]).into();
+            assert_eq!(&bytes[..], &[0x08, 0x00, 0x00, 0xA5, 0x37]);
+
+            let bytes: Bytes = Request::Diagnostics(
+                DiagnosticsSubFunction::ReturnQueryData,
+                vec![0xA537, 0x1234],
+            )
+            .into();
+            assert_eq!(&bytes[..], &[0x08, 0x00, 0x00, 0xA5, 0x37, 0x12, 0x34]);
+        }
+
+        #[test]
+        fn read_device_identification() {
+            let bytes: Bytes =
+                Request::ReadDeviceIdentification(ReadDeviceIdCode::Regular, 0x03.into()).into();
+            assert_eq!(&bytes[..], &[0x2B, 0x0E, 0x02, 0x03]);
+        }
+
         #[test]
         fn custom() {
             let bytes: Bytes = Request::Custom(0x55, vec![0xCC, 0x88, 0xAA, 0xFF]).into();
@@ -864,6 +1448,137 @@ mod tests {
             );
         }
 
+        #[test]
+        fn serial_line_management() {
+            let req = Request::try_from(Bytes::from(vec![0x07])).unwrap();
+            assert_eq!(req, Request::ReadExceptionStatus);
+            let req = Request::try_from(Bytes::from(vec![0x0B])).unwrap();
+            assert_eq!(req, Request::GetCommEventCounter);
+            let req = Request::try_from(Bytes::from(vec![0x0C])).unwrap();
+            assert_eq!(req, Request::GetCommEventLog);
+            let req = Request::try_from(Bytes::from(vec![0x11])).unwrap();
+            assert_eq!(req, Request::ReportServerId);
+        }
+
+        #[test]
+        fn mask_write_register() {
+            let bytes = Bytes::from(vec![0x16, 0x00, 0x04, 0x00, 0xF2, 0x00, 0x25]);
+            let req = Request::try_from(bytes).unwrap();
+            assert_eq!(req, Request::MaskWriteRegister(0x04, 0xF2, 0x25));
+        }
+
+        #[test]
+        fn read_fifo_queue() {
+            let req = Request::try_from(Bytes::from(vec![0x18, 0x04, 0xDE])).unwrap();
+            assert_eq!(req, Request::ReadFifoQueue(0x04DE));
+        }
+
+        #[test]
+        fn read_file_record() {
+            let bytes = Bytes::from(vec![
+                0x14, 0x0E, 0x06, 0x00, 0x04, 0x00, 0x01, 0x00, 0x02, 0x06, 0x00, 0x03, 0x00, 0x09,
+                0x00, 0x02,
+            ]);
+            let req = Request::try_from(bytes).unwrap();
+            assert_eq!(
+                req,
+                Request::ReadFileRecord(vec![
+                    ReadFileRecordSubRequest {
+                        file_number: 4,
+                        record_number: 1,
+                        record_length: 2,
+                    },
+                    ReadFileRecordSubRequest {
+                        file_number: 3,
+                        record_number: 9,
+                        record_length: 2,
+                    },
+                ])
+            );
+
+            // Invalid reference type
+            let bytes = Bytes::from(vec![0x14, 0x07, 0x07, 0x00, 0x04, 0x00, 0x01, 0x00, 0x02]);
+            assert!(Request::try_from(bytes).is_err());
+
+            // Byte count is not a multiple of the sub-request length
+            let bytes = Bytes::from(vec![
+                0x14, 0x08, 0x06, 0x00, 0x04, 0x00, 0x01, 0x00, 0x02, 0x06,
+            ]);
+            assert!(Request::try_from(bytes).is_err());
+        }
+
+        #[test]
+        fn write_file_record() {
+            let bytes = Bytes::from(vec![
+                0x15, 0x0D, 0x06, 0x00, 0x04, 0x00, 0x07, 0x00, 0x03, 0x06, 0xAF, 0x04, 0xBE, 0x10,
+                0x0D,
+            ]);
+            let req = Request::try_from(bytes).unwrap();
+            assert_eq!(
+                req,
+                Request::WriteFileRecord(vec![WriteFileRecordSubRequest {
+                    file_number: 4,
+                    record_number: 7,
+                    record_data: vec![0x06AF, 0x04BE, 0x100D],
+                }])
+            );
+
+            // Record length exceeds the byte count
+            let bytes = Bytes::from(vec![
+                0x15, 0x0B, 0x06, 0x00, 0x04, 0x00, 0x07, 0x00, 0x03, 0x06, 0xAF, 0x04, 0xBE,
+            ]);
+            assert!(Request::try_from(bytes).is_err());
+        }
+
+        #[test]
+        fn diagnostics() {
+            let bytes = Bytes::from(vec![0x08, 0x00, 0x0B, 0x00, 0x00]);
+            let req = Request::try_from(bytes).unwrap();
+            assert_eq!(
+                req,
+                Request::Diagnostics(DiagnosticsSubFunction::ReturnBusMessageCount, vec![0x0000])
+            );
+
+            let bytes = Bytes::from(vec![0x08, 0x00, 0x13, 0x00, 0x00]);
+            let req = Request::try_from(bytes).unwrap();
+            assert_eq!(
+                req,
+                Request::Diagnostics(DiagnosticsSubFunction::from(0x13), vec![0x0000])
+            );
+
+            let bytes = Bytes::from(vec![0x08, 0x00, 0x00, 0xA5, 0x37, 0x12, 0x34]);
+            let req = Request::try_from(bytes).unwrap();
+            assert_eq!(
+                req,
+                Request::Diagnostics(
+                    DiagnosticsSubFunction::ReturnQueryData,
+                    vec![0xA537, 0x1234]
+                )
+            );
+
+            assert!(Request::try_from(Bytes::from(vec![0x08, 0x00, 0x0B, 0x00])).is_err());
+        }
+
+        #[test]
+        fn read_device_identification() {
+            let bytes = Bytes::from(vec![0x2B, 0x0E, 0x01, 0x00]);
+            let req = Request::try_from(bytes).unwrap();
+            assert_eq!(
+                req,
+                Request::ReadDeviceIdentification(
+                    ReadDeviceIdCode::Basic,
+                    DeviceIdObject::VendorName
+                )
+            );
+
+            assert!(Request::try_from(Bytes::from(vec![0x2B, 0x0E, 0x05, 0x00])).is_err());
+
+            // Other MEI types are not supported
+            let bytes = Bytes::from(vec![0x2B, 0x0D, 0x01]);
+            let req = Request::try_from(bytes).unwrap();
+            assert_eq!(req, Request::Custom(0x2B, vec![0x0D, 0x01]));
+        }
+
         #[test]
         fn custom() {
             let bytes = Bytes::from(vec![0x55, 0xCC, 0x88, 0xAA, 0xFF]);
@@ -965,6 +1680,120 @@ mod tests {
             assert_eq!(bytes[3], 0x34);
         }
 
+        #[test]
+        fn read_exception_status() {
+            let bytes: Bytes = Response::ReadExceptionStatus(0x6D).into();
+            assert_eq!(&bytes[..], &[0x07, 0x6D]);
+        }
+
+        #[test]
+        fn get_comm_event_counter() {
+            let bytes: Bytes = Response::GetCommEventCounter(0xFFFF, 0x0108).into();
+            assert_eq!(&bytes[..], &[0x0B, 0xFF, 0xFF, 0x01, 0x08]);
+        }
+
+        #[test]
+        fn get_comm_event_log() {
+            let bytes: Bytes = Response::GetCommEventLog(CommEventLog {
+                status: 0x0000,
+                event_count: 0x0108,
+                message_count: 0x0121,
+                events: vec![0x20, 0x00],
+            })
+            .into();
+            assert_eq!(
+                &bytes[..],
+                &[0x0C, 0x08, 0x00, 0x00, 0x01, 0x08, 0x01, 0x21, 0x20, 0x00]
+            );
+        }
+
+        #[test]
+        fn report_server_id() {
+            let bytes: Bytes = Response::ReportServerId(0x42, true, vec![0x10, 0x20]).into();
+            assert_eq!(&bytes[..], &[0x11, 0x04, 0x42, 0xFF, 0x10, 0x20]);
+        }
+
+        #[test]
+        fn mask_write_register() {
+            let bytes: Bytes = Response::MaskWriteRegister(0x04, 0xF2, 0x25).into();
+            assert_eq!(&bytes[..], &[0x16, 0x00, 0x04, 0x00, 0xF2, 0x00, 0x25]);
+        }
+
+        #[test]
+        fn read_fifo_queue() {
+            let bytes: Bytes = Response::ReadFifoQueue(vec![0x01B8, 0x1284]).into();
+            assert_eq!(
+                &bytes[..],
+                &[0x18, 0x00, 0x06, 0x00, 0x02, 0x01, 0xB8, 0x12, 0x84]
+            );
+        }
+
+        #[test]
+        fn read_file_record() {
+            let bytes: Bytes =
+                Response::ReadFileRecord(vec![vec![0x0DFE, 0x0020], vec![0x33CD, 0x0040]]).into();
+            assert_eq!(
+                &bytes[..],
+                &[
+                    0x14, 0x0C, 0x05, 0x06, 0x0D, 0xFE, 0x00, 0x20, 0x05, 0x06, 0x33, 0xCD, 0x00,
+                    0x40
+                ]
+            );
+        }
+
+        #[test]
+        fn write_file_record() {
+            let bytes: Bytes = Response::WriteFileRecord(vec![WriteFileRecordSubRequest {
+                file_number: 4,
+                record_number: 7,
+                record_data: vec![0x06AF, 0x04BE, 0x100D],
+            }])
+            .into();
+            assert_eq!(
+                &bytes[..],
+                &[
+                    0x15, 0x0D, 0x06, 0x00, 0x04, 0x00, 0x07, 0x00, 0x03, 0x06, 0xAF, 0x04, 0xBE,
+                    0x10, 0x0D
+                ]
+            );
+        }
+
+        #[test]
+        fn diagnostics() {
+            let bytes: Bytes = Response::Diagnostics(
+                DiagnosticsSubFunction::ReturnServerMessageCount,
+                vec![0x0102],
+            )
+            .into();
+            assert_eq!(&bytes[..], &[0x08, 0x00, 0x0E, 0x01, 0x02]);
+        }
+
+        #[test]
+        fn read_device_identification() {
+            let bytes: Bytes = Response::ReadDeviceIdentification(DeviceIdentification {
+                read_device_id_code: ReadDeviceIdCode::Basic,
+                conformity_level: ConformityLevel {
+                    category: ReadDeviceIdCode::Regular,
+                    individual_access: true,
+                },
+                more_follows: true,
+                next_object: DeviceIdObject::MajorMinorRevision,
+                objects: [
+                    (DeviceIdObject::VendorName, b"Foo".to_vec()),
+                    (DeviceIdObject::ProductCode, b"B4".to_vec()),
+                ]
+                .into(),
+            })
+            .into();
+            assert_eq!(
+                &bytes[..],
+                &[
+                    0x2B, 0x0E, 0x01, 0x82, 0xFF, 0x02, 0x02, 0x00, 0x03, b'F', b'o', b'o', 0x01,
+                    0x02, b'B', b'4'
+                ]
+            );
+        }
+
         #[test]
         fn custom() {
             let bytes: Bytes = Response::Custom(0x55, vec![0xCC, 0x88, 0xAA, 0xFF]).into();
@@ -1078,6 +1907,158 @@ mod tests {
             assert_eq!(rsp, Response::ReadWriteMultipleRegisters(vec![0x1234]));
         }
 
+        #[test]
+        fn read_exception_status() {
+            let rsp = Response::try_from(Bytes::from(vec![0x07, 0x6D])).unwrap();
+            assert_eq!(rsp, Response::ReadExceptionStatus(0x6D));
+        }
+
+        #[test]
+        fn get_comm_event_counter() {
+            let bytes = Bytes::from(vec![0x0B, 0xFF, 0xFF, 0x01, 0x08]);
+            let rsp = Response::try_from(bytes).unwrap();
+            assert_eq!(rsp, Response::GetCommEventCounter(0xFFFF, 0x0108));
+        }
+
+        #[test]
+        fn get_comm_event_log() {
+            let bytes = Bytes::from(vec![
+                0x0C, 0x08, 0x00, 0x00, 0x01, 0x08, 0x01, 0x21, 0x20, 0x00,
+            ]);
+            let rsp = Response::try_from(bytes).unwrap();
+            assert_eq!(
+                rsp,
+                Response::GetCommEventLog(CommEventLog {
+                    status: 0x0000,
+                    event_count: 0x0108,
+                    message_count: 0x0121,
+                    events: vec![0x20, 0x00],
+                })
+            );
+
+            // Byte count exceeds data
+            let bytes = Bytes::from(vec![0x0C, 0x08, 0x00, 0x00, 0x01, 0x08, 0x01, 0x21]);
+            assert!(Response::try_from(bytes).is_err());
+        }
+
+        #[test]
+        fn report_server_id() {
+            let bytes = Bytes::from(vec![0x11, 0x04, 0x42, 0xFF, 0x10, 0x20]);
+            let rsp = Response::try_from(bytes).unwrap();
+            assert_eq!(rsp, Response::ReportServerId(0x42, true, vec![0x10, 0x20]));
+
+            let bytes = Bytes::from(vec![0x11, 0x02, 0x42, 0x00]);
+            let rsp = Response::try_from(bytes).unwrap();
+            assert_eq!(rsp, Response::ReportServerId(0x42, false, vec![]));
+
+            assert!(Response::try_from(Bytes::from(vec![0x11, 0x01, 0x42])).is_err());
+        }
+
+        #[test]
+        fn mask_write_register() {
+            let bytes = Bytes::from(vec![0x16, 0x00, 0x04, 0x00, 0xF2, 0x00, 0x25]);
+            let rsp = Response::try_from(bytes).unwrap();
+            assert_eq!(rsp, Response::MaskWriteRegister(0x04, 0xF2, 0x25));
+        }
+
+        #[test]
+        fn read_fifo_queue() {
+            let bytes = Bytes::from(vec![0x18, 0x00, 0x06, 0x00, 0x02, 0x01, 0xB8, 0x12, 0x84]);
+            let rsp = Response::try_from(bytes).unwrap();
+            assert_eq!(rsp, Response::ReadFifoQueue(vec![0x01B8, 0x1284]));
+
+            // Byte count does not match FIFO count
+            let bytes = Bytes::from(vec![0x18, 0x00, 0x04, 0x00, 0x02, 0x01, 0xB8, 0x12, 0x84]);
+            assert!(Response::try_from(bytes).is_err());
+        }
+
+        #[test]
+        fn read_file_record() {
+            let bytes = Bytes::from(vec![
+                0x14, 0x0C, 0x05, 0x06, 0x0D, 0xFE, 0x00, 0x20, 0x05, 0x06, 0x33, 0xCD, 0x00, 0x40,
+            ]);
+            let rsp = Response::try_from(bytes).unwrap();
+            assert_eq!(
+                rsp,
+                Response::ReadFileRecord(vec![vec![0x0DFE, 0x0020], vec![0x33CD, 0x0040]])
+            );
+
+            // File response length exceeds the byte count
+            let bytes = Bytes::from(vec![0x14, 0x06, 0x07, 0x06, 0x0D, 0xFE, 0x00, 0x20]);
+            assert!(Response::try_from(bytes).is_err());
+        }
+
+        #[test]
+        fn write_file_record() {
+            let bytes = Bytes::from(vec![
+                0x15, 0x0D, 0x06, 0x00, 0x04, 0x00, 0x07, 0x00, 0x03, 0x06, 0xAF, 0x04, 0xBE, 0x10,
+                0x0D,
+            ]);
+            let rsp = Response::try_from(bytes).unwrap();
+            assert_eq!(
+                rsp,
+                Response::WriteFileRecord(vec![WriteFileRecordSubRequest {
+                    file_number: 4,
+                    record_number: 7,
+                    record_data: vec![0x06AF, 0x04BE, 0x100D],
+                }])
+            );
+        }
+
+        #[test]
+        fn diagnostics() {
+            let bytes = Bytes::from(vec![0x08, 0x00, 0x02, 0x80, 0x01]);
+            let rsp = Response::try_from(bytes).unwrap();
+            assert_eq!(
+                rsp,
+                Response::Diagnostics(
+                    DiagnosticsSubFunction::ReturnDiagnosticRegister,
+                    vec![0x8001]
+                )
+            );
+
+            let bytes = Bytes::from(vec![0x08, 0x00, 0x00, 0xA5, 0x37, 0x12, 0x34]);
+            let rsp = Response::try_from(bytes).unwrap();
+            assert_eq!(
+                rsp,
+                Response::Diagnostics(
+                    DiagnosticsSubFunction::ReturnQueryData,
+                    vec![0xA537, 0x1234]
+                )
+            );
+        }
+
+        #[test]
+        fn read_device_identification() {
+            let bytes = Bytes::from(vec![
+                0x2B, 0x0E, 0x04, 0x83, 0x00, 0x00, 0x01, 0x80, 0x02, 0xCA, 0xFE,
+            ]);
+            let rsp = Response::try_from(bytes).unwrap();
+            assert_eq!(
+                rsp,
+                Response::ReadDeviceIdentification(DeviceIdentification {
+                    read_device_id_code: ReadDeviceIdCode::Specific,
+                    conformity_level: ConformityLevel {
+                        category: ReadDeviceIdCode::Extended,
+                        individual_access: true,
+                    },
+                    more_follows: false,
+                    next_object: DeviceIdObject::VendorName,
+                    objects: [(DeviceIdObject::from(0x80), vec![0xCA, 0xFE])].into(),
+                })
+            );
+
+            // Truncated object value
+            let bytes = Bytes::from(vec![
+                0x2B, 0x0E, 0x04, 0x83, 0x00, 0x00, 0x01, 0x80, 0x02, 0xCA,
+            ]);
+            assert!(Response::try_from(bytes).is_err());
+
+            // Invalid conformity level
+            let bytes = Bytes::from(vec![0x2B, 0x0E, 0x01, 0x04, 0x00, 0x00, 0x00]);
+            assert!(Response::try_from(bytes).is_err());
+        }
+
         #[test]
         fn custom() {
             let bytes = Bytes::from(vec![0x55, 0xCC, 0x88, 0xAA, 0xFF]);
@@ -9,24 +9,91 @@ use byteorder::BigEndian;
 use bytes::{Buf, BufMut, Bytes, BytesMut};
 use log::{debug, error, warn};
 use smallvec::SmallVec;
-use std::io::{Cursor, Error, ErrorKind, Result};
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{Cursor, Error, ErrorKind, Result},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
 use tokio_util::codec::{Decoder, Encoder};
 
+mod timing;
+
+pub use self::timing::FrameTiming;
+pub(crate) use self::timing::{FrameBoundaries, FrameBoundary, SilenceDelimited};
+
 // [MODBUS over Serial Line Specification and Implementation Guide V1.02](http://modbus.org/docs/Modbus_over_serial_line_V1_02.pdf), page 13
 // "The maximum size of a MODBUS RTU frame is 256 bytes."
 const MAX_FRAME_LEN: usize = 256;
 
 type DroppedBytes = SmallVec<[u8; MAX_FRAME_LEN]>;
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Default)]
+struct Counters {
+    crc_errors: AtomicU64,
+    dropped_bytes: AtomicU64,
+    frames_given_up: AtomicU64,
+    malformed_pdus: AtomicU64,
+}
+
+/// Counters of bus errors that have been detected while decoding RTU frames.
+///
+/// All clones share the same counters, i.e. a clone can be used for
+/// monitoring the bus while the client or server is running.
+#[derive(Debug, Clone, Default)]
+pub struct ErrorCounters(Arc<Counters>);
+
+impl ErrorCounters {
+    /// The number of frames with an invalid CRC.
+    #[must_use]
+    pub fn crc_errors(&self) -> u64 {
+        self.0.crc_errors.load(Ordering::Relaxed)
+    }
+
+    /// The number of bytes that have been dropped while searching
+    /// for the next valid frame.
+    #[must_use]
+    pub fn dropped_bytes(&self) -> u64 {
+        self.0.dropped_bytes.load(Ordering::Relaxed)
+    }
+
+    /// The number of times that searching for a valid frame has been
+    /// given up after dropping the maximum size of a frame.
+    #[must_use]
+    pub fn frames_given_up(&self) -> u64 {
+        self.0.frames_given_up.load(Ordering::Relaxed)
+    }
+
+    /// The number of frames with a valid CRC that contained
+    /// a malformed PDU.
+    #[must_use]
+    pub fn malformed_pdus(&self) -> u64 {
+        self.0.malformed_pdus.load(Ordering::Relaxed)
+    }
+
+    fn increment(counter: &AtomicU64, count: usize) {
+        counter.fetch_add(count as u64, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug)]
 pub(crate) struct FrameDecoder {
     dropped_bytes: SmallVec<[u8; MAX_FRAME_LEN]>,
+    /// Frames are delimited by silent intervals instead of
+    /// their expected length if present.
+    frame_boundaries: Option<FrameBoundaries>,
+    error_counters: ErrorCounters,
 }
 
 impl Default for FrameDecoder {
     fn default() -> Self {
         Self {
             dropped_bytes: DroppedBytes::new(),
+            frame_boundaries: None,
+            error_counters: ErrorCounters::default(),
         }
     }
 }
@@ -57,18 +124,25 @@ impl FrameDecoder {
                         let pdu_data = adu_buf.freeze();
                         return Ok(Some((slave_id, pdu_data)));
                     }
-                    Err(err) => Err(err),
+                    Err(err) => {
+                        // Only the CRC error of the corrupted frame is counted,
+                        // not those of the subsequent attempts to resynchronize
+                        // at the following offsets.
+                        if self.dropped_bytes.is_empty() {
+                            ErrorCounters::increment(&self.error_counters.0.crc_errors, 1);
+                        }
+                        Err(err)
+                    }
                 },
                 Err(err) => Err(err),
             }
-            .map_err(|err| {
+            .inspect_err(|_| {
                 // Restore the input buffer
                 let rem_buf = buf.split();
                 debug_assert!(buf.is_empty());
                 buf.unsplit(adu_buf);
                 buf.unsplit(crc_buf);
                 buf.unsplit(rem_buf);
-                err
             })
         } else {
             // Incomplete frame
@@ -90,43 +164,238 @@ impl FrameDecoder {
                     self.dropped_bytes
                 );
                 self.dropped_bytes.clear();
+                ErrorCounters::increment(&self.error_counters.0.frames_given_up, 1);
             }
             self.dropped_bytes.push(*first);
         }
         buf.advance(1);
+        ErrorCounters::increment(&self.error_counters.0.dropped_bytes, 1);
+    }
+
+    /// Drop a whole frame that has been delimited by a silent interval.
+    fn drop_frame(&mut self, buf: &mut BytesMut, frame_len: usize) {
+        debug!("Dropped frame: {:X?}", &buf[..frame_len]);
+        buf.advance(frame_len);
+        ErrorCounters::increment(&self.error_counters.0.dropped_bytes, frame_len);
+    }
+}
+
+type ResolveLengthFn = dyn Fn(&[u8]) -> Result<Option<usize>> + Send + Sync;
+
+/// Resolves the length of the PDU of an RTU frame for a function code.
+///
+/// The PDU starts with the function code and excludes both the
+/// leading slave address and the trailing CRC of the frame.
+#[derive(Clone)]
+pub enum LengthResolver {
+    /// The PDU always has the given length.
+    Fixed(usize),
+
+    /// The PDU contains a byte count at the given offset that is
+    /// followed by the corresponding number of bytes. The function
+    /// code is located at offset 0.
+    ByteCount(usize),
+
+    /// The length is resolved from the bytes of the PDU that have been
+    /// received so far, see [`LengthResolver::custom()`].
+    Custom(Arc<ResolveLengthFn>),
+}
+
+impl LengthResolver {
+    /// Resolve the length with a closure.
+    ///
+    /// The closure receives the bytes of the PDU that have been received
+    /// so far, starting with the function code. It returns `Ok(None)`
+    /// if more bytes are needed and an error if the frame is invalid.
+    pub fn custom<F>(resolve: F) -> Self
+    where
+        F: Fn(&[u8]) -> Result<Option<usize>> + Send + Sync + 'static,
+    {
+        Self::Custom(Arc::new(resolve))
+    }
+
+    fn resolve(&self, pdu_buf: &[u8]) -> Result<Option<usize>> {
+        match self {
+            Self::Fixed(len) => Ok(Some(*len)),
+            Self::ByteCount(offset) => Ok(pdu_buf
+                .get(*offset)
+                .map(|&byte_count| offset + 1 + usize::from(byte_count))),
+            Self::Custom(resolve) => resolve(pdu_buf),
+        }
     }
 }
 
-#[derive(Debug, Default, Eq, PartialEq)]
+impl fmt::Debug for LengthResolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Fixed(len) => f.debug_tuple("Fixed").field(len).finish(),
+            Self::ByteCount(offset) => f.debug_tuple("ByteCount").field(offset).finish(),
+            Self::Custom(_) => f.debug_tuple("Custom").finish_non_exhaustive(),
+        }
+    }
+}
+
+/// A registry of [`LengthResolver`]s for custom or vendor-specific
+/// function codes that are unknown to the RTU codec.
+///
+/// Registered resolvers take precedence over the built-in lengths
+/// of the standard function codes. Exception responses of registered
+/// function codes are resolved implicitly.
+#[derive(Debug, Clone, Default)]
+pub struct LengthResolvers {
+    resolvers: HashMap<FunctionCode, LengthResolver>,
+}
+
+impl LengthResolvers {
+    /// Create an empty registry.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register the resolver for a function code.
+    ///
+    /// Returns the previously registered resolver, if any.
+    pub fn register(
+        &mut self,
+        fn_code: FunctionCode,
+        resolver: LengthResolver,
+    ) -> Option<LengthResolver> {
+        self.resolvers.insert(fn_code, resolver)
+    }
+
+    /// Register the resolver for a function code.
+    #[must_use]
+    pub fn with(mut self, fn_code: FunctionCode, resolver: LengthResolver) -> Self {
+        self.register(fn_code, resolver);
+        self
+    }
+
+    /// Resolve the PDU length if a resolver is registered for the
+    /// function code of the frame.
+    fn resolve(&self, adu_buf: &[u8]) -> Option<Result<Option<usize>>> {
+        let fn_code = *adu_buf.get(1)?;
+        if let Some(resolver) = self.resolvers.get(&fn_code) {
+            return Some(resolver.resolve(&adu_buf[1..]));
+        }
+        if fn_code & 0x80 != 0 && self.resolvers.contains_key(&(fn_code & 0x7F)) {
+            // Exception response: |fn_code|exception|
+            return Some(Ok(Some(2)));
+        }
+        None
+    }
+
+    /// The resolvers for responses that are registered by default.
+    fn default_responses() -> Self {
+        Self::new().with(
+            0xFE,
+            LengthResolver::custom(get_vendor_0xfe_response_pdu_len),
+        )
+    }
+}
+
+#[cfg_attr(not(feature = "server"), allow(dead_code))]
+#[derive(Debug, Default)]
 pub(crate) struct RequestDecoder {
     frame_decoder: FrameDecoder,
+    length_resolvers: LengthResolvers,
 }
 
-#[derive(Debug, Default, Eq, PartialEq)]
+#[derive(Debug)]
 pub(crate) struct ResponseDecoder {
     frame_decoder: FrameDecoder,
+    length_resolvers: LengthResolvers,
+}
+
+impl Default for ResponseDecoder {
+    fn default() -> Self {
+        Self {
+            frame_decoder: FrameDecoder::default(),
+            length_resolvers: LengthResolvers::default_responses(),
+        }
+    }
 }
 
-#[derive(Debug, Default, Eq, PartialEq)]
+#[derive(Debug, Default)]
 pub(crate) struct ClientCodec {
     pub(crate) decoder: ResponseDecoder,
 }
 
-#[derive(Debug, Default, Eq, PartialEq)]
+impl ClientCodec {
+    /// Create a codec with additional resolvers for response lengths
+    /// that override the default resolvers.
+    pub(crate) fn with_length_resolvers(length_resolvers: LengthResolvers) -> Self {
+        let mut codec = Self::default();
+        codec
+            .decoder
+            .length_resolvers
+            .resolvers
+            .extend(length_resolvers.resolvers);
+        codec
+    }
+
+    /// Create a codec for frames that are delimited by silent intervals.
+    pub(crate) fn with_frame_boundaries(frame_boundaries: FrameBoundaries) -> Self {
+        let mut codec = Self::default();
+        codec.decoder.frame_decoder.frame_boundaries = Some(frame_boundaries);
+        codec
+    }
+
+    pub(crate) fn error_counters(&self) -> &ErrorCounters {
+        &self.decoder.frame_decoder.error_counters
+    }
+}
+
+#[cfg_attr(not(feature = "server"), allow(dead_code))]
+#[derive(Debug, Default)]
 pub(crate) struct ServerCodec {
     pub(crate) decoder: RequestDecoder,
 }
 
+#[cfg_attr(not(feature = "server"), allow(dead_code))]
+impl ServerCodec {
+    /// Create a codec with resolvers for request lengths.
+    pub(crate) fn with_length_resolvers(length_resolvers: LengthResolvers) -> Self {
+        Self {
+            decoder: RequestDecoder {
+                frame_decoder: FrameDecoder::default(),
+                length_resolvers,
+            },
+        }
+    }
+
+    /// Create a codec for frames that are delimited by silent intervals.
+    pub(crate) fn with_frame_boundaries(frame_boundaries: FrameBoundaries) -> Self {
+        let mut codec = Self::default();
+        codec.decoder.frame_decoder.frame_boundaries = Some(frame_boundaries);
+        codec
+    }
+
+    /// Count bus errors with shared counters.
+    #[must_use]
+    pub(crate) fn with_error_counters(mut self, error_counters: ErrorCounters) -> Self {
+        self.decoder.frame_decoder.error_counters = error_counters;
+        self
+    }
+}
+
+#[cfg_attr(not(feature = "server"), allow(dead_code))]
 fn get_request_pdu_len(adu_buf: &BytesMut) -> Result<Option<usize>> {
     if let Some(fn_code) = adu_buf.get(1) {
         let len = match fn_code {
-            0x01..=0x06 => 5,
+            // Clients only send Diagnostics requests with a single data word.
+            0x01..=0x06 | 0x08 => 5,
             0x07 | 0x0B | 0x0C | 0x11 => 1,
             0x0F | 0x10 => {
                 return Ok(adu_buf
                     .get(6)
                     .map(|&byte_count| 6 + usize::from(byte_count)));
             }
+            0x14 | 0x15 => {
+                return Ok(adu_buf
+                    .get(2)
+                    .map(|&byte_count| 2 + usize::from(byte_count)));
+            }
             0x16 => 7,
             0x18 => 3,
             0x17 => {
@@ -134,11 +403,21 @@ fn get_request_pdu_len(adu_buf: &BytesMut) -> Result<Option<usize>> {
                     .get(10)
                     .map(|&byte_count| 10 + usize::from(byte_count)));
             }
+            0x2B => match adu_buf.get(2) {
+                Some(&MEI_READ_DEVICE_ID) => 4,
+                Some(mei_type) => {
+                    return Err(protocol_violation(format!(
+                        "Invalid MEI type: 0x{:0>2X}",
+                        mei_type
+                    )));
+                }
+                None => return Ok(None),
+            },
             _ => {
-                return Err(Error::new(
-                    ErrorKind::InvalidData,
-                    format!("Invalid function code: 0x{:0>2X}", fn_code),
-                ));
+                return Err(protocol_violation(format!(
+                    "Invalid function code: 0x{:0>2X}",
+                    fn_code
+                )));
             }
         };
         Ok(Some(len))
@@ -150,12 +429,12 @@ fn get_request_pdu_len(adu_buf: &BytesMut) -> Result<Option<usize>> {
 fn get_response_pdu_len(adu_buf: &BytesMut) -> Result<Option<usize>> {
     if let Some(fn_code) = adu_buf.get(1) {
         let len = match fn_code {
-            0x01..=0x04 | 0x0C | 0x17 => {
+            0x01..=0x04 | 0x0C | 0x11 | 0x14 | 0x15 | 0x17 => {
                 return Ok(adu_buf
                     .get(2)
                     .map(|&byte_count| 2 + usize::from(byte_count)));
             }
-            0x05 | 0x06 | 0x0B | 0x0F | 0x10 => 5,
+            0x05 | 0x06 | 0x08 | 0x0B | 0x0F | 0x10 => 5,
             0x07 => 2,
             0x16 => 7,
             0x18 => {
@@ -166,45 +445,13 @@ fn get_response_pdu_len(adu_buf: &BytesMut) -> Result<Option<usize>> {
                     return Ok(None);
                 }
             }
+            0x2B => return get_read_device_id_response_pdu_len(adu_buf),
             0x81..=0xAB => 2,
-            0xfe => {
-                if adu_buf.len() < 4 {
-                    //if not enough bytes where received yet wait for more bytes
-                    return Ok(None);
-                }
-                let subcall = usize::from(Cursor::new(&adu_buf[2..=3]).read_u16::<BigEndian>()?);
-                match subcall {
-                    0x0701 => {
-                        // expected response format |addr|fn_code|0x07|0x01|0xXX|0xXX|0xXX|0xXX|crc|
-                        // pdu is byte length without addr and crc => 3 + 4 = 7
-                        7
-                    },
-                    0x0702 => {
-                        // expected response format |addr|fn_code|0x07|0x02|crc|
-                        // pdu is byte length without addr and crc => 3 + 0= 7
-                        3
-                    }
-                    0x0703 => {
-                        // expected response format |addr|fn_code|0x07|0x03|0xXX|crc|
-                        // pdu is byte length without addr and crc => 3 + 1 = 4
-                        4
-                    }
-                    0x0704 => {
-                        // expected response format |addr|fn_code|0x07|0x04|0xXX|0xXX|0xXX|0xXX|64*0xAA|crc|
-                        // pdu is byte length without addr and crc => 64+4+3 = 71
-                        71
-                    },
-                    _ => {
-                        warn!("Response length calculation for subcall response for code 0x{:x} not implemented!", subcall);
-                        unimplemented!()
-                    }
-                }
-            },
             _ => {
-                return Err(Error::new(
-                    ErrorKind::InvalidData,
-                    format!("Invalid function code: 0x{:0>2X}", fn_code),
-                ));
+                return Err(protocol_violation(format!(
+                    "Invalid function code: 0x{:0>2X}",
+                    fn_code
+                )));
             }
         };
         Ok(Some(len))
@@ -213,6 +460,60 @@ fn get_response_pdu_len(adu_buf: &BytesMut) -> Result<Option<usize>> {
     }
 }
 
+fn get_read_device_id_response_pdu_len(adu_buf: &BytesMut) -> Result<Option<usize>> {
+    match adu_buf.get(2) {
+        Some(&MEI_READ_DEVICE_ID) => {}
+        Some(mei_type) => {
+            return Err(protocol_violation(format!(
+                "Invalid MEI type: 0x{:0>2X}",
+                mei_type
+            )));
+        }
+        None => return Ok(None),
+    }
+    // |addr|fn_code|mei_type|read_device_id_code|conformity_level|more_follows|next_object_id|number_of_objects|
+    // followed by |object_id|object_len|object_value...| for each object
+    let Some(&number_of_objects) = adu_buf.get(7) else {
+        return Ok(None);
+    };
+    let mut offset = 8;
+    for _ in 0..number_of_objects {
+        let Some(&object_len) = adu_buf.get(offset + 1) else {
+            return Ok(None);
+        };
+        offset += 2 + usize::from(object_len);
+    }
+    // pdu is byte length without addr
+    Ok(Some(offset - 1))
+}
+
+/// Resolve the response length of the vendor-specific function code 0xFE
+/// from its sub-call.
+fn get_vendor_0xfe_response_pdu_len(pdu_buf: &[u8]) -> Result<Option<usize>> {
+    if pdu_buf.len() < 3 {
+        // Wait for more bytes
+        return Ok(None);
+    }
+    let subcall = Cursor::new(&pdu_buf[1..=2]).read_u16::<BigEndian>()?;
+    let len = match subcall {
+        // expected response format |fn_code|0x07|0x01|0xXX|0xXX|0xXX|0xXX| => 3 + 4 = 7
+        0x0701 => 7,
+        // expected response format |fn_code|0x07|0x02| => 3 + 0 = 3
+        0x0702 => 3,
+        // expected response format |fn_code|0x07|0x03|0xXX| => 3 + 1 = 4
+        0x0703 => 4,
+        // expected response format |fn_code|0x07|0x04|0xXX|0xXX|0xXX|0xXX|64*0xAA| => 3 + 4 + 64 = 71
+        0x0704 => 71,
+        _ => {
+            return Err(protocol_violation(format!(
+                "Unknown sub-call of function code 0xFE: 0x{:0>4X}",
+                subcall
+            )));
+        }
+    };
+    Ok(Some(len))
+}
+
 fn calc_crc(data: &[u8]) -> u16 {
     let mut crc = 0xFFFF;
     for x in data {
@@ -225,19 +526,16 @@ fn calc_crc(data: &[u8]) -> u16 {
             }
         }
     }
-    crc << 8 | crc >> 8
+    crc.rotate_left(8)
 }
 
 fn check_crc(adu_data: &[u8], expected_crc: u16) -> Result<()> {
     let actual_crc = calc_crc(adu_data);
     if expected_crc != actual_crc {
-        return Err(Error::new(
-            ErrorKind::InvalidData,
-            format!(
-                "Invalid CRC: expected = 0x{:0>4X}, actual = 0x{:0>4X}",
-                expected_crc, actual_crc
-            ),
-        ));
+        return Err(protocol_violation(format!(
+            "Invalid CRC: expected = 0x{:0>4X}, actual = 0x{:0>4X}",
+            expected_crc, actual_crc
+        )));
     }
     Ok(())
 }
@@ -247,7 +545,17 @@ impl Decoder for RequestDecoder {
     type Error = Error;
 
     fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<(SlaveId, Bytes)>> {
-        decode("request", &mut self.frame_decoder, get_request_pdu_len, buf)
+        let length_resolvers = &self.length_resolvers;
+        decode(
+            "request",
+            &mut self.frame_decoder,
+            |adu_buf| {
+                length_resolvers
+                    .resolve(adu_buf)
+                    .unwrap_or_else(|| get_request_pdu_len(adu_buf))
+            },
+            buf,
+        )
     }
 }
 
@@ -256,10 +564,15 @@ impl Decoder for ResponseDecoder {
     type Error = Error;
 
     fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<(SlaveId, Bytes)>> {
+        let length_resolvers = &self.length_resolvers;
         decode(
             "response",
             &mut self.frame_decoder,
-            get_response_pdu_len,
+            |adu_buf| {
+                length_resolvers
+                    .resolve(adu_buf)
+                    .unwrap_or_else(|| get_response_pdu_len(adu_buf))
+            },
             buf,
         )
     }
@@ -274,6 +587,9 @@ fn decode<F>(
 where
     F: Fn(&BytesMut) -> Result<Option<usize>>,
 {
+    if let Some(frame_boundaries) = frame_decoder.frame_boundaries.clone() {
+        return decode_delimited(pdu_type, frame_decoder, &frame_boundaries, buf);
+    }
     // TODO: Transform this loop into idiomatic code
     loop {
         let mut retry = false;
@@ -299,36 +615,68 @@ where
     }
 }
 
+/// Decode the next frame that has been delimited by a silent interval.
+///
+/// Invalid frames are dropped as a whole.
+fn decode_delimited(
+    pdu_type: &str,
+    frame_decoder: &mut FrameDecoder,
+    frame_boundaries: &FrameBoundaries,
+    buf: &mut BytesMut,
+) -> Result<Option<(SlaveId, Bytes)>> {
+    while let Some(FrameBoundary { len, interrupted }) = frame_boundaries.front() {
+        if buf.len() < len {
+            // Incomplete frame
+            return Ok(None);
+        }
+        frame_boundaries.pop();
+        // |slave_id|fn_code|...|crc_hi|crc_lo|
+        let res = if interrupted {
+            Err(protocol_violation(
+                "Silent interval of more than t1.5 within frame",
+            ))
+        } else if len < 4 {
+            Err(protocol_violation("Frame too short"))
+        } else {
+            frame_decoder.decode(buf, len - 3)
+        };
+        match res {
+            Ok(frame) => {
+                debug_assert!(frame.is_some());
+                return Ok(frame);
+            }
+            Err(err) => {
+                warn!("Failed to decode {} frame: {}", pdu_type, err);
+                frame_decoder.drop_frame(buf, len);
+            }
+        }
+    }
+    Ok(None)
+}
+
 impl Decoder for ClientCodec {
     type Item = ResponseAdu;
     type Error = Error;
 
     fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<ResponseAdu>> {
-        self.decoder
-            .decode(buf)
-            .and_then(|frame| {
-                if let Some((slave_id, pdu_data)) = frame {
-                    let hdr = Header { slave_id };
-                    // Decoding of the PDU should are unlikely to fail due
-                    // to transmission errors, because the frame's bytes
-                    // have already been verified with the CRC.
-                    ResponsePdu::try_from(pdu_data)
-                        .map(|pdu| Some(ResponseAdu { hdr, pdu }))
-                        .map_err(|err| {
-                            // Unrecoverable error
-                            error!("Failed to decode response PDU: {}", err);
-                            err
-                        })
-                } else {
-                    Ok(None)
+        while let Some((slave_id, pdu_data)) = self.decoder.decode(buf)? {
+            let hdr = Header { slave_id };
+            // Decoding of the PDU is unlikely to fail due to transmission
+            // errors, because the frame's bytes have already been verified
+            // with the CRC. The frame is skipped to resynchronize with
+            // the bus otherwise.
+            match ResponsePdu::try_from(pdu_data) {
+                Ok(pdu) => return Ok(Some(ResponseAdu { hdr, pdu })),
+                Err(err) => {
+                    warn!("Dropping frame with malformed response PDU: {}", err);
+                    ErrorCounters::increment(
+                        &self.decoder.frame_decoder.error_counters.0.malformed_pdus,
+                        1,
+                    );
                 }
-            })
-            .map_err(|err| {
-                println!("{err:#?}");
-                // Decoding the transport frame is non-destructive and must
-                // never fail!
-                unreachable!();
-            })
+            }
+        }
+        Ok(None)
     }
 }
 
@@ -337,36 +685,30 @@ impl Decoder for ServerCodec {
     type Error = Error;
 
     fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<RequestAdu>> {
-        self.decoder
-            .decode(buf)
-            .and_then(|frame| {
-                if let Some((slave_id, pdu_data)) = frame {
-                    let hdr = Header { slave_id };
-                    // Decoding of the PDU should are unlikely to fail due
-                    // to transmission errors, because the frame's bytes
-                    // have already been verified with the CRC.
-                    RequestPdu::try_from(pdu_data)
-                        .map(|pdu| {
-                            Some(RequestAdu {
-                                hdr,
-                                pdu,
-                                disconnect: false,
-                            })
-                        })
-                        .map_err(|err| {
-                            // Unrecoverable error
-                            error!("Failed to decode request PDU: {}", err);
-                            err
-                        })
-                } else {
-                    Ok(None)
+        while let Some((slave_id, pdu_data)) = self.decoder.decode(buf)? {
+            let hdr = Header { slave_id };
+            // Decoding of the PDU is unlikely to fail due to transmission
+            // errors, because the frame's bytes have already been verified
+            // with the CRC. The frame is skipped to resynchronize with
+            // the bus otherwise.
+            match RequestPdu::try_from(pdu_data) {
+                Ok(pdu) => {
+                    return Ok(Some(RequestAdu {
+                        hdr,
+                        pdu,
+                        disconnect: false,
+                    }))
                 }
-            })
-            .map_err(|_| {
-                // Decoding the transport frame is non-destructive and must
-                // never fail!
-                unreachable!();
-            })
+                Err(err) => {
+                    warn!("Dropping frame with malformed request PDU: {}", err);
+                    ErrorCounters::increment(
+                        &self.decoder.frame_decoder.error_counters.0.malformed_pdus,
+                        1,
+                    );
+                }
+            }
+        }
+        Ok(None)
     }
 }
 
@@ -385,6 +727,17 @@ impl Encoder<RequestAdu> for ClientCodec {
             ));
         }
         let RequestAdu { hdr, pdu, .. } = adu;
+        check_request_len(&pdu.0)?;
+        if let Request::Diagnostics(_, data) = &pdu.0 {
+            if data.len() != 1 {
+                // Neither the server nor the client could delimit
+                // frames of other lengths by their function code.
+                return Err(crate::Error::InvalidRequest(
+                    "RTU diagnostics requests must contain a single data word".to_owned(),
+                )
+                .into());
+            }
+        }
         let pdu_data: Bytes = pdu.into();
         buf.reserve(pdu_data.len() + 3);
         buf.put_u8(hdr.slave_id);
@@ -452,7 +805,8 @@ mod tests {
         buf[1] = 0x07;
         assert_eq!(get_request_pdu_len(&buf).unwrap(), Some(1));
 
-        // TODO: 0x08
+        buf[1] = 0x08;
+        assert_eq!(get_request_pdu_len(&buf).unwrap(), Some(5));
 
         buf[1] = 0x0B;
         assert_eq!(get_request_pdu_len(&buf).unwrap(), Some(1));
@@ -471,9 +825,13 @@ mod tests {
         buf[1] = 0x11;
         assert_eq!(get_request_pdu_len(&buf).unwrap(), Some(1));
 
-        // TODO: 0x14
+        buf[1] = 0x14;
+        buf[2] = 14; // byte count
+        assert_eq!(get_request_pdu_len(&buf).unwrap(), Some(16));
 
-        // TODO: 0x15
+        buf[1] = 0x15;
+        buf[2] = 99; // byte count
+        assert_eq!(get_request_pdu_len(&buf).unwrap(), Some(101));
 
         buf[1] = 0x16;
         assert_eq!(get_request_pdu_len(&buf).unwrap(), Some(7));
@@ -485,7 +843,12 @@ mod tests {
         buf[1] = 0x18;
         assert_eq!(get_request_pdu_len(&buf).unwrap(), Some(3));
 
-        // TODO: 0x2B
+        buf[1] = 0x2B;
+        buf[2] = 0x0E;
+        assert_eq!(get_request_pdu_len(&buf).unwrap(), Some(4));
+
+        buf[2] = 0x0D;
+        assert!(get_request_pdu_len(&buf).is_err());
     }
 
     #[test]
@@ -519,7 +882,8 @@ mod tests {
         buf[1] = 0x07;
         assert_eq!(get_response_pdu_len(&buf).unwrap(), Some(2));
 
-        // TODO: 0x08
+        buf[1] = 0x08;
+        assert_eq!(get_response_pdu_len(&buf).unwrap(), Some(5));
 
         buf[1] = 0x0B;
         assert_eq!(get_response_pdu_len(&buf).unwrap(), Some(5));
@@ -533,11 +897,14 @@ mod tests {
         buf[1] = 0x10;
         assert_eq!(get_response_pdu_len(&buf).unwrap(), Some(5));
 
-        // TODO: 0x11
+        buf[1] = 0x11;
+        assert_eq!(get_response_pdu_len(&buf).unwrap(), Some(101));
 
-        // TODO: 0x14
+        buf[1] = 0x14;
+        assert_eq!(get_response_pdu_len(&buf).unwrap(), Some(101));
 
-        // TODO: 0x15
+        buf[1] = 0x15;
+        assert_eq!(get_response_pdu_len(&buf).unwrap(), Some(101));
 
         buf[1] = 0x16;
         assert_eq!(get_response_pdu_len(&buf).unwrap(), Some(7));
@@ -550,7 +917,19 @@ mod tests {
         buf[3] = 0x00; // byte count Lo
         assert_eq!(get_response_pdu_len(&buf).unwrap(), Some(259));
 
-        // TODO: 0x2B
+        let mut buf = BytesMut::new();
+        buf.extend_from_slice(&[0x66, 0x2B, 0x0E, 0x01, 0x01, 0x00, 0x00, 0x02]);
+        assert_eq!(get_response_pdu_len(&buf).unwrap(), None);
+        buf.extend_from_slice(&[0x00, 0x03, b'F', b'o', b'o']);
+        assert_eq!(get_response_pdu_len(&buf).unwrap(), None);
+        buf.extend_from_slice(&[0x01, 0x02]);
+        assert_eq!(get_response_pdu_len(&buf).unwrap(), Some(16));
+
+        buf[2] = 0x0D;
+        assert!(get_response_pdu_len(&buf).is_err());
+
+        let mut buf = BytesMut::new();
+        buf.extend_from_slice(&[0x66, 0x00, 99, 0x00]);
 
         for i in 0x81..0xAB {
             buf[1] = i;
@@ -802,5 +1181,201 @@ mod tests {
             }
             assert!(codec.encode(adu, &mut buf).is_ok());
         }
+
+        #[test]
+        fn reject_diagnostics_request_without_single_data_word() {
+            let mut codec = ClientCodec::default();
+            for data in [vec![], vec![0x0102, 0x0304]] {
+                let adu = RequestAdu {
+                    hdr: Header { slave_id: 0x01 },
+                    pdu: Request::Diagnostics(DiagnosticsSubFunction::ReturnQueryData, data).into(),
+                    disconnect: false,
+                };
+                let mut buf = BytesMut::new();
+                let err = codec.encode(adu, &mut buf).unwrap_err();
+                assert_eq!(err.kind(), ErrorKind::InvalidInput);
+                assert!(buf.is_empty());
+            }
+        }
+    }
+
+    mod length_resolvers {
+        use super::*;
+
+        fn encode_request(req: Request) -> BytesMut {
+            let adu = RequestAdu {
+                hdr: Header { slave_id: 0x01 },
+                pdu: req.into(),
+                disconnect: false,
+            };
+            let mut buf = BytesMut::new();
+            ClientCodec::default().encode(adu, &mut buf).unwrap();
+            buf
+        }
+
+        fn encode_response(rsp: std::result::Result<Response, ExceptionResponse>) -> BytesMut {
+            let adu = ResponseAdu {
+                hdr: Header { slave_id: 0x01 },
+                pdu: rsp.into(),
+            };
+            let mut buf = BytesMut::new();
+            ServerCodec::default().encode(adu, &mut buf).unwrap();
+            buf
+        }
+
+        #[test]
+        fn decode_custom_request_with_fixed_length() {
+            let mut codec = ServerCodec::with_length_resolvers(
+                LengthResolvers::new().with(0x41, LengthResolver::Fixed(3)),
+            );
+            let mut buf = encode_request(Request::Custom(0x41, vec![0x12, 0x34]));
+            buf.extend_from_slice(&encode_request(Request::ReadCoils(0x00, 5)));
+            let adu = codec.decode(&mut buf).unwrap().unwrap();
+            assert_eq!(adu.pdu.0, Request::Custom(0x41, vec![0x12, 0x34]));
+            let adu = codec.decode(&mut buf).unwrap().unwrap();
+            assert_eq!(adu.pdu.0, Request::ReadCoils(0x00, 5));
+            assert!(buf.is_empty());
+        }
+
+        #[test]
+        fn decode_custom_response_with_byte_count() {
+            let mut codec = ClientCodec::with_length_resolvers(
+                LengthResolvers::new().with(0x42, LengthResolver::ByteCount(2)),
+            );
+            let mut buf = encode_response(Ok(Response::Custom(0x42, vec![0x07, 0x02, 0xAB, 0xCD])));
+            let adu = codec.decode(&mut buf).unwrap().unwrap();
+            assert_eq!(
+                adu.pdu.0,
+                Ok(Response::Custom(0x42, vec![0x07, 0x02, 0xAB, 0xCD]))
+            );
+            assert!(buf.is_empty());
+
+            // Exception responses of registered function codes
+            let exception = ExceptionResponse {
+                function: 0x42,
+                exception: Exception::IllegalDataValue,
+            };
+            let mut buf = encode_response(Err(exception));
+            let adu = codec.decode(&mut buf).unwrap().unwrap();
+            assert_eq!(adu.pdu.0, Err(exception));
+        }
+
+        #[test]
+        fn decode_custom_response_with_closure() {
+            let mut codec = ClientCodec::with_length_resolvers(LengthResolvers::new().with(
+                0x43,
+                LengthResolver::custom(|pdu_buf| {
+                    Ok(pdu_buf.get(1).map(|&len| 2 + usize::from(len) * 2))
+                }),
+            ));
+            let mut buf = encode_response(Ok(Response::Custom(0x43, vec![0x01, 0xAB, 0xCD])));
+            // Incomplete frame
+            let mut partial = buf.split_to(3);
+            assert!(codec.decode(&mut partial).unwrap().is_none());
+            partial.unsplit(buf);
+            let adu = codec.decode(&mut partial).unwrap().unwrap();
+            assert_eq!(
+                adu.pdu.0,
+                Ok(Response::Custom(0x43, vec![0x01, 0xAB, 0xCD]))
+            );
+        }
+
+        #[test]
+        fn decode_vendor_0xfe_response() {
+            let mut codec = ClientCodec::default();
+            let mut buf = encode_response(Ok(Response::Custom(0xFE, vec![0x07, 0x03, 0x2A])));
+            let adu = codec.decode(&mut buf).unwrap().unwrap();
+            assert_eq!(
+                adu.pdu.0,
+                Ok(Response::Custom(0xFE, vec![0x07, 0x03, 0x2A]))
+            );
+
+            // Unknown sub-calls are dropped instead of panicking
+            let mut buf = encode_response(Ok(Response::Custom(0xFE, vec![0x07, 0x05])));
+            assert!(codec.decode(&mut buf).unwrap().is_none());
+        }
+
+        #[test]
+        fn unknown_function_codes_are_not_resolved() {
+            let mut codec = ServerCodec::default();
+            let mut buf = encode_request(Request::Custom(0x41, vec![0x12, 0x34]));
+            assert!(codec.decode(&mut buf).unwrap().is_none());
+        }
+    }
+
+    mod error_counters {
+        use super::*;
+
+        fn frame(adu: &[u8]) -> BytesMut {
+            let mut buf = BytesMut::from(adu);
+            let crc = calc_crc(adu);
+            buf.put_u16(crc);
+            buf
+        }
+
+        #[test]
+        fn count_crc_errors_and_dropped_bytes() {
+            let mut codec = ClientCodec::default();
+            let error_counters = codec.error_counters().clone();
+            let mut buf = frame(&[0x05, 0x06, 0x00, 0x00, 0x00, 0x07]);
+            // Corrupt the CRC
+            buf[7] ^= 0xFF;
+            buf.unsplit(frame(&[0x05, 0x06, 0x00, 0x02, 0x00, 0x04]));
+            let adu = codec.decode(&mut buf).unwrap().unwrap();
+            assert_eq!(adu.pdu.0, Ok(Response::WriteSingleRegister(0x02, 0x04)));
+            assert!(buf.is_empty());
+
+            // The corrupted frame is counted once, not at every
+            // offset that has been tried while resynchronizing
+            assert_eq!(error_counters.crc_errors(), 1);
+            assert_eq!(error_counters.dropped_bytes(), 8);
+            assert_eq!(error_counters.frames_given_up(), 0);
+            assert_eq!(error_counters.malformed_pdus(), 0);
+
+            // Corrupt the data of the next frame
+            let mut buf = frame(&[0x05, 0x06, 0x00, 0x00, 0x00, 0x07]);
+            buf[5] ^= 0xFF;
+            buf.unsplit(frame(&[0x05, 0x06, 0x00, 0x02, 0x00, 0x04]));
+            let adu = codec.decode(&mut buf).unwrap().unwrap();
+            assert_eq!(adu.pdu.0, Ok(Response::WriteSingleRegister(0x02, 0x04)));
+            assert!(buf.is_empty());
+            assert_eq!(error_counters.crc_errors(), 2);
+            assert_eq!(error_counters.dropped_bytes(), 16);
+        }
+
+        #[test]
+        fn count_frames_given_up() {
+            let error_counters = ErrorCounters::default();
+            let mut codec = ServerCodec::default().with_error_counters(error_counters.clone());
+            let mut buf = BytesMut::from(&[0x00; MAX_FRAME_LEN + 4][..]);
+            assert!(codec.decode(&mut buf).unwrap().is_none());
+            assert_eq!(error_counters.frames_given_up(), 1);
+            assert_eq!(error_counters.dropped_bytes(), MAX_FRAME_LEN as u64 + 3);
+        }
+
+        #[test]
+        fn skip_malformed_response_pdu() {
+            let mut codec = ClientCodec::default();
+            // Byte count does not match FIFO count
+            let mut buf = frame(&[0x01, 0x18, 0x00, 0x04, 0x00, 0x02, 0x01, 0xB8]);
+            buf.unsplit(frame(&[0x01, 0x06, 0x00, 0x01, 0x00, 0x03]));
+            let adu = codec.decode(&mut buf).unwrap().unwrap();
+            assert_eq!(adu.pdu.0, Ok(Response::WriteSingleRegister(0x01, 0x03)));
+            assert_eq!(codec.error_counters().malformed_pdus(), 1);
+            assert_eq!(codec.error_counters().crc_errors(), 0);
+        }
+
+        #[test]
+        fn skip_malformed_request_pdu() {
+            let error_counters = ErrorCounters::default();
+            let mut codec = ServerCodec::default().with_error_counters(error_counters.clone());
+            // Byte count is not a multiple of the sub-request length
+            let mut buf = frame(&[
+                0x01, 0x14, 0x08, 0x06, 0x00, 0x04, 0x00, 0x01, 0x00, 0x02, 0x06,
+            ]);
+            assert!(codec.decode(&mut buf).unwrap().is_none());
+            assert!(buf.is_empty());
+            assert_eq!(error_counters.malformed_pdus(), 1);
+        }
     }
 }
@@ -0,0 +1,406 @@
+// SPDX-FileCopyrightText: Copyright (c) 2017-2022 slowtec GmbH <post@slowtec.de>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Delimitation of RTU frames by silent intervals on the serial line
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    future::Future as _,
+    io::Result,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use bytes::{Buf as _, BytesMut};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    time::{sleep_until, Instant, Sleep},
+};
+use tokio_serial::{DataBits, Parity, SerialPort, StopBits};
+
+// [MODBUS over Serial Line Specification and Implementation Guide V1.02](http://modbus.org/docs/Modbus_over_serial_line_V1_02.pdf), page 13
+// "If baud rate is greater than 19200 Bps, fixed values for the 2 timers should be used"
+const MAX_TIMED_BAUD_RATE: u32 = 19_200;
+const FIXED_T1_5: Duration = Duration::from_micros(750);
+const FIXED_T3_5: Duration = Duration::from_micros(1_750);
+
+/// The timing of characters on a serial line.
+///
+/// RTU frames are separated by silent intervals of at least 3.5 character
+/// times (t3.5). A silent interval of more than 1.5 character times (t1.5)
+/// within a frame renders it incomplete.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameTiming {
+    char_time: Duration,
+    fixed_intervals: bool,
+}
+
+impl FrameTiming {
+    /// Calculate the timing from the baud rate and the framing
+    /// of the serial line.
+    ///
+    /// Each character consists of a start bit, the data bits,
+    /// an optional parity bit, and the stop bits.
+    #[must_use]
+    pub fn new(baud_rate: u32, data_bits: DataBits, parity: Parity, stop_bits: StopBits) -> Self {
+        let data_bits = match data_bits {
+            DataBits::Five => 5,
+            DataBits::Six => 6,
+            DataBits::Seven => 7,
+            DataBits::Eight => 8,
+        };
+        let parity_bits = match parity {
+            Parity::None => 0,
+            Parity::Odd | Parity::Even => 1,
+        };
+        let stop_bits = match stop_bits {
+            StopBits::One => 1,
+            StopBits::Two => 2,
+        };
+        let bits_per_char: u32 = 1 + data_bits + parity_bits + stop_bits;
+        Self {
+            char_time: Duration::from_secs(1) * bits_per_char / baud_rate.max(1),
+            fixed_intervals: baud_rate > MAX_TIMED_BAUD_RATE,
+        }
+    }
+
+    /// Calculate the timing from the current settings of a serial port.
+    pub fn from_port(port: &impl SerialPort) -> Result<Self> {
+        Ok(Self::new(
+            port.baud_rate()?,
+            port.data_bits()?,
+            port.parity()?,
+            port.stop_bits()?,
+        ))
+    }
+
+    /// The time for transmitting a single character.
+    #[must_use]
+    pub fn char_time(&self) -> Duration {
+        self.char_time
+    }
+
+    /// The maximum silent interval between two characters of a frame.
+    #[must_use]
+    pub fn t1_5(&self) -> Duration {
+        if self.fixed_intervals {
+            return FIXED_T1_5;
+        }
+        self.char_time * 3 / 2
+    }
+
+    /// The minimum silent interval between two frames.
+    #[must_use]
+    pub fn t3_5(&self) -> Duration {
+        if self.fixed_intervals {
+            return FIXED_T3_5;
+        }
+        self.char_time * 7 / 2
+    }
+}
+
+/// A complete frame that has been received by a [`SilenceDelimited`]
+/// transport.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct FrameBoundary {
+    pub(crate) len: usize,
+    /// A silent interval of more than t1.5 occurred within the frame
+    pub(crate) interrupted: bool,
+}
+
+/// The boundaries of frames that have been received by
+/// a [`SilenceDelimited`] transport but not yet decoded.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct FrameBoundaries(Arc<Mutex<VecDeque<FrameBoundary>>>);
+
+impl FrameBoundaries {
+    fn push(&self, boundary: FrameBoundary) {
+        self.0.lock().unwrap().push_back(boundary);
+    }
+
+    pub(crate) fn front(&self) -> Option<FrameBoundary> {
+        self.0.lock().unwrap().front().copied()
+    }
+
+    pub(crate) fn pop(&self) -> Option<FrameBoundary> {
+        self.0.lock().unwrap().pop_front()
+    }
+}
+
+/// A transport that withholds received bytes until a frame has been
+/// terminated by a silent interval and records the frame boundaries.
+///
+/// Invalid frames are dropped by the codec.
+pub(crate) struct SilenceDelimited<T> {
+    inner: T,
+    timing: FrameTiming,
+    boundaries: FrameBoundaries,
+    /// The bytes of the frame that is currently received
+    frame: BytesMut,
+    /// A silent interval of more than t1.5 occurred within the frame
+    interrupted: bool,
+    last_received: Instant,
+    silence: Pin<Box<Sleep>>,
+    /// Complete frames that have not been read yet
+    ready: BytesMut,
+}
+
+impl<T> SilenceDelimited<T> {
+    pub(crate) fn new(inner: T, timing: FrameTiming, boundaries: FrameBoundaries) -> Self {
+        let now = Instant::now();
+        Self {
+            inner,
+            timing,
+            boundaries,
+            frame: BytesMut::new(),
+            interrupted: false,
+            last_received: now,
+            silence: Box::pin(sleep_until(now)),
+            ready: BytesMut::new(),
+        }
+    }
+
+    fn receive(&mut self, bytes: &[u8]) {
+        let now = Instant::now();
+        // Serial drivers deliver received characters in chunks. The
+        // characters of a chunk have been transmitted during the time
+        // that has elapsed since the previous chunk, which therefore
+        // doesn't count as silence.
+        let transmission_time = self
+            .timing
+            .char_time()
+            .saturating_mul(u32::try_from(bytes.len()).unwrap_or(u32::MAX));
+        let silence = now
+            .duration_since(self.last_received)
+            .saturating_sub(transmission_time);
+        if !self.frame.is_empty() && silence > self.timing.t1_5() {
+            self.interrupted = true;
+        }
+        self.frame.extend_from_slice(bytes);
+        self.last_received = now;
+        self.silence.as_mut().reset(now + self.timing.t3_5());
+    }
+
+    fn end_frame(&mut self) {
+        let frame = self.frame.split();
+        self.boundaries.push(FrameBoundary {
+            len: frame.len(),
+            interrupted: std::mem::take(&mut self.interrupted),
+        });
+        self.ready.unsplit(frame);
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for SilenceDelimited<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SilenceDelimited")
+            .field("inner", &self.inner)
+            .field("timing", &self.timing)
+            .field("frame", &self.frame)
+            .field("ready", &self.ready)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for SilenceDelimited<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if !this.ready.is_empty() {
+                let len = this.ready.len().min(buf.remaining());
+                buf.put_slice(&this.ready[..len]);
+                this.ready.advance(len);
+                return Poll::Ready(Ok(()));
+            }
+            let mut chunk = [0; 256];
+            let mut chunk = ReadBuf::new(&mut chunk);
+            match Pin::new(&mut this.inner).poll_read(cx, &mut chunk) {
+                Poll::Ready(Ok(())) if chunk.filled().is_empty() => {
+                    // End of stream
+                    if this.frame.is_empty() {
+                        return Poll::Ready(Ok(()));
+                    }
+                    this.end_frame();
+                }
+                Poll::Ready(Ok(())) => this.receive(chunk.filled()),
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                Poll::Pending => {
+                    if this.frame.is_empty() {
+                        return Poll::Pending;
+                    }
+                    if this.silence.as_mut().poll(cx).is_pending() {
+                        return Poll::Pending;
+                    }
+                    this.end_frame();
+                }
+            }
+        }
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for SilenceDelimited<T> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        codec::rtu::{ClientCodec, ServerCodec},
+        frame::{rtu::*, Response},
+    };
+
+    use futures::StreamExt as _;
+    use tokio::{
+        io::{AsyncReadExt as _, AsyncWriteExt as _},
+        time::sleep,
+    };
+    use tokio_util::codec::{Encoder as _, FramedRead};
+
+    fn encode_response(rsp: Response) -> BytesMut {
+        let adu = ResponseAdu {
+            hdr: Header { slave_id: 0x01 },
+            pdu: Ok(rsp).into(),
+        };
+        let mut buf = BytesMut::new();
+        ServerCodec::default().encode(adu, &mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn calculate_intervals_from_framing() {
+        let timing = FrameTiming::new(9600, DataBits::Eight, Parity::None, StopBits::One);
+        assert_eq!(timing.char_time(), Duration::from_nanos(1_041_666));
+        assert_eq!(timing.t1_5(), Duration::from_nanos(1_562_499));
+        assert_eq!(timing.t3_5(), Duration::from_nanos(3_645_831));
+
+        let timing = FrameTiming::new(19200, DataBits::Eight, Parity::Even, StopBits::One);
+        assert_eq!(timing.char_time(), Duration::from_nanos(572_916));
+
+        // Fixed intervals for higher baud rates
+        let timing = FrameTiming::new(115_200, DataBits::Eight, Parity::Even, StopBits::One);
+        assert_eq!(timing.t1_5(), Duration::from_micros(750));
+        assert_eq!(timing.t3_5(), Duration::from_micros(1750));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn delimit_frames_by_silent_intervals() {
+        // A low baud rate, because timers have a resolution of 1 ms
+        let timing = FrameTiming::new(1200, DataBits::Eight, Parity::None, StopBits::One);
+        let (mut tx, rx) = tokio::io::duplex(1024);
+        let frame_boundaries = FrameBoundaries::default();
+        let mut framed = FramedRead::new(
+            SilenceDelimited::new(rx, timing, frame_boundaries.clone()),
+            ClientCodec::with_frame_boundaries(frame_boundaries),
+        );
+
+        tokio::spawn(async move {
+            let silence = timing.t3_5() * 2;
+            // Noise
+            tx.write_all(&[0x42, 0x43]).await.unwrap();
+            sleep(silence).await;
+            // A response with an unknown function code, sent byte by byte
+            for byte in encode_response(Response::Custom(0x41, vec![0x01, 0x02, 0x03])) {
+                tx.write_all(&[byte]).await.unwrap();
+                sleep(timing.char_time()).await;
+            }
+            sleep(silence).await;
+            // A response that is interrupted by a silent interval of
+            // 2 characters, i.e. more than t1.5 but less than t3.5
+            let frame = encode_response(Response::Custom(0x41, vec![0x04]));
+            tx.write_all(&frame[..2]).await.unwrap();
+            sleep(timing.char_time() * 3).await;
+            for byte in &frame[2..] {
+                tx.write_all(&[*byte]).await.unwrap();
+                sleep(timing.char_time()).await;
+            }
+            sleep(silence).await;
+            // Followed by a valid response
+            tx.write_all(&encode_response(Response::Custom(0x41, vec![0x05])))
+                .await
+                .unwrap();
+        });
+
+        let adu = framed.next().await.unwrap().unwrap();
+        assert_eq!(
+            adu.pdu.0,
+            Ok(Response::Custom(0x41, vec![0x01, 0x02, 0x03]))
+        );
+        let adu = framed.next().await.unwrap().unwrap();
+        assert_eq!(adu.pdu.0, Ok(Response::Custom(0x41, vec![0x05])));
+        assert!(framed.next().await.is_none());
+
+        // Both the noise and the interrupted response have been dropped,
+        // although the latter has a valid CRC
+        let error_counters = framed.decoder().error_counters();
+        assert_eq!(error_counters.dropped_bytes(), 2 + 5);
+        assert_eq!(error_counters.crc_errors(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn mark_frame_interrupted_by_more_than_t1_5() {
+        let timing = FrameTiming::new(1200, DataBits::Eight, Parity::None, StopBits::One);
+        let (mut tx, rx) = tokio::io::duplex(1024);
+        let frame_boundaries = FrameBoundaries::default();
+        let mut rx = SilenceDelimited::new(rx, timing, frame_boundaries.clone());
+
+        tokio::spawn(async move {
+            tx.write_all(&[0x01, 0x41]).await.unwrap();
+            // Less than t3.5, i.e. the frame continues
+            sleep(timing.char_time() * 3).await;
+            tx.write_all(&[0x04]).await.unwrap();
+            sleep(timing.t3_5() * 2).await;
+        });
+
+        let mut frame = [0; 3];
+        rx.read_exact(&mut frame).await.unwrap();
+        let boundary = frame_boundaries.pop().unwrap();
+        assert_eq!(boundary.len, 3);
+        assert!(boundary.interrupted);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn accept_frames_received_in_chunks() {
+        let timing = FrameTiming::new(1200, DataBits::Eight, Parity::None, StopBits::One);
+        let (mut tx, rx) = tokio::io::duplex(1024);
+        let frame_boundaries = FrameBoundaries::default();
+        let mut framed = FramedRead::new(
+            SilenceDelimited::new(rx, timing, frame_boundaries.clone()),
+            ClientCodec::with_frame_boundaries(frame_boundaries),
+        );
+
+        tokio::spawn(async move {
+            // The driver delivers the characters of a response in chunks
+            // as soon as they have been transmitted. The time between
+            // the chunks exceeds t1.5, but not t3.5.
+            let frame = encode_response(Response::Custom(0x41, (1..=8).collect()));
+            for chunk in frame.chunks(2) {
+                sleep(timing.char_time() * 2).await;
+                tx.write_all(chunk).await.unwrap();
+            }
+        });
+
+        let adu = framed.next().await.unwrap().unwrap();
+        assert_eq!(adu.pdu.0, Ok(Response::Custom(0x41, (1..=8).collect())));
+        assert!(framed.next().await.is_none());
+        assert_eq!(framed.decoder().error_counters().dropped_bytes(), 0);
+    }
+}
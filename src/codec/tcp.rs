@@ -6,7 +6,7 @@ use super::*;
 use crate::frame::tcp::*;
 
 use byteorder::{BigEndian, ByteOrder};
-use bytes::{BufMut, Bytes, BytesMut};
+use bytes::{Buf as _, BufMut, Bytes, BytesMut};
 use std::io::{Error, ErrorKind, Result};
 use tokio_util::codec::{Decoder, Encoder};
 
@@ -14,88 +14,171 @@ const HEADER_LEN: usize = 7;
 
 const PROTOCOL_ID: u16 = 0x0000; // TCP
 
-#[derive(Debug, PartialEq)]
-pub(crate) struct AduDecoder;
-
-#[derive(Debug, PartialEq)]
-pub(crate) struct ClientCodec {
-    pub(crate) decoder: AduDecoder,
+/// The maximum length of a PDU according to the spec.
+const MAX_PDU_LEN: usize = 253;
+
+/// A frame that violates the Modbus TCP protocol.
+#[cfg_attr(
+    not(any(
+        feature = "tcp-server-unstable",
+        all(feature = "server", feature = "udp")
+    )),
+    allow(dead_code)
+)]
+#[derive(Debug)]
+pub(crate) struct FrameViolation {
+    pub(crate) hdr: Header,
+    protocol_id: u16,
+    /// The function code of the PDU, if any
+    function: Option<FunctionCode>,
+    pub(crate) error: Error,
 }
 
-impl Default for ClientCodec {
-    fn default() -> Self {
-        Self {
-            decoder: AduDecoder,
+#[cfg_attr(
+    not(any(
+        feature = "tcp-server-unstable",
+        all(feature = "server", feature = "udp")
+    )),
+    allow(dead_code)
+)]
+impl FrameViolation {
+    /// The function code for an exception response, if the frame
+    /// may be answered at all.
+    ///
+    /// Frames of other protocols and exception function codes are
+    /// never answered.
+    pub(crate) fn exception_function(&self) -> Option<FunctionCode> {
+        if self.protocol_id != PROTOCOL_ID {
+            return None;
         }
+        self.function.filter(|function| *function < 0x80)
     }
 }
 
-#[derive(Debug, PartialEq)]
-pub(crate) struct ServerCodec {
+impl From<FrameViolation> for Error {
+    fn from(from: FrameViolation) -> Self {
+        from.error
+    }
+}
+
+/// A decoded request or a frame that violates the protocol.
+#[cfg_attr(
+    not(any(
+        feature = "tcp-server-unstable",
+        all(feature = "server", feature = "udp")
+    )),
+    allow(dead_code)
+)]
+#[derive(Debug)]
+pub(crate) enum DecodedRequest {
+    Request(RequestAdu),
+    Violation(FrameViolation),
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct AduDecoder {
+    /// The number of bytes of an invalid frame that still need to be skipped
+    skip_len: usize,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct ClientCodec {
     pub(crate) decoder: AduDecoder,
 }
 
-impl Default for ServerCodec {
-    fn default() -> Self {
-        Self {
-            decoder: AduDecoder,
-        }
-    }
+#[cfg_attr(
+    not(any(
+        feature = "tcp-server-unstable",
+        all(feature = "server", feature = "udp")
+    )),
+    allow(dead_code)
+)]
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct ServerCodec {
+    pub(crate) decoder: AduDecoder,
 }
 
-impl Decoder for AduDecoder {
-    type Item = (Header, Bytes);
-    type Error = Error;
+impl AduDecoder {
+    fn skip(&mut self, buf: &mut BytesMut) {
+        let len = self.skip_len.min(buf.len());
+        buf.advance(len);
+        self.skip_len -= len;
+    }
 
+    /// Decode the next frame.
+    ///
+    /// Invalid frames are skipped according to the length in their header
+    /// without buffering them. The length is limited by the spec, i.e. peers
+    /// cannot force the decoder to buffer more than a single valid frame.
     #[allow(clippy::assertions_on_constants)]
-    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<(Header, Bytes)>> {
-        if buf.len() < HEADER_LEN {
+    fn decode_frame(
+        &mut self,
+        buf: &mut BytesMut,
+    ) -> std::result::Result<Option<(Header, Bytes)>, FrameViolation> {
+        self.skip(buf);
+        if self.skip_len > 0 || buf.len() < HEADER_LEN {
             return Ok(None);
         }
 
-        debug_assert!(HEADER_LEN >= 6);
+        debug_assert!(HEADER_LEN > 6);
+        let transaction_id = BigEndian::read_u16(&buf[0..2]);
+        let protocol_id = BigEndian::read_u16(&buf[2..4]);
+        // len = bytes of PDU + one byte (unit ID)
         let len = usize::from(BigEndian::read_u16(&buf[4..6]));
-        let pdu_len = if len > 0 {
-            // len = bytes of PDU + one byte (unit ID)
-            len - 1
+        let unit_id = buf[6];
+        let hdr = Header {
+            transaction_id,
+            unit_id,
+        };
+
+        let error = if !(2..=MAX_PDU_LEN + 1).contains(&len) {
+            Some(format!("Invalid data length: {}", len))
+        } else if protocol_id != PROTOCOL_ID {
+            Some(format!(
+                "Invalid protocol identifier: expected = {}, actual = {}",
+                PROTOCOL_ID, protocol_id
+            ))
         } else {
-            return Err(Error::new(
-                ErrorKind::InvalidData,
-                format!("Invalid data length: {}", len),
-            ));
+            None
         };
-        if buf.len() < HEADER_LEN + pdu_len {
-            return Ok(None);
+        if let Some(error) = error {
+            let function = if len > 1 {
+                let Some(&function) = buf.get(HEADER_LEN) else {
+                    // Wait for the function code
+                    return Ok(None);
+                };
+                Some(function)
+            } else {
+                None
+            };
+            buf.advance(HEADER_LEN);
+            self.skip_len = len.saturating_sub(1);
+            self.skip(buf);
+            return Err(FrameViolation {
+                hdr,
+                protocol_id,
+                function,
+                error: protocol_violation(error),
+            });
         }
 
-        let header_data = buf.split_to(HEADER_LEN);
-
-        debug_assert!(HEADER_LEN >= 4);
-        let protocol_id = BigEndian::read_u16(&header_data[2..4]);
-        if protocol_id != PROTOCOL_ID {
-            return Err(Error::new(
-                ErrorKind::InvalidData,
-                format!(
-                    "Invalid protocol identifier: expected = {}, actual = {}",
-                    PROTOCOL_ID, protocol_id
-                ),
-            ));
+        let pdu_len = len - 1;
+        if buf.len() < HEADER_LEN + pdu_len {
+            return Ok(None);
         }
+        buf.advance(HEADER_LEN);
+        let pdu_data = buf.split_to(pdu_len).freeze();
 
-        debug_assert!(HEADER_LEN >= 2);
-        let transaction_id = BigEndian::read_u16(&header_data[0..2]);
-
-        debug_assert!(HEADER_LEN > 6);
-        let unit_id = header_data[6];
-
-        let header = Header {
-            transaction_id,
-            unit_id,
-        };
+        Ok(Some((hdr, pdu_data)))
+    }
+}
 
-        let pdu_data = buf.split_to(pdu_len).freeze();
+impl Decoder for AduDecoder {
+    type Item = (Header, Bytes);
+    type Error = Error;
 
-        Ok(Some((header, pdu_data)))
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<(Header, Bytes)>> {
+        self.decode_frame(buf).map_err(Into::into)
     }
 }
 
@@ -114,20 +197,30 @@ impl Decoder for ClientCodec {
 }
 
 impl Decoder for ServerCodec {
-    type Item = RequestAdu;
+    type Item = DecodedRequest;
     type Error = Error;
 
-    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<RequestAdu>> {
-        if let Some((hdr, pdu_data)) = self.decoder.decode(buf)? {
-            let pdu = RequestPdu::try_from(pdu_data)?;
-            Ok(Some(RequestAdu {
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<DecodedRequest>> {
+        let (hdr, pdu_data) = match self.decoder.decode_frame(buf) {
+            Ok(Some(frame)) => frame,
+            Ok(None) => return Ok(None),
+            Err(violation) => return Ok(Some(DecodedRequest::Violation(violation))),
+        };
+        let function = pdu_data.first().copied();
+        let decoded = match RequestPdu::try_from(pdu_data) {
+            Ok(pdu) => DecodedRequest::Request(RequestAdu {
                 hdr,
                 pdu,
                 disconnect: false,
-            }))
-        } else {
-            Ok(None)
-        }
+            }),
+            Err(error) => DecodedRequest::Violation(FrameViolation {
+                hdr,
+                protocol_id: PROTOCOL_ID,
+                function,
+                error,
+            }),
+        };
+        Ok(Some(decoded))
     }
 }
 
@@ -145,6 +238,7 @@ impl Encoder<RequestAdu> for ClientCodec {
             ));
         }
         let RequestAdu { hdr, pdu, .. } = adu;
+        check_request_len(&pdu.0)?;
         let pdu_data: Bytes = pdu.into();
         buf.reserve(pdu_data.len() + 7);
         buf.put_u16(hdr.transaction_id);
@@ -268,6 +362,28 @@ mod tests {
             assert!(format!("{}", err).contains("Invalid protocol identifier"));
         }
 
+        #[test]
+        fn decode_with_oversized_length() {
+            let mut codec = ClientCodec::default();
+            let mut buf = BytesMut::from(
+                &[
+                    TRANSACTION_ID_HI,
+                    TRANSACTION_ID_LO,
+                    PROTOCOL_ID_HI,
+                    PROTOCOL_ID_LO,
+                    0xFF, // length HI
+                    0xFF, // length LO
+                    UNIT_ID,
+                    0x03, // function code
+                ][..],
+            );
+            let err = codec.decode(&mut buf).err().unwrap();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+            assert!(format!("{}", err).contains("Invalid data length"));
+            // The frame is skipped without being buffered
+            assert!(buf.is_empty());
+        }
+
         #[test]
         fn encode_read_request() {
             let mut codec = ClientCodec::default();
@@ -318,5 +434,130 @@ mod tests {
             }
             assert!(codec.encode(adu, &mut buf).is_ok());
         }
+
+        #[test]
+        fn reject_request_with_oversized_byte_count() {
+            let mut codec = ClientCodec::default();
+            let hdr = Header {
+                transaction_id: TRANSACTION_ID,
+                unit_id: UNIT_ID,
+            };
+            let mut buf = BytesMut::new();
+            // 128 registers need 256 bytes
+            let adu = RequestAdu {
+                hdr,
+                pdu: Request::WriteMultipleRegisters(0x00, vec![0; 128]).into(),
+                disconnect: false,
+            };
+            let err = codec.encode(adu, &mut buf).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidInput);
+            assert!(matches!(
+                crate::Error::from(err),
+                crate::Error::InvalidRequest(_)
+            ));
+            assert!(buf.is_empty());
+
+            let adu = RequestAdu {
+                hdr,
+                pdu: Request::WriteMultipleRegisters(0x00, vec![0; 127]).into(),
+                disconnect: false,
+            };
+            assert!(codec.encode(adu, &mut buf).is_ok());
+        }
+    }
+
+    mod server {
+
+        use super::*;
+
+        fn decode_all(codec: &mut ServerCodec, buf: &mut BytesMut) -> Vec<DecodedRequest> {
+            let mut decoded = Vec::new();
+            while let Some(item) = codec.decode(buf).unwrap() {
+                decoded.push(item);
+            }
+            decoded
+        }
+
+        #[test]
+        fn skip_frame_with_oversized_length_across_reads() {
+            let mut codec = ServerCodec::default();
+            // 1 byte unit ID + 299 bytes PDU
+            let mut buf = BytesMut::from(&[0x00, 0x01, 0x00, 0x00, 0x01, 0x2C, 0x01, 0x10][..]);
+            let decoded = decode_all(&mut codec, &mut buf);
+            let [DecodedRequest::Violation(violation)] = &decoded[..] else {
+                panic!("unexpected decoded requests: {decoded:?}");
+            };
+            assert_eq!(violation.hdr.transaction_id, 0x0001);
+            assert_eq!(violation.function, Some(0x10));
+            assert!(buf.is_empty());
+
+            // The remainder of the frame arrives in chunks
+            buf.extend_from_slice(&[0xAA; 200]);
+            assert!(decode_all(&mut codec, &mut buf).is_empty());
+            assert!(buf.is_empty());
+            buf.extend_from_slice(&[0xAA; 98]);
+            // Followed by a valid request
+            buf.extend_from_slice(&[
+                0x00, 0x02, 0x00, 0x00, 0x00, 0x06, 0x01, 0x03, 0x00, 0x00, 0x00, 0x01,
+            ]);
+            let decoded = decode_all(&mut codec, &mut buf);
+            let [DecodedRequest::Request(adu)] = &decoded[..] else {
+                panic!("unexpected decoded requests: {decoded:?}");
+            };
+            assert_eq!(adu.hdr.transaction_id, 0x0002);
+            assert_eq!(adu.pdu.0, Request::ReadHoldingRegisters(0x0000, 1));
+        }
+
+        #[test]
+        fn report_frame_without_function_code() {
+            let mut codec = ServerCodec::default();
+            let mut buf = BytesMut::from(&[0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x01][..]);
+            let decoded = decode_all(&mut codec, &mut buf);
+            let [DecodedRequest::Violation(violation)] = &decoded[..] else {
+                panic!("unexpected decoded requests: {decoded:?}");
+            };
+            assert_eq!(violation.function, None);
+            assert!(buf.is_empty());
+        }
+
+        #[test]
+        fn report_malformed_pdu_with_function_code() {
+            let mut codec = ServerCodec::default();
+            // Read holding registers without quantity
+            let mut buf =
+                BytesMut::from(&[0x00, 0x01, 0x00, 0x00, 0x00, 0x04, 0x01, 0x03, 0x00, 0x00][..]);
+            let decoded = decode_all(&mut codec, &mut buf);
+            let [DecodedRequest::Violation(violation)] = &decoded[..] else {
+                panic!("unexpected decoded requests: {decoded:?}");
+            };
+            assert_eq!(violation.function, Some(0x03));
+            assert_eq!(violation.hdr.unit_id, 0x01);
+            assert_eq!(violation.exception_function(), Some(0x03));
+            assert!(buf.is_empty());
+        }
+
+        #[test]
+        fn do_not_answer_other_protocols_and_exception_functions() {
+            let mut codec = ServerCodec::default();
+            let mut buf = BytesMut::from(
+                &[
+                    // Invalid protocol id
+                    0x00, 0x01, 0x00, 0x01, 0x00, 0x06, 0x01, 0x03, 0x00, 0x00, 0x00, 0x01,
+                    // Exception function code
+                    0x00, 0x02, 0x00, 0x00, 0x00, 0x03, 0x01, 0x83, 0x02,
+                ][..],
+            );
+            let decoded = decode_all(&mut codec, &mut buf);
+            let [DecodedRequest::Violation(other_protocol), DecodedRequest::Violation(exception)] =
+                &decoded[..]
+            else {
+                panic!("unexpected decoded requests: {decoded:?}");
+            };
+            assert_eq!(other_protocol.function, Some(0x03));
+            assert_eq!(other_protocol.exception_function(), None);
+            assert_eq!(exception.function, Some(0x83));
+            assert_eq!(exception.exception_function(), None);
+            assert!(buf.is_empty());
+        }
     }
 }
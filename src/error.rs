@@ -0,0 +1,223 @@
+// SPDX-FileCopyrightText: Copyright (c) 2017-2022 slowtec GmbH <post@slowtec.de>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Modbus errors
+
+use std::{error, fmt, io};
+
+use crate::frame::ExceptionResponse;
+
+/// A Modbus error.
+#[derive(Debug)]
+pub enum Error {
+    /// The transport failed, e.g. the connection has been closed.
+    Transport(io::Error),
+
+    /// No response has been received in time.
+    Timeout,
+
+    /// The server (slave) responded with an exception.
+    Exception(ExceptionResponse),
+
+    /// A frame violated the protocol, e.g. an invalid header,
+    /// checksum, or a malformed PDU.
+    ProtocolViolation(String),
+
+    /// A valid response that does not match the request.
+    UnexpectedResponse(String),
+
+    /// The request has been rejected before sending it, e.g. a
+    /// broadcast of a request that reads data.
+    InvalidRequest(String),
+}
+
+/// The class of an [`Error`], i.e. its variant without any details.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorClass {
+    /// See [`Error::Transport`].
+    Transport,
+
+    /// See [`Error::Timeout`].
+    Timeout,
+
+    /// See [`Error::Exception`].
+    Exception,
+
+    /// See [`Error::ProtocolViolation`].
+    ProtocolViolation,
+
+    /// See [`Error::UnexpectedResponse`].
+    UnexpectedResponse,
+
+    /// See [`Error::InvalidRequest`].
+    InvalidRequest,
+}
+
+impl Error {
+    /// The class of the error.
+    #[must_use]
+    pub fn class(&self) -> ErrorClass {
+        match self {
+            Self::Transport(_) => ErrorClass::Transport,
+            Self::Timeout => ErrorClass::Timeout,
+            Self::Exception(_) => ErrorClass::Exception,
+            Self::ProtocolViolation(_) => ErrorClass::ProtocolViolation,
+            Self::UnexpectedResponse(_) => ErrorClass::UnexpectedResponse,
+            Self::InvalidRequest(_) => ErrorClass::InvalidRequest,
+        }
+    }
+
+    /// The exception, if the server (slave) responded with one.
+    #[must_use]
+    pub fn exception(&self) -> Option<&ExceptionResponse> {
+        match self {
+            Self::Exception(exception) => Some(exception),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn unexpected_response() -> Self {
+        Self::UnexpectedResponse("unexpected response".to_owned())
+    }
+
+    pub(crate) fn invalid_response() -> Self {
+        Self::UnexpectedResponse("invalid response".to_owned())
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Transport(err) => write!(f, "Transport error: {}", err),
+            Self::Timeout => write!(f, "Timeout"),
+            Self::Exception(exception) => write!(f, "{}", exception),
+            Self::ProtocolViolation(msg) => write!(f, "Protocol violation: {}", msg),
+            Self::UnexpectedResponse(msg) => write!(f, "Unexpected response: {}", msg),
+            Self::InvalidRequest(msg) => write!(f, "Invalid request: {}", msg),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Transport(err) => Some(err),
+            Self::Exception(exception) => Some(exception),
+            _ => None,
+        }
+    }
+}
+
+impl From<ExceptionResponse> for Error {
+    fn from(from: ExceptionResponse) -> Self {
+        Self::Exception(from)
+    }
+}
+
+/// A protocol violation that has been detected by the codecs of
+/// this crate.
+///
+/// Distinguishes decoding errors from `InvalidData` errors of other
+/// origins, e.g. of the underlying transport.
+#[derive(Debug)]
+struct ProtocolViolation(String);
+
+impl fmt::Display for ProtocolViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl error::Error for ProtocolViolation {}
+
+/// Create the `io::Error` of a codec for a frame that violates the protocol.
+pub(crate) fn protocol_violation(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, ProtocolViolation(msg.into()))
+}
+
+/// Classifies errors of the codecs and transports that are
+/// reported as `io::Error`.
+///
+/// Only decoding errors of the codecs of this crate are classified as
+/// [`Error::ProtocolViolation`]. Errors that have been converted
+/// into `io::Error` before are restored.
+impl From<io::Error> for Error {
+    fn from(from: io::Error) -> Self {
+        let from = match from.downcast::<Self>() {
+            Ok(err) => return err,
+            Err(from) => from,
+        };
+        let from = match from.downcast::<ExceptionResponse>() {
+            Ok(exception) => return Self::Exception(exception),
+            Err(from) => from,
+        };
+        let from = match from.downcast::<ProtocolViolation>() {
+            Ok(ProtocolViolation(msg)) => return Self::ProtocolViolation(msg),
+            Err(from) => from,
+        };
+        match from.kind() {
+            io::ErrorKind::TimedOut => Self::Timeout,
+            _ => Self::Transport(from),
+        }
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(from: Error) -> Self {
+        match from {
+            Error::Transport(err) => err,
+            Error::Timeout => io::Error::new(io::ErrorKind::TimedOut, from),
+            Error::Exception(exception) => io::Error::other(exception),
+            Error::ProtocolViolation(_) | Error::UnexpectedResponse(_) => {
+                io::Error::new(io::ErrorKind::InvalidData, from)
+            }
+            Error::InvalidRequest(_) => io::Error::new(io::ErrorKind::InvalidInput, from),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::frame::Exception;
+
+    #[test]
+    fn convert_from_and_into_io_error() {
+        let exception = ExceptionResponse {
+            function: 0x03,
+            exception: Exception::IllegalDataAddress,
+        };
+        let err = io::Error::from(Error::Exception(exception));
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+        assert_eq!(Error::from(err).exception(), Some(&exception));
+
+        let err = io::Error::from(Error::invalid_response());
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(matches!(Error::from(err), Error::UnexpectedResponse(_)));
+
+        let err = protocol_violation("Invalid CRC");
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let Error::ProtocolViolation(msg) = Error::from(err) else {
+            panic!("unexpected error");
+        };
+        assert_eq!(msg, "Invalid CRC");
+
+        // Only errors of the codecs are protocol violations
+        let err = io::Error::new(
+            io::ErrorKind::InvalidData,
+            "stream did not contain valid UTF-8",
+        );
+        assert!(matches!(Error::from(err), Error::Transport(_)));
+
+        let err = io::Error::from(Error::Timeout);
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(matches!(Error::from(err), Error::Timeout));
+
+        let err = Error::from(io::Error::from(io::ErrorKind::BrokenPipe));
+        let Error::Transport(err) = err else {
+            panic!("unexpected error: {err}");
+        };
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+    }
+}
@@ -0,0 +1,24 @@
+// SPDX-FileCopyrightText: Copyright (c) 2017-2022 slowtec GmbH <post@slowtec.de>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use super::*;
+
+use crate::slave::SlaveId;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Header {
+    pub(crate) slave_id: SlaveId,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct RequestAdu {
+    pub(crate) hdr: Header,
+    pub(crate) pdu: RequestPdu,
+    pub(crate) disconnect: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ResponseAdu {
+    pub(crate) hdr: Header,
+    pub(crate) pdu: ResponsePdu,
+}
@@ -1,13 +1,16 @@
 // SPDX-FileCopyrightText: Copyright (c) 2017-2022 slowtec GmbH <post@slowtec.de>
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
+#[cfg(feature = "ascii")]
+pub(crate) mod ascii;
+
 #[cfg(feature = "rtu")]
 pub(crate) mod rtu;
 
-#[cfg(feature = "tcp")]
+#[cfg(any(feature = "tcp", feature = "udp"))]
 pub(crate) mod tcp;
 
-use std::{error, fmt};
+use std::{collections::BTreeMap, error, fmt};
 
 /// A Modbus function code is represented by an unsigned 8 bit integer.
 pub(crate) type FunctionCode = u8;
@@ -82,6 +85,58 @@ pub enum Request {
     /// The fourth parameter is the vector of values to write to the registers.
     ReadWriteMultipleRegisters(Address, Quantity, Address, Vec<Word>),
 
+    /// A request to read the eight exception status outputs of a serial line device (0x07).
+    ReadExceptionStatus,
+
+    /// A request to read the status word and the event counter
+    /// of the communications event log of a serial line device (0x0B).
+    GetCommEventCounter,
+
+    /// A request to read the communications event log of a serial line device (0x0C).
+    GetCommEventLog,
+
+    /// A request to read the identification and run status of a serial line device (0x11).
+    ReportServerId,
+
+    /// A request to perform a diagnostics sub-function on a serial line device (0x08).
+    /// The first parameter is the sub-function.
+    /// The second parameter is the data of the sub-function, usually a
+    /// single word. [`DiagnosticsSubFunction::ReturnQueryData`] accepts
+    /// data of any length that is echoed by the device, except for
+    /// Modbus RTU where requests are framed by their function code
+    /// and must contain a single word.
+    ///
+    /// [`DiagnosticsSubFunction::ForceListenOnlyMode`] is not answered
+    /// by the device. The client does not wait for a response and
+    /// returns an echo of the request instead.
+    Diagnostics(DiagnosticsSubFunction, Vec<Word>),
+
+    /// A request to read records of files (0x14).
+    /// The parameter contains the records to read.
+    ReadFileRecord(Vec<ReadFileRecordSubRequest>),
+
+    /// A request to write records of files (0x15).
+    /// The parameter contains the records and their data to write.
+    WriteFileRecord(Vec<WriteFileRecordSubRequest>),
+
+    /// A request to modify the contents of a holding register using
+    /// a combination of an AND mask and an OR mask (0x16).
+    /// The first parameter is the address of the holding register.
+    /// The second parameter is the AND mask.
+    /// The third parameter is the OR mask.
+    ///
+    /// The result is `(current AND and_mask) OR (or_mask AND (NOT and_mask))`.
+    MaskWriteRegister(Address, Word, Word),
+
+    /// A request to read the contents of a First-In-First-Out queue of registers (0x18).
+    /// The parameter is the address of the FIFO pointer register.
+    ReadFifoQueue(Address),
+
+    /// A request to read the identification of a device (0x2B / MEI 0x0E).
+    /// The first parameter is the category of objects to read.
+    /// The second parameter is the id of the first object to read.
+    ReadDeviceIdentification(ReadDeviceIdCode, DeviceIdObject),
+
     /// A raw Modbus request.
     /// The first parameter is the Modbus function code.
     /// The second parameter is the raw bytes of the request.
@@ -149,30 +204,575 @@ pub enum Response {
     /// The parameter contains the register values that have been read as part of the read instruction
     ReadWriteMultipleRegisters(Vec<Word>),
 
+    /// Response to a ReadExceptionStatus request
+    /// The parameter contains the states of the eight exception status outputs
+    ReadExceptionStatus(u8),
+
+    /// Response to a GetCommEventCounter request
+    /// The first parameter contains the status word, `0xFFFF` if a previous command is still being processed
+    /// The second parameter contains the event counter
+    GetCommEventCounter(Word, Word),
+
+    /// Response to a GetCommEventLog request
+    /// The parameter contains the communications event log
+    GetCommEventLog(CommEventLog),
+
+    /// Response to a ReportServerId request
+    /// The first parameter contains the server id
+    /// The second parameter contains the run indicator status
+    /// The third parameter contains additional, device specific data
+    ReportServerId(u8, bool, Vec<u8>),
+
+    /// Response to a Diagnostics request
+    /// The first parameter contains the echoed sub-function
+    /// The second parameter contains the echoed data or the requested value, e.g. a counter
+    Diagnostics(DiagnosticsSubFunction, Vec<Word>),
+
+    /// Response to a ReadFileRecord request
+    /// The parameter contains the register values of each requested record
+    ReadFileRecord(Vec<Vec<Word>>),
+
+    /// Response to a WriteFileRecord request
+    /// The parameter contains the records and data that have been written
+    WriteFileRecord(Vec<WriteFileRecordSubRequest>),
+
+    /// Response to a MaskWriteRegister request
+    /// The first parameter contains the address of the holding register
+    /// The second parameter contains the AND mask
+    /// The third parameter contains the OR mask
+    MaskWriteRegister(Address, Word, Word),
+
+    /// Response to a ReadFifoQueue request
+    /// The parameter contains the register values of the queue
+    ReadFifoQueue(Vec<Word>),
+
+    /// Response to a ReadDeviceIdentification request
+    /// The parameter contains the objects that have been read
+    ReadDeviceIdentification(DeviceIdentification),
+
     /// Response to a raw Modbus request
     /// The first parameter contains the returned Modbus function code
     /// The second parameter contains the bytes read following the function code
     Custom(FunctionCode, Vec<u8>),
 }
 
+impl Request {
+    /// Check if the server answers the request.
+    #[cfg_attr(
+        not(any(feature = "rtu", feature = "ascii", feature = "tcp", feature = "udp")),
+        allow(dead_code)
+    )]
+    pub(crate) fn expects_response(&self) -> bool {
+        !matches!(
+            self,
+            Request::Diagnostics(DiagnosticsSubFunction::ForceListenOnlyMode, _)
+        )
+    }
+
+    /// Check if the request only reads data, i.e. if it could be
+    /// repeated without side effects.
+    pub(crate) fn is_read_only(&self) -> bool {
+        use DiagnosticsSubFunction::*;
+        match self {
+            Request::ReadCoils(..)
+            | Request::ReadDiscreteInputs(..)
+            | Request::ReadInputRegisters(..)
+            | Request::ReadHoldingRegisters(..)
+            | Request::ReadExceptionStatus
+            | Request::GetCommEventCounter
+            | Request::GetCommEventLog
+            | Request::ReportServerId
+            | Request::ReadFileRecord(_)
+            | Request::ReadFifoQueue(_)
+            | Request::ReadDeviceIdentification(..) => true,
+            Request::Diagnostics(sub_function, _) => matches!(
+                sub_function,
+                ReturnQueryData
+                    | ReturnDiagnosticRegister
+                    | ReturnBusMessageCount
+                    | ReturnBusCommunicationErrorCount
+                    | ReturnBusExceptionErrorCount
+                    | ReturnServerMessageCount
+                    | ReturnServerNoResponseCount
+                    | ReturnServerNakCount
+                    | ReturnServerBusyCount
+                    | ReturnBusCharacterOverrunCount
+            ),
+            Request::WriteSingleCoil(..)
+            | Request::WriteMultipleCoils(..)
+            | Request::WriteSingleRegister(..)
+            | Request::WriteMultipleRegisters(..)
+            | Request::ReadWriteMultipleRegisters(..)
+            | Request::WriteFileRecord(_)
+            | Request::MaskWriteRegister(..)
+            | Request::Custom(..)
+            | Request::Disconnect => false,
+        }
+    }
+
+    /// The response that is returned to the caller for requests
+    /// that are never answered by the server.
+    #[cfg_attr(
+        not(any(feature = "rtu", feature = "ascii", feature = "tcp", feature = "udp")),
+        allow(dead_code)
+    )]
+    pub(crate) fn implicit_response(&self) -> Option<Response> {
+        match self {
+            Request::Diagnostics(sub_function, data) if !self.expects_response() => {
+                Some(Response::Diagnostics(*sub_function, data.clone()))
+            }
+            _ => None,
+        }
+    }
+
+    /// The response to a broadcast of the request, which is not
+    /// answered by any server. Only requests that write data could
+    /// be broadcast.
+    #[cfg_attr(
+        not(any(feature = "rtu", feature = "ascii", feature = "tcp")),
+        allow(dead_code)
+    )]
+    pub(crate) fn broadcast_response(&self) -> Option<Response> {
+        let res = match self {
+            Request::WriteSingleCoil(address, coil) => Response::WriteSingleCoil(*address, *coil),
+            Request::WriteMultipleCoils(address, coils) => {
+                Response::WriteMultipleCoils(*address, u16::try_from(coils.len()).ok()?)
+            }
+            Request::WriteSingleRegister(address, word) => {
+                Response::WriteSingleRegister(*address, *word)
+            }
+            Request::WriteMultipleRegisters(address, words) => {
+                Response::WriteMultipleRegisters(*address, u16::try_from(words.len()).ok()?)
+            }
+            Request::MaskWriteRegister(address, and_mask, or_mask) => {
+                Response::MaskWriteRegister(*address, *and_mask, *or_mask)
+            }
+            Request::WriteFileRecord(records) => Response::WriteFileRecord(records.clone()),
+            Request::Diagnostics(sub_function, data) if !self.is_read_only() => {
+                Response::Diagnostics(*sub_function, data.clone())
+            }
+            _ => return None,
+        };
+        Some(res)
+    }
+}
+
+/// The communications event log of a serial line device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommEventLog {
+    /// The status word, `0xFFFF` if a previous command is still being processed.
+    pub status: Word,
+
+    /// The event counter, i.e. the number of successfully completed messages.
+    pub event_count: Word,
+
+    /// The number of messages that have been processed since the
+    /// last restart, clear counters operation, or power-up.
+    pub message_count: Word,
+
+    /// Up to 64 events, the most recent event first.
+    pub events: Vec<u8>,
+}
+
+/// A record of a file to read.
+///
+/// Records are addressed by their number (`0` - `9999`) within a
+/// file and contain registers, i.e. 16 bit words.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadFileRecordSubRequest {
+    /// The number of the file.
+    pub file_number: Word,
+
+    /// The number of the first record to read.
+    pub record_number: Word,
+
+    /// The number of registers to read.
+    pub record_length: Quantity,
+}
+
+/// A record of a file to write.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WriteFileRecordSubRequest {
+    /// The number of the file.
+    pub file_number: Word,
+
+    /// The number of the first record to write.
+    pub record_number: Word,
+
+    /// The registers to write.
+    pub record_data: Vec<Word>,
+}
+
+/// A sub-function of the Diagnostics function (0x08).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DiagnosticsSubFunction {
+    /// Echo the data of the request (`0x00`).
+    ReturnQueryData,
+    /// Restart the serial line port and leave the listen only mode (`0x01`).
+    ///
+    /// The communications event log is cleared if the data is `0xFF00`.
+    RestartCommunicationsOption,
+    /// Return the contents of the diagnostic register (`0x02`).
+    ReturnDiagnosticRegister,
+    /// Change the end of message delimiter of Modbus ASCII to
+    /// the character in the high byte of the data (`0x03`).
+    ChangeAsciiInputDelimiter,
+    /// Stop answering requests until communications are restarted (`0x04`).
+    ForceListenOnlyMode,
+    /// Clear all counters and the diagnostic register (`0x0A`).
+    ClearCountersAndDiagnosticRegister,
+    /// Return the number of messages detected on the bus (`0x0B`).
+    ReturnBusMessageCount,
+    /// Return the number of CRC errors (`0x0C`).
+    ReturnBusCommunicationErrorCount,
+    /// Return the number of exception responses (`0x0D`).
+    ReturnBusExceptionErrorCount,
+    /// Return the number of messages addressed to the server (`0x0E`).
+    ReturnServerMessageCount,
+    /// Return the number of messages that have not been answered (`0x0F`).
+    ReturnServerNoResponseCount,
+    /// Return the number of *Negative Acknowledge* exception responses (`0x10`).
+    ReturnServerNakCount,
+    /// Return the number of *Server Device Busy* exception responses (`0x11`).
+    ReturnServerBusyCount,
+    /// Return the number of character overrun errors (`0x12`).
+    ReturnBusCharacterOverrunCount,
+    /// Clear the character overrun counter and error flag (`0x14`).
+    ClearOverrunCounterAndFlag,
+    /// Any other, reserved sub-function.
+    Reserved(ReservedSubFunction),
+}
+
+/// A sub-function code that has no dedicated variant in
+/// [`DiagnosticsSubFunction`].
+///
+/// Known sub-function codes are always represented by their variants.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ReservedSubFunction(u16);
+
+impl ReservedSubFunction {
+    /// The reserved sub-function code, or `None` if the code has
+    /// a dedicated variant in [`DiagnosticsSubFunction`].
+    #[must_use]
+    pub fn new(code: u16) -> Option<Self> {
+        match DiagnosticsSubFunction::from(code) {
+            DiagnosticsSubFunction::Reserved(reserved) => Some(reserved),
+            _ => None,
+        }
+    }
+
+    /// The raw sub-function code.
+    #[must_use]
+    pub const fn code(self) -> u16 {
+        self.0
+    }
+}
+
+impl From<u16> for DiagnosticsSubFunction {
+    fn from(from: u16) -> Self {
+        use DiagnosticsSubFunction::*;
+        match from {
+            0x00 => ReturnQueryData,
+            0x01 => RestartCommunicationsOption,
+            0x02 => ReturnDiagnosticRegister,
+            0x03 => ChangeAsciiInputDelimiter,
+            0x04 => ForceListenOnlyMode,
+            0x0A => ClearCountersAndDiagnosticRegister,
+            0x0B => ReturnBusMessageCount,
+            0x0C => ReturnBusCommunicationErrorCount,
+            0x0D => ReturnBusExceptionErrorCount,
+            0x0E => ReturnServerMessageCount,
+            0x0F => ReturnServerNoResponseCount,
+            0x10 => ReturnServerNakCount,
+            0x11 => ReturnServerBusyCount,
+            0x12 => ReturnBusCharacterOverrunCount,
+            0x14 => ClearOverrunCounterAndFlag,
+            _ => Reserved(ReservedSubFunction(from)),
+        }
+    }
+}
+
+impl From<DiagnosticsSubFunction> for u16 {
+    fn from(from: DiagnosticsSubFunction) -> Self {
+        use DiagnosticsSubFunction::*;
+        match from {
+            ReturnQueryData => 0x00,
+            RestartCommunicationsOption => 0x01,
+            ReturnDiagnosticRegister => 0x02,
+            ChangeAsciiInputDelimiter => 0x03,
+            ForceListenOnlyMode => 0x04,
+            ClearCountersAndDiagnosticRegister => 0x0A,
+            ReturnBusMessageCount => 0x0B,
+            ReturnBusCommunicationErrorCount => 0x0C,
+            ReturnBusExceptionErrorCount => 0x0D,
+            ReturnServerMessageCount => 0x0E,
+            ReturnServerNoResponseCount => 0x0F,
+            ReturnServerNakCount => 0x10,
+            ReturnServerBusyCount => 0x11,
+            ReturnBusCharacterOverrunCount => 0x12,
+            ClearOverrunCounterAndFlag => 0x14,
+            Reserved(reserved) => reserved.code(),
+        }
+    }
+}
+
+/// The category of device identification objects to read,
+/// i.e. the *Read Device ID code*.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ReadDeviceIdCode {
+    /// Stream access to the basic objects.
+    Basic = 0x01,
+    /// Stream access to the basic and regular objects.
+    Regular = 0x02,
+    /// Stream access to all objects.
+    Extended = 0x03,
+    /// Individual access to a single object.
+    Specific = 0x04,
+}
+
+impl From<ReadDeviceIdCode> for u8 {
+    fn from(from: ReadDeviceIdCode) -> Self {
+        from as u8
+    }
+}
+
+/// The id of a device identification object.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DeviceIdObject {
+    /// Basic object (mandatory)
+    VendorName,
+    /// Basic object (mandatory)
+    ProductCode,
+    /// Basic object (mandatory)
+    MajorMinorRevision,
+    /// Regular object (optional)
+    VendorUrl,
+    /// Regular object (optional)
+    ProductName,
+    /// Regular object (optional)
+    ModelName,
+    /// Regular object (optional)
+    UserApplicationName,
+    /// Regular object that is reserved for future use (`0x07` - `0x7F`)
+    Reserved(ReservedObjectId),
+    /// Extended, device dependent object (`0x80` - `0xFF`)
+    Private(PrivateObjectId),
+}
+
+/// The id of a device identification object that is reserved for
+/// future use (`0x07` - `0x7F`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ReservedObjectId(u8);
+
+impl ReservedObjectId {
+    /// The reserved object id, or `None` if the id is not
+    /// within the reserved range.
+    #[must_use]
+    pub const fn new(id: u8) -> Option<Self> {
+        match id {
+            0x07..=0x7F => Some(Self(id)),
+            _ => None,
+        }
+    }
+
+    /// The raw object id.
+    #[must_use]
+    pub const fn id(self) -> u8 {
+        self.0
+    }
+}
+
+/// The id of an extended, device dependent identification object
+/// (`0x80` - `0xFF`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PrivateObjectId(u8);
+
+impl PrivateObjectId {
+    /// The private object id, or `None` if the id is not
+    /// within the private range.
+    #[must_use]
+    pub const fn new(id: u8) -> Option<Self> {
+        match id {
+            0x80..=0xFF => Some(Self(id)),
+            _ => None,
+        }
+    }
+
+    /// The raw object id.
+    #[must_use]
+    pub const fn id(self) -> u8 {
+        self.0
+    }
+}
+
+impl From<u8> for DeviceIdObject {
+    fn from(from: u8) -> Self {
+        use DeviceIdObject::*;
+        match from {
+            0x00 => VendorName,
+            0x01 => ProductCode,
+            0x02 => MajorMinorRevision,
+            0x03 => VendorUrl,
+            0x04 => ProductName,
+            0x05 => ModelName,
+            0x06 => UserApplicationName,
+            0x07..=0x7F => Reserved(ReservedObjectId(from)),
+            0x80..=0xFF => Private(PrivateObjectId(from)),
+        }
+    }
+}
+
+impl From<DeviceIdObject> for u8 {
+    fn from(from: DeviceIdObject) -> Self {
+        use DeviceIdObject::*;
+        match from {
+            VendorName => 0x00,
+            ProductCode => 0x01,
+            MajorMinorRevision => 0x02,
+            VendorUrl => 0x03,
+            ProductName => 0x04,
+            ModelName => 0x05,
+            UserApplicationName => 0x06,
+            Reserved(id) => id.id(),
+            Private(id) => id.id(),
+        }
+    }
+}
+
+/// The identification conformity level of a device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConformityLevel {
+    /// The category of objects that is supported by the device.
+    ///
+    /// Either [`ReadDeviceIdCode::Basic`], [`ReadDeviceIdCode::Regular`],
+    /// or [`ReadDeviceIdCode::Extended`].
+    pub category: ReadDeviceIdCode,
+
+    /// Individual access to objects is supported by the device.
+    pub individual_access: bool,
+}
+
+impl From<ConformityLevel> for u8 {
+    fn from(from: ConformityLevel) -> Self {
+        let individual_access = if from.individual_access { 0x80 } else { 0x00 };
+        u8::from(from.category) | individual_access
+    }
+}
+
+/// The identification objects of a device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceIdentification {
+    /// The category of objects that has been requested.
+    pub read_device_id_code: ReadDeviceIdCode,
+
+    /// The conformity level of the device.
+    pub conformity_level: ConformityLevel,
+
+    /// More objects are available and need to be requested,
+    /// starting with `next_object`.
+    pub more_follows: bool,
+
+    /// The first object of the next request if `more_follows`.
+    pub next_object: DeviceIdObject,
+
+    /// The values of the objects.
+    pub objects: BTreeMap<DeviceIdObject, Vec<u8>>,
+}
+
+impl DeviceIdentification {
+    fn text(&self, object: DeviceIdObject) -> Option<&str> {
+        self.objects
+            .get(&object)
+            .and_then(|value| std::str::from_utf8(value).ok())
+    }
+
+    /// The vendor name, if available as text.
+    #[must_use]
+    pub fn vendor_name(&self) -> Option<&str> {
+        self.text(DeviceIdObject::VendorName)
+    }
+
+    /// The product code, if available as text.
+    #[must_use]
+    pub fn product_code(&self) -> Option<&str> {
+        self.text(DeviceIdObject::ProductCode)
+    }
+
+    /// The major and minor revision, if available as text.
+    #[must_use]
+    pub fn major_minor_revision(&self) -> Option<&str> {
+        self.text(DeviceIdObject::MajorMinorRevision)
+    }
+}
+
 /// A server (slave) exception.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-#[repr(u8)]
+#[non_exhaustive]
 pub enum Exception {
-    IllegalFunction = 0x01,
-    IllegalDataAddress = 0x02,
-    IllegalDataValue = 0x03,
-    ServerDeviceFailure = 0x04,
-    Acknowledge = 0x05,
-    ServerDeviceBusy = 0x06,
-    MemoryParityError = 0x08,
-    GatewayPathUnavailable = 0x0A,
-    GatewayTargetDevice = 0x0B,
+    IllegalFunction,
+    IllegalDataAddress,
+    IllegalDataValue,
+    ServerDeviceFailure,
+    Acknowledge,
+    ServerDeviceBusy,
+    NegativeAcknowledge,
+    MemoryParityError,
+    GatewayPathUnavailable,
+    GatewayTargetDevice,
+    /// Any other, e.g. vendor-specific exception code.
+    Custom(CustomExceptionCode),
+}
+
+/// An exception code that has no dedicated variant in [`Exception`].
+///
+/// It can only be obtained by converting the code into an [`Exception`],
+/// i.e. known exception codes are always represented by their variants.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CustomExceptionCode(u8);
+
+impl CustomExceptionCode {
+    /// The raw exception code.
+    #[must_use]
+    pub const fn code(self) -> u8 {
+        self.0
+    }
+}
+
+impl From<u8> for Exception {
+    fn from(from: u8) -> Self {
+        use Exception::*;
+        match from {
+            0x01 => IllegalFunction,
+            0x02 => IllegalDataAddress,
+            0x03 => IllegalDataValue,
+            0x04 => ServerDeviceFailure,
+            0x05 => Acknowledge,
+            0x06 => ServerDeviceBusy,
+            0x07 => NegativeAcknowledge,
+            0x08 => MemoryParityError,
+            0x0A => GatewayPathUnavailable,
+            0x0B => GatewayTargetDevice,
+            _ => Custom(CustomExceptionCode(from)),
+        }
+    }
 }
 
 impl From<Exception> for u8 {
     fn from(from: Exception) -> Self {
-        from as u8
+        use Exception::*;
+        match from {
+            IllegalFunction => 0x01,
+            IllegalDataAddress => 0x02,
+            IllegalDataValue => 0x03,
+            ServerDeviceFailure => 0x04,
+            Acknowledge => 0x05,
+            ServerDeviceBusy => 0x06,
+            NegativeAcknowledge => 0x07,
+            MemoryParityError => 0x08,
+            GatewayPathUnavailable => 0x0A,
+            GatewayTargetDevice => 0x0B,
+            Custom(code) => code.code(),
+        }
     }
 }
 
@@ -187,14 +787,19 @@ impl Exception {
             ServerDeviceFailure => "Server device failure",
             Acknowledge => "Acknowledge",
             ServerDeviceBusy => "Server device busy",
+            NegativeAcknowledge => "Negative acknowledge",
             MemoryParityError => "Memory parity error",
             GatewayPathUnavailable => "Gateway path unavailable",
             GatewayTargetDevice => "Gateway target device failed to respond",
+            Custom(_) => "Unknown exception",
         }
     }
 }
 
 /// A server (slave) exception response.
+///
+/// The function code is the one of the request, i.e. without the
+/// exception bit (`0x80`).
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct ExceptionResponse {
     pub function: FunctionCode,
@@ -235,7 +840,7 @@ impl From<ExceptionResponse> for ResponsePdu {
 
 impl From<Result<Response, ExceptionResponse>> for ResponsePdu {
     fn from(from: Result<Response, ExceptionResponse>) -> Self {
-        ResponsePdu(from.map(Into::into).map_err(Into::into))
+        ResponsePdu(from)
     }
 }
 
@@ -247,6 +852,9 @@ impl From<ResponsePdu> for Result<Response, ExceptionResponse> {
 
 impl fmt::Display for Exception {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Self::Custom(code) = self {
+            return write!(f, "{} (0x{:0>2X})", self.description(), code.code());
+        }
         write!(f, "{}", self.description())
     }
 }
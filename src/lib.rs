@@ -22,6 +22,9 @@
 
 pub mod prelude;
 
+mod error;
+pub use self::error::{Error, ErrorClass};
+
 pub mod client;
 
 pub mod slave;
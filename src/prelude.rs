@@ -14,6 +14,12 @@ pub mod sync {
     pub use crate::client::sync::*;
 }
 
+#[allow(missing_docs)]
+#[cfg(feature = "ascii")]
+pub mod ascii {
+    pub use crate::client::ascii::*;
+}
+
 #[allow(missing_docs)]
 #[cfg(feature = "rtu")]
 pub mod rtu {
@@ -29,14 +35,31 @@ pub mod tcp {
     pub use crate::server::*;
 }
 
+#[allow(missing_docs)]
+#[cfg(feature = "tls")]
+pub mod tls {
+    pub use crate::client::tls::*;
+}
+
+#[allow(missing_docs)]
+#[cfg(feature = "udp")]
+pub mod udp {
+    pub use crate::client::udp::*;
+}
+
 #[cfg(feature = "server")]
 pub use crate::server;
 
 ///////////////////////////////////////////////////////////////////
 /// Structs
 ///////////////////////////////////////////////////////////////////
-pub use crate::frame::{Request, Response};
-pub use crate::slave::{Slave, SlaveId};
+pub use crate::frame::{
+    CommEventLog, ConformityLevel, DeviceIdObject, DeviceIdentification, DiagnosticsSubFunction,
+    PrivateObjectId, ReadDeviceIdCode, ReadFileRecordSubRequest, Request, ReservedObjectId,
+    ReservedSubFunction, Response, WriteFileRecordSubRequest,
+};
+pub use crate::slave::{BroadcastIds, Slave, SlaveId};
+pub use crate::{Error, ErrorClass};
 
 ///////////////////////////////////////////////////////////////////
 /// Traits
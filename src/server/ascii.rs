@@ -0,0 +1,150 @@
+// SPDX-FileCopyrightText: Copyright (c) 2017-2022 slowtec GmbH <post@slowtec.de>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Modbus ASCII server skeleton
+
+use crate::{
+    codec,
+    frame::*,
+    server::service::{process_broadcast, response_pdu, NewService, Service},
+    slave::Slave,
+};
+use futures::{select, Future, FutureExt as _};
+use futures_util::{SinkExt as _, StreamExt as _};
+use log::{error, trace};
+use std::{io::Error, path::Path};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_serial::SerialStream;
+use tokio_util::codec::Framed;
+
+#[derive(Debug)]
+pub struct Server {
+    serial: SerialStream,
+}
+
+impl Server {
+    /// set up a new Server instance from an interface path and baud rate
+    pub fn new_from_path<P: AsRef<Path>>(p: P, baud_rate: u32) -> Result<Self, Error> {
+        let serial =
+            SerialStream::open(&tokio_serial::new(p.as_ref().to_string_lossy(), baud_rate))?;
+        Ok(Server { serial })
+    }
+
+    /// set up a new Server instance based on a pre-configured SerialStream instance
+    #[must_use]
+    pub fn new(serial: SerialStream) -> Self {
+        Server { serial }
+    }
+
+    /// serve Modbus ASCII requests based on the provided service until it finishes
+    pub async fn serve_forever<S>(self, new_service: S)
+    where
+        S: NewService<Request = Request, Response = Response> + Send + Sync + 'static,
+        S::Error: Into<crate::Error>,
+        S::Instance: 'static + Send + Sync,
+    {
+        self.serve_until(new_service, futures::future::pending())
+            .await;
+    }
+
+    /// serve Modbus ASCII requests based on the provided service until it finishes or a shutdown signal is received
+    pub async fn serve_until<S, Sd>(self, new_service: S, shutdown_signal: Sd)
+    where
+        S: NewService<Request = Request, Response = Response> + Send + Sync + 'static,
+        Sd: Future<Output = ()> + Sync + Send + Unpin + 'static,
+        S::Request: From<Request>,
+        S::Response: Into<Response>,
+        S::Error: Into<crate::Error>,
+        S::Instance: Send + Sync + 'static,
+    {
+        let framed = Framed::new(self.serial, codec::ascii::ServerCodec::default());
+        let service = new_service.new_service().unwrap();
+        let future = process(framed, service);
+
+        let mut server = Box::pin(future).fuse();
+        let mut shutdown = shutdown_signal.fuse();
+
+        async {
+            select! {
+                res = server => if let Err(e) = res {
+                    error!("error: {}", e);
+                },
+                _ = shutdown => trace!("Shutdown signal received")
+            }
+        }
+        .await;
+    }
+}
+
+/// frame wrapper around the underlying service's responses to forwarded requests
+async fn process<T, S>(
+    mut framed: Framed<T, codec::ascii::ServerCodec>,
+    service: S,
+) -> Result<(), Error>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+    S: Service<Request = Request, Response = Response> + Send + Sync + 'static,
+    S::Error: Into<crate::Error>,
+{
+    loop {
+        let request = match framed.next().await {
+            // Stream is exhausted
+            None => break,
+            Some(request) => request,
+        }?;
+
+        let hdr = request.hdr;
+        if Slave(hdr.slave_id).is_broadcast() {
+            // Broadcasts are never answered
+            process_broadcast(&service, request.pdu.0).await?;
+            continue;
+        }
+        let expects_response = request.pdu.0.expects_response();
+        let pdu = response_pdu(service.call(request.pdu.0).await)?;
+        if !expects_response {
+            continue;
+        }
+        framed.send(ascii::ResponseAdu { hdr, pdu }).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::client::Reader as _;
+
+    use futures::future;
+
+    struct DummyService;
+
+    impl Service for DummyService {
+        type Request = Request;
+        type Response = Response;
+        type Error = Error;
+        type Future = future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn call(&self, req: Self::Request) -> Self::Future {
+            match req {
+                Request::ReadHoldingRegisters(addr, cnt) => future::ready(Ok(
+                    Response::ReadHoldingRegisters((addr..addr + cnt).collect()),
+                )),
+                _ => unimplemented!(),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn serve_client_requests() {
+        let (client_io, server_io) = tokio::io::duplex(1024);
+        let framed = Framed::new(server_io, codec::ascii::ServerCodec::default());
+        tokio::spawn(process(framed, DummyService));
+
+        let mut ctx = crate::client::ascii::connect_slave(client_io, Slave(0x11))
+            .await
+            .unwrap();
+        let rsp = ctx.read_holding_registers(0x006B, 3).await.unwrap();
+        assert_eq!(rsp, vec![0x006B, 0x006C, 0x006D]);
+    }
+}
@@ -6,12 +6,27 @@
 // TODO: Add missing documentation
 #![allow(missing_docs)]
 
+#[cfg(feature = "ascii")]
+pub mod ascii;
+
 #[cfg(feature = "rtu")]
 pub mod rtu;
 
+#[cfg(all(feature = "rtu", feature = "tcp-server-unstable"))]
+pub mod rtu_over_tcp;
+
 #[cfg(feature = "tcp-server-unstable")]
 pub mod tcp;
 
+#[cfg(all(feature = "tls", feature = "tcp-server-unstable"))]
+pub mod tls;
+
+#[cfg(feature = "udp")]
+pub mod udp;
+
 mod service;
 
+#[cfg(any(feature = "tcp-server-unstable", feature = "udp"))]
+mod violation;
+
 pub use service::{NewService, Service};
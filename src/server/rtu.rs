@@ -6,17 +6,24 @@
 use crate::{
     codec,
     frame::*,
-    server::service::{NewService, Service},
+    server::service::{process_broadcast, response_pdu, NewService, Service},
+    slave::Slave,
 };
 use futures::{select, Future, FutureExt as _};
 use futures_util::{SinkExt as _, StreamExt as _};
 use std::{io::Error, path::Path};
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio_serial::SerialStream;
 use tokio_util::codec::Framed;
 
+pub use crate::codec::rtu::{ErrorCounters, FrameTiming, LengthResolver, LengthResolvers};
+
 #[derive(Debug)]
 pub struct Server {
     serial: SerialStream,
+    length_resolvers: LengthResolvers,
+    frame_timing: Option<FrameTiming>,
+    error_counters: ErrorCounters,
 }
 
 impl Server {
@@ -24,20 +31,50 @@ impl Server {
     pub fn new_from_path<P: AsRef<Path>>(p: P, baud_rate: u32) -> Result<Self, Error> {
         let serial =
             SerialStream::open(&tokio_serial::new(p.as_ref().to_string_lossy(), baud_rate))?;
-        Ok(Server { serial })
+        Ok(Self::new(serial))
     }
 
     /// set up a new Server instance based on a pre-configured SerialStream instance
     #[must_use]
     pub fn new(serial: SerialStream) -> Self {
-        Server { serial }
+        Server {
+            serial,
+            length_resolvers: LengthResolvers::default(),
+            frame_timing: None,
+            error_counters: ErrorCounters::default(),
+        }
+    }
+
+    /// Resolve the lengths of requests with custom or vendor-specific
+    /// function codes by the `length_resolvers`.
+    #[must_use]
+    pub fn with_length_resolvers(mut self, length_resolvers: LengthResolvers) -> Self {
+        self.length_resolvers = length_resolvers;
+        self
+    }
+
+    /// Delimit the received frames by silent intervals on the serial
+    /// line instead of their expected length.
+    ///
+    /// The length resolvers are not needed in this mode.
+    #[must_use]
+    pub fn with_frame_timing(mut self, frame_timing: FrameTiming) -> Self {
+        self.frame_timing = Some(frame_timing);
+        self
+    }
+
+    /// The counters of bus errors that are detected while decoding
+    /// requests. They can be read while the server is running.
+    #[must_use]
+    pub fn error_counters(&self) -> ErrorCounters {
+        self.error_counters.clone()
     }
 
     /// serve Modbus RTU requests based on the provided service until it finishes
     pub async fn serve_forever<S>(self, new_service: S)
     where
         S: NewService<Request = Request, Response = Response> + Send + Sync + 'static,
-        S::Error: Into<Error>,
+        S::Error: Into<crate::Error>,
         S::Instance: 'static + Send + Sync,
     {
         self.serve_until(new_service, futures::future::pending())
@@ -51,14 +88,27 @@ impl Server {
         Sd: Future<Output = ()> + Sync + Send + Unpin + 'static,
         S::Request: From<Request>,
         S::Response: Into<Response>,
-        S::Error: Into<Error>,
+        S::Error: Into<crate::Error>,
         S::Instance: Send + Sync + 'static,
     {
-        let framed = Framed::new(self.serial, codec::rtu::ServerCodec::default());
         let service = new_service.new_service().unwrap();
-        let future = process(framed, service);
+        let future = if let Some(frame_timing) = self.frame_timing {
+            let frame_boundaries = codec::rtu::FrameBoundaries::default();
+            let serial = codec::rtu::SilenceDelimited::new(
+                self.serial,
+                frame_timing,
+                frame_boundaries.clone(),
+            );
+            let codec = codec::rtu::ServerCodec::with_frame_boundaries(frame_boundaries)
+                .with_error_counters(self.error_counters);
+            process(Framed::new(serial, codec), service).boxed()
+        } else {
+            let codec = codec::rtu::ServerCodec::with_length_resolvers(self.length_resolvers)
+                .with_error_counters(self.error_counters);
+            process(Framed::new(self.serial, codec), service).boxed()
+        };
 
-        let mut server = Box::pin(future).fuse();
+        let mut server = future.fuse();
         let mut shutdown = shutdown_signal.fuse();
 
         async {
@@ -74,13 +124,14 @@ impl Server {
 }
 
 /// frame wrapper around the underlying service's responses to forwarded requests
-async fn process<S>(
-    mut framed: Framed<SerialStream, codec::rtu::ServerCodec>,
+pub(crate) async fn process<T, S>(
+    mut framed: Framed<T, codec::rtu::ServerCodec>,
     service: S,
 ) -> Result<(), Error>
 where
+    T: AsyncRead + AsyncWrite + Unpin,
     S: Service<Request = Request, Response = Response> + Send + Sync + 'static,
-    S::Error: Into<Error>,
+    S::Error: Into<crate::Error>,
 {
     loop {
         let request = match framed.next().await {
@@ -90,13 +141,203 @@ where
         }?;
 
         let hdr = request.hdr;
-        let response = service.call(request.pdu.0).await.map_err(Into::into)?;
-        framed
-            .send(rtu::ResponseAdu {
-                hdr,
-                pdu: response.into(),
-            })
-            .await?;
+        if Slave(hdr.slave_id).is_broadcast() {
+            // Broadcasts are never answered
+            process_broadcast(&service, request.pdu.0).await?;
+            continue;
+        }
+        let expects_response = request.pdu.0.expects_response();
+        let pdu = response_pdu(service.call(request.pdu.0).await)?;
+        if !expects_response {
+            continue;
+        }
+        framed.send(rtu::ResponseAdu { hdr, pdu }).await?;
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        client::{Reader as _, Writer as _},
+        slave::SlaveContext as _,
+    };
+
+    use futures::future;
+    use std::{
+        collections::HashMap,
+        sync::{
+            atomic::{AtomicU16, Ordering},
+            Mutex,
+        },
+    };
+
+    #[derive(Default)]
+    struct DiagnosticsService {
+        server_message_count: AtomicU16,
+    }
+
+    impl Service for DiagnosticsService {
+        type Request = Request;
+        type Response = Response;
+        type Error = Error;
+        type Future = future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn call(&self, req: Self::Request) -> Self::Future {
+            let count = self.server_message_count.fetch_add(1, Ordering::Relaxed) + 1;
+            match req {
+                Request::Diagnostics(DiagnosticsSubFunction::ReturnServerMessageCount, _) => {
+                    future::ready(Ok(Response::Diagnostics(
+                        DiagnosticsSubFunction::ReturnServerMessageCount,
+                        vec![count],
+                    )))
+                }
+                Request::Diagnostics(sub_function, data) => {
+                    future::ready(Ok(Response::Diagnostics(sub_function, data)))
+                }
+                _ => unimplemented!(),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn do_not_answer_in_listen_only_mode() {
+        let (client_io, server_io) = tokio::io::duplex(1024);
+        let framed = Framed::new(server_io, codec::rtu::ServerCodec::default());
+        tokio::spawn(process(framed, DiagnosticsService::default()));
+
+        let mut ctx = crate::client::rtu::connect_slave(client_io, Slave(0x11))
+            .await
+            .unwrap();
+        let rsp = ctx
+            .diagnostics(DiagnosticsSubFunction::ForceListenOnlyMode, &[0x0000])
+            .await
+            .unwrap();
+        assert_eq!(rsp, vec![0x0000]);
+        let rsp = ctx
+            .diagnostics(DiagnosticsSubFunction::ReturnServerMessageCount, &[0x0000])
+            .await
+            .unwrap();
+        assert_eq!(rsp, vec![2]);
+    }
+
+    /// Files of registers that grow on demand.
+    #[derive(Default)]
+    struct FileService {
+        files: Mutex<HashMap<Word, Vec<Word>>>,
+    }
+
+    impl Service for FileService {
+        type Request = Request;
+        type Response = Response;
+        type Error = Error;
+        type Future = future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn call(&self, req: Self::Request) -> Self::Future {
+            let mut files = self.files.lock().unwrap();
+            match req {
+                Request::ReadFileRecord(sub_requests) => {
+                    let records = sub_requests
+                        .iter()
+                        .map(|sub_request| {
+                            let file = files.entry(sub_request.file_number).or_default();
+                            let start = usize::from(sub_request.record_number);
+                            let end = start + usize::from(sub_request.record_length);
+                            if file.len() < end {
+                                file.resize(end, 0);
+                            }
+                            file[start..end].to_vec()
+                        })
+                        .collect();
+                    future::ready(Ok(Response::ReadFileRecord(records)))
+                }
+                Request::WriteFileRecord(sub_requests) => {
+                    for sub_request in &sub_requests {
+                        let file = files.entry(sub_request.file_number).or_default();
+                        let start = usize::from(sub_request.record_number);
+                        let end = start + sub_request.record_data.len();
+                        if file.len() < end {
+                            file.resize(end, 0);
+                        }
+                        file[start..end].copy_from_slice(&sub_request.record_data);
+                    }
+                    future::ready(Ok(Response::WriteFileRecord(sub_requests)))
+                }
+                _ => unimplemented!(),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn read_and_write_file_records() {
+        let (client_io, server_io) = tokio::io::duplex(1024);
+        let framed = Framed::new(server_io, codec::rtu::ServerCodec::default());
+        tokio::spawn(process(framed, FileService::default()));
+
+        let mut ctx = crate::client::rtu::connect_slave(client_io, Slave(0x11))
+            .await
+            .unwrap();
+        ctx.write_file_record(&[
+            WriteFileRecordSubRequest {
+                file_number: 4,
+                record_number: 7,
+                record_data: vec![0x06AF, 0x04BE, 0x100D],
+            },
+            WriteFileRecordSubRequest {
+                file_number: 5,
+                record_number: 0,
+                record_data: vec![0x1234],
+            },
+        ])
+        .await
+        .unwrap();
+        let records = ctx
+            .read_file_record(&[
+                ReadFileRecordSubRequest {
+                    file_number: 4,
+                    record_number: 8,
+                    record_length: 2,
+                },
+                ReadFileRecordSubRequest {
+                    file_number: 5,
+                    record_number: 0,
+                    record_length: 2,
+                },
+            ])
+            .await
+            .unwrap();
+        assert_eq!(records, vec![vec![0x04BE, 0x100D], vec![0x1234, 0x0000]]);
+    }
+
+    #[tokio::test]
+    async fn process_broadcast_without_response() {
+        let (client_io, server_io) = tokio::io::duplex(1024);
+        let framed = Framed::new(server_io, codec::rtu::ServerCodec::default());
+        tokio::spawn(process(framed, FileService::default()));
+
+        let mut ctx = crate::client::rtu::connect_slave(client_io, Slave::broadcast())
+            .await
+            .unwrap();
+        // Does not wait for a response
+        ctx.write_file_record(&[WriteFileRecordSubRequest {
+            file_number: 1,
+            record_number: 0,
+            record_data: vec![0x1234],
+        }])
+        .await
+        .unwrap();
+        let sub_request = ReadFileRecordSubRequest {
+            file_number: 1,
+            record_number: 0,
+            record_length: 1,
+        };
+        let err = ctx.read_file_record(&[sub_request]).await.unwrap_err();
+        assert!(matches!(err, crate::Error::InvalidRequest(_)));
+
+        ctx.set_slave(Slave(0x11));
+        let records = ctx.read_file_record(&[sub_request]).await.unwrap();
+        assert_eq!(records, vec![vec![0x1234]]);
+    }
+}
@@ -0,0 +1,163 @@
+// SPDX-FileCopyrightText: Copyright (c) 2017-2022 slowtec GmbH <post@slowtec.de>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Modbus RTU over TCP server skeleton
+//!
+//! Serves raw RTU frames including the trailing CRC over TCP
+//! connections instead of MBAP framed TCP messages, e.g. for
+//! clients that are connected through a serial device server.
+
+use crate::{
+    codec,
+    frame::*,
+    server::{rtu::process, service::NewService},
+};
+
+use futures::{self, Future};
+use futures_util::future::FutureExt as _;
+use log::{error, trace};
+use std::{io::Error, net::SocketAddr, sync::Arc};
+use tokio::net::TcpListener;
+use tokio_util::codec::Framed;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Server {
+    socket_addr: SocketAddr,
+}
+
+impl Server {
+    /// Set the address for the server (mandatory).
+    #[must_use]
+    pub fn new(socket_addr: SocketAddr) -> Self {
+        Self { socket_addr }
+    }
+
+    /// Start an async Modbus RTU over TCP server task.
+    pub async fn serve<S>(&self, service: S) -> Result<(), Error>
+    where
+        S: NewService<Request = Request, Response = Response> + Send + Sync + 'static,
+        S::Request: From<Request>,
+        S::Response: Into<Response>,
+        S::Error: Into<crate::Error>,
+        S::Instance: Send + Sync + 'static,
+    {
+        let service = Arc::new(service);
+        let listener = TcpListener::bind(self.socket_addr).await?;
+
+        loop {
+            let (stream, _) = listener.accept().await?;
+            let framed = Framed::new(stream, codec::rtu::ServerCodec::default());
+            let new_service = service.clone();
+
+            tokio::spawn(Box::pin(async move {
+                let service = new_service.new_service().unwrap();
+                if let Err(err) = process(framed, service).await {
+                    error!("{:?}", err);
+                }
+            }));
+        }
+    }
+
+    /// Start a Modbus RTU over TCP server that blocks the current thread until a shutdown is requested
+    pub fn serve_until<S, Sd>(self, service: S, shutdown_signal: Sd)
+    where
+        S: NewService<Request = Request, Response = Response> + Send + Sync + 'static,
+        Sd: Future<Output = ()> + Sync + Send + Unpin + 'static,
+        S::Request: From<Request>,
+        S::Response: Into<Response>,
+        S::Error: Into<crate::Error>,
+        S::Instance: Send + Sync + 'static,
+    {
+        let shutdown_signal = shutdown_signal.fuse();
+        let rt = tokio::runtime::Builder::new_multi_thread()
+            .enable_io()
+            .build()
+            .unwrap();
+
+        rt.block_on(async {
+            tokio::select! {
+                res = self.serve(service) => if let Err(e) = res { error!("error: {}", e) },
+                _ = shutdown_signal => trace!("Shutdown signal received")
+            }
+        })
+    }
+
+    pub fn serve_forever<S>(self, service: S)
+    where
+        S: NewService<Request = Request, Response = Response> + Send + Sync + 'static,
+        S::Request: From<Request>,
+        S::Response: Into<Response>,
+        S::Error: Into<crate::Error>,
+        S::Instance: Send + Sync + 'static,
+    {
+        self.serve_until(service, futures::future::pending())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{client::Reader as _, prelude::Slave, server::Service};
+
+    use futures::future;
+
+    struct DummyService;
+
+    impl Service for DummyService {
+        type Request = Request;
+        type Response = Response;
+        type Error = Error;
+        type Future = future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn call(&self, req: Self::Request) -> Self::Future {
+            match req {
+                Request::ReadInputRegisters(addr, cnt) => future::ready(Ok(
+                    Response::ReadInputRegisters((addr..addr + cnt).collect()),
+                )),
+                _ => unimplemented!(),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn serve_rtu_over_tcp_client() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let socket_addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let framed = Framed::new(stream, codec::rtu::ServerCodec::default());
+            process(framed, DummyService).await.unwrap();
+        });
+
+        let mut ctx = crate::client::rtu::connect_rtu_over_tcp(socket_addr, Slave(0x12))
+            .await
+            .unwrap();
+        let rsp = ctx.read_input_registers(0x0010, 3).await.unwrap();
+        assert_eq!(rsp, vec![0x0010, 0x0011, 0x0012]);
+    }
+
+    #[tokio::test]
+    async fn serve_connections_of_rtu_over_tcp_clients() {
+        // Bind to an ephemeral port before spawning the server
+        let socket_addr = {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            listener.local_addr().unwrap()
+        };
+        let server = Server::new(socket_addr);
+        tokio::spawn(async move { server.serve(|| Ok(DummyService)).await });
+        // Wait until the server is listening
+        while tokio::net::TcpStream::connect(socket_addr).await.is_err() {
+            tokio::task::yield_now().await;
+        }
+
+        // Each connection is served independently
+        for slave in [Slave(0x01), Slave(0x02)] {
+            let mut ctx = crate::client::rtu::connect_rtu_over_tcp(socket_addr, slave)
+                .await
+                .unwrap();
+            let rsp = ctx.read_input_registers(0x0020, 2).await.unwrap();
+            assert_eq!(rsp, vec![0x0020, 0x0021]);
+        }
+    }
+}
@@ -108,3 +108,47 @@ impl<S: Service + ?Sized + 'static> Service for Arc<S> {
         (**self).call(request)
     }
 }
+
+/// Convert the result of a service call into a response PDU.
+///
+/// Exceptions are sent back to the client, all other errors
+/// terminate the connection.
+#[cfg(any(
+    feature = "rtu",
+    feature = "ascii",
+    feature = "udp",
+    feature = "tcp-server-unstable"
+))]
+pub(crate) fn response_pdu<E>(
+    result: Result<crate::frame::Response, E>,
+) -> io::Result<crate::frame::ResponsePdu>
+where
+    E: Into<crate::Error>,
+{
+    match result.map_err(Into::into) {
+        Ok(response) => Ok(response.into()),
+        Err(crate::Error::Exception(exception)) => Ok(exception.into()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Process a request that has been broadcast to all servers (slaves)
+/// without responding.
+///
+/// Only requests that write data are processed, all other requests
+/// are ignored.
+#[cfg(any(feature = "rtu", feature = "ascii"))]
+pub(crate) async fn process_broadcast<S>(
+    service: &S,
+    request: crate::frame::Request,
+) -> io::Result<()>
+where
+    S: Service<Request = crate::frame::Request, Response = crate::frame::Response>,
+    S::Error: Into<crate::Error>,
+{
+    if request.broadcast_response().is_none() {
+        log::debug!("Ignoring broadcast of {:?}", request);
+        return Ok(());
+    }
+    response_pdu(service.call(request).await).map(drop)
+}
@@ -4,33 +4,88 @@
 //! Modbus TCP server skeleton
 
 use crate::{
-    codec,
+    codec::{
+        self,
+        tcp::{DecodedRequest, FrameViolation},
+    },
     frame::*,
-    server::service::{NewService, Service},
+    server::service::{response_pdu, NewService, Service},
+    slave::{BroadcastIds, Slave},
 };
 
+pub use crate::server::violation::{ViolationCounter, ViolationPolicy};
+
 use futures::{self, Future};
 use futures_util::{future::FutureExt as _, sink::SinkExt as _, stream::StreamExt as _};
-use log::{error, trace};
+use log::{debug, error, trace, warn};
 use socket2::{Domain, Socket, Type};
-use std::{
-    io::{self, Error},
-    net::SocketAddr,
-    sync::Arc,
+use std::{io, net::SocketAddr, sync::Arc};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpListener,
 };
-use tokio::net::{TcpListener, TcpStream};
 use tokio_util::codec::Framed;
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+/// The settings of a server that apply to each of its connections.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ConnectionConfig {
+    pub(crate) violation_policy: ViolationPolicy,
+    pub(crate) violation_counter: ViolationCounter,
+    pub(crate) broadcast_ids: BroadcastIds,
+}
+
+#[derive(Debug, Clone)]
 pub struct Server {
     socket_addr: SocketAddr,
+    connection: ConnectionConfig,
 }
 
 impl Server {
     /// Set the address for the server (mandatory).
     #[must_use]
     pub fn new(socket_addr: SocketAddr) -> Self {
-        Self { socket_addr }
+        Self {
+            socket_addr,
+            connection: ConnectionConfig::default(),
+        }
+    }
+
+    /// Handle frames that violate the protocol according to the policy
+    /// (optional). The connection is closed by default.
+    #[must_use]
+    pub fn with_violation_policy(self, violation_policy: ViolationPolicy) -> Self {
+        Self {
+            connection: ConnectionConfig {
+                violation_policy,
+                ..self.connection
+            },
+            ..self
+        }
+    }
+
+    /// Process requests to the given unit ids as broadcasts without
+    /// responding (optional).
+    ///
+    /// Empty by default, i.e. requests are answered regardless of
+    /// their unit id. Gateways that forward requests to a serial line
+    /// might need [`BroadcastIds::SERIAL_LINE`], some of them also
+    /// [`Slave::tcp_device()`].
+    #[must_use]
+    pub fn with_broadcast_ids(self, broadcast_ids: BroadcastIds) -> Self {
+        Self {
+            connection: ConnectionConfig {
+                broadcast_ids,
+                ..self.connection
+            },
+            ..self
+        }
+    }
+
+    /// The counter of frames that violated the protocol. It can be
+    /// read while the server is running.
+    #[must_use]
+    pub fn violation_counter(&self) -> ViolationCounter {
+        self.connection.violation_counter.clone()
     }
 
     /// Start an async Modbus TCP server task.
@@ -39,20 +94,21 @@ impl Server {
         S: NewService<Request = Request, Response = Response> + Send + Sync + 'static,
         S::Request: From<Request>,
         S::Response: Into<Response>,
-        S::Error: Into<Error>,
+        S::Error: Into<crate::Error>,
         S::Instance: Send + Sync + 'static,
     {
         let service = Arc::new(service);
         let listener = TcpListener::bind(self.socket_addr).await?;
 
         loop {
-            let (stream, _) = listener.accept().await?;
+            let (stream, peer_addr) = listener.accept().await?;
             let framed = Framed::new(stream, codec::tcp::ServerCodec::default());
             let new_service = service.clone();
+            let connection = self.connection.clone();
 
             tokio::spawn(Box::pin(async move {
                 let service = new_service.new_service().unwrap();
-                if let Err(err) = process(framed, service).await {
+                if let Err(err) = process(framed, peer_addr, &connection, service, Ok).await {
                     eprintln!("{:?}", err);
                 }
             }));
@@ -66,7 +122,7 @@ impl Server {
         Sd: Future<Output = ()> + Sync + Send + Unpin + 'static,
         S::Request: From<Request>,
         S::Response: Into<Response>,
-        S::Error: Into<Error>,
+        S::Error: Into<crate::Error>,
         S::Instance: Send + Sync + 'static,
     {
         let shutdown_signal = shutdown_signal.fuse();
@@ -88,21 +144,29 @@ impl Server {
         S: NewService<Request = Request, Response = Response> + Send + Sync + 'static,
         S::Request: From<Request>,
         S::Response: Into<Response>,
-        S::Error: Into<Error>,
+        S::Error: Into<crate::Error>,
         S::Instance: Send + Sync + 'static,
     {
         self.serve_until(service, futures::future::pending())
     }
 }
 
-/// The request-response loop spawned by serve_until for each client
-async fn process<S>(
-    framed: Framed<TcpStream, codec::tcp::ServerCodec>,
+/// The request-response loop spawned by the TCP and TLS servers for each client
+///
+/// Requests are converted into requests of the service by
+/// `accept_request`, which might also reject them with an exception.
+pub(crate) async fn process<T, S, F>(
+    framed: Framed<T, codec::tcp::ServerCodec>,
+    peer_addr: SocketAddr,
+    connection: &ConnectionConfig,
     service: S,
+    accept_request: F,
 ) -> io::Result<()>
 where
-    S: Service<Request = Request, Response = Response> + Send + Sync + 'static,
-    S::Error: Into<Error>,
+    T: AsyncRead + AsyncWrite + Unpin,
+    S: Service<Response = Response> + Send + Sync + 'static,
+    S::Error: Into<crate::Error>,
+    F: Fn(Request) -> Result<S::Request, ExceptionResponse>,
 {
     let mut framed = framed;
 
@@ -114,20 +178,79 @@ where
             break;
         }
 
-        let request = request.unwrap()?;
+        let request = match request.unwrap()? {
+            DecodedRequest::Request(request) => request,
+            DecodedRequest::Violation(violation) => {
+                let keep_open =
+                    handle_violation(&mut framed, violation, peer_addr, connection).await?;
+                if keep_open {
+                    continue;
+                }
+                break;
+            }
+        };
         let hdr = request.hdr;
-        let response = service.call(request.pdu.0).await.map_err(Into::into)?;
+        let request = request.pdu.0;
+        if connection.broadcast_ids.contains(Slave(hdr.unit_id)) {
+            // Only requests that write data are processed, without responding
+            if request.broadcast_response().is_none() {
+                debug!("Ignoring broadcast of {:?}", request);
+                continue;
+            }
+            if let Ok(response) = accept_request(request).map(|request| service.call(request)) {
+                response_pdu(response.await)?;
+            }
+            continue;
+        }
+        let expects_response = request.expects_response();
+        let pdu = match accept_request(request).map(|request| service.call(request)) {
+            Ok(response) => response_pdu(response.await)?,
+            Err(exception) => exception.into(),
+        };
+        if !expects_response {
+            continue;
+        }
 
-        framed
-            .send(tcp::ResponseAdu {
-                hdr,
-                pdu: response.into(),
-            })
-            .await?;
+        framed.send(tcp::ResponseAdu { hdr, pdu }).await?;
     }
     Ok(())
 }
 
+/// Count and log a frame that violates the protocol and apply the policy.
+///
+/// Returns `false` if the connection should be closed.
+async fn handle_violation<T>(
+    framed: &mut Framed<T, codec::tcp::ServerCodec>,
+    violation: FrameViolation,
+    peer_addr: SocketAddr,
+    connection: &ConnectionConfig,
+) -> io::Result<bool>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    connection.violation_counter.increment();
+    warn!("Protocol violation by {}: {}", peer_addr, violation.error);
+    match connection.violation_policy {
+        ViolationPolicy::Close => return Ok(false),
+        ViolationPolicy::SendException => {
+            if let Some(function) = violation.exception_function() {
+                let exception = ExceptionResponse {
+                    function,
+                    exception: Exception::IllegalDataValue,
+                };
+                framed
+                    .send(tcp::ResponseAdu {
+                        hdr: violation.hdr,
+                        pdu: exception.into(),
+                    })
+                    .await?;
+            }
+        }
+        ViolationPolicy::Skip => {}
+    }
+    Ok(true)
+}
+
 /// Start TCP listener - configure and open TCP socket
 #[allow(unused)]
 fn listener(addr: SocketAddr, workers: usize) -> io::Result<TcpListener> {
@@ -160,9 +283,12 @@ fn configure_tcp(_workers: usize, _tcp: &Socket) -> io::Result<()> {
 #[cfg(test)]
 mod tests {
     use super::*;
+
     use crate::server::Service;
+    use std::io::Error;
 
     use futures::future;
+    use tokio::net::TcpStream;
 
     #[tokio::test]
     async fn service_wrapper() {
@@ -191,4 +317,194 @@ mod tests {
 
         assert_eq!(rsp_adu, service.response);
     }
+
+    async fn serve_one_connection(
+        violation_policy: ViolationPolicy,
+        violation_counter: ViolationCounter,
+        broadcast_ids: BroadcastIds,
+    ) -> TcpStream {
+        #[derive(Clone)]
+        struct EchoService;
+
+        impl Service for EchoService {
+            type Request = Request;
+            type Response = Response;
+            type Error = crate::Error;
+            type Future = future::Ready<Result<Self::Response, Self::Error>>;
+
+            fn call(&self, req: Self::Request) -> Self::Future {
+                let Request::ReadHoldingRegisters(_, cnt) = req else {
+                    return future::ready(Err(ExceptionResponse {
+                        function: codec::req_to_fn_code(&req),
+                        exception: Exception::IllegalFunction,
+                    }
+                    .into()));
+                };
+                future::ready(Ok(Response::ReadHoldingRegisters(vec![0; cnt.into()])))
+            }
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (stream, peer_addr) = listener.accept().await.unwrap();
+        let framed = Framed::new(stream, codec::tcp::ServerCodec::default());
+        let connection = ConnectionConfig {
+            violation_policy,
+            violation_counter,
+            broadcast_ids,
+        };
+        tokio::spawn(async move { process(framed, peer_addr, &connection, EchoService, Ok).await });
+        client
+    }
+
+    // Read holding registers without quantity
+    const MALFORMED_REQUEST: [u8; 10] =
+        [0x00, 0x01, 0x00, 0x00, 0x00, 0x04, 0x01, 0x03, 0x00, 0x00];
+    const VALID_REQUEST: [u8; 12] = [
+        0x00, 0x02, 0x00, 0x00, 0x00, 0x06, 0x01, 0x03, 0x00, 0x00, 0x00, 0x01,
+    ];
+    const VALID_RESPONSE: [u8; 11] = [
+        0x00, 0x02, 0x00, 0x00, 0x00, 0x05, 0x01, 0x03, 0x02, 0x00, 0x00,
+    ];
+
+    #[tokio::test]
+    async fn close_connection_on_violation() {
+        use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+
+        let violation_counter = ViolationCounter::default();
+        let mut client = serve_one_connection(
+            ViolationPolicy::Close,
+            violation_counter.clone(),
+            BroadcastIds::NONE,
+        )
+        .await;
+        client.write_all(&MALFORMED_REQUEST).await.unwrap();
+        client.write_all(&VALID_REQUEST).await.unwrap();
+        let mut rsp = Vec::new();
+        client.read_to_end(&mut rsp).await.unwrap();
+        assert!(rsp.is_empty());
+        assert_eq!(violation_counter.get(), 1);
+    }
+
+    #[tokio::test]
+    async fn send_exception_on_violation() {
+        use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+
+        let violation_counter = ViolationCounter::default();
+        let mut client = serve_one_connection(
+            ViolationPolicy::SendException,
+            violation_counter.clone(),
+            BroadcastIds::NONE,
+        )
+        .await;
+        // Frames of other protocols are not answered
+        client
+            .write_all(&[
+                0x00, 0x07, 0x00, 0x01, 0x00, 0x06, 0x01, 0x03, 0x00, 0x00, 0x00, 0x01,
+            ])
+            .await
+            .unwrap();
+        client.write_all(&MALFORMED_REQUEST).await.unwrap();
+        client.write_all(&VALID_REQUEST).await.unwrap();
+        let mut rsp = [0; 9 + 11];
+        client.read_exact(&mut rsp).await.unwrap();
+        assert_eq!(
+            rsp[..9],
+            [0x00, 0x01, 0x00, 0x00, 0x00, 0x03, 0x01, 0x83, 0x03]
+        );
+        assert_eq!(rsp[9..], VALID_RESPONSE);
+        assert_eq!(violation_counter.get(), 2);
+    }
+
+    #[tokio::test]
+    async fn skip_frame_on_violation() {
+        use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+
+        let violation_counter = ViolationCounter::default();
+        let mut client = serve_one_connection(
+            ViolationPolicy::Skip,
+            violation_counter.clone(),
+            BroadcastIds::NONE,
+        )
+        .await;
+        client.write_all(&MALFORMED_REQUEST).await.unwrap();
+        client.write_all(&VALID_REQUEST).await.unwrap();
+        let mut rsp = [0; 11];
+        client.read_exact(&mut rsp).await.unwrap();
+        assert_eq!(rsp, VALID_RESPONSE);
+        assert_eq!(violation_counter.get(), 1);
+    }
+
+    #[tokio::test]
+    async fn send_exception_returned_by_service() {
+        use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+
+        let violation_counter = ViolationCounter::default();
+        let mut client = serve_one_connection(
+            ViolationPolicy::Close,
+            violation_counter.clone(),
+            BroadcastIds::NONE,
+        )
+        .await;
+        // Read coils
+        client
+            .write_all(&[
+                0x00, 0x01, 0x00, 0x00, 0x00, 0x06, 0x01, 0x01, 0x00, 0x00, 0x00, 0x01,
+            ])
+            .await
+            .unwrap();
+        client.write_all(&VALID_REQUEST).await.unwrap();
+        let mut rsp = [0; 9 + 11];
+        client.read_exact(&mut rsp).await.unwrap();
+        assert_eq!(
+            rsp[..9],
+            [0x00, 0x01, 0x00, 0x00, 0x00, 0x03, 0x01, 0x81, 0x01]
+        );
+        assert_eq!(rsp[9..], VALID_RESPONSE);
+        assert_eq!(violation_counter.get(), 0);
+    }
+
+    #[tokio::test]
+    async fn do_not_answer_broadcast_if_enabled() {
+        use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+
+        // Write single register to unit id 0
+        const BROADCAST_REQUEST: [u8; 12] = [
+            0x00, 0x01, 0x00, 0x00, 0x00, 0x06, 0x00, 0x06, 0x00, 0x01, 0x00, 0x03,
+        ];
+        // Write single register to unit id 255
+        const GATEWAY_BROADCAST_REQUEST: [u8; 12] = [
+            0x00, 0x03, 0x00, 0x00, 0x00, 0x06, 0xFF, 0x06, 0x00, 0x01, 0x00, 0x03,
+        ];
+
+        let broadcast_ids = BroadcastIds::NONE
+            .with(Slave::broadcast())
+            .with(Slave::tcp_device());
+        let mut client = serve_one_connection(
+            ViolationPolicy::Close,
+            ViolationCounter::default(),
+            broadcast_ids,
+        )
+        .await;
+        client.write_all(&BROADCAST_REQUEST).await.unwrap();
+        client.write_all(&GATEWAY_BROADCAST_REQUEST).await.unwrap();
+        client.write_all(&VALID_REQUEST).await.unwrap();
+        let mut rsp = [0; 11];
+        client.read_exact(&mut rsp).await.unwrap();
+        assert_eq!(rsp, VALID_RESPONSE);
+
+        // Answered like any other request if disabled
+        let mut client = serve_one_connection(
+            ViolationPolicy::Close,
+            ViolationCounter::default(),
+            BroadcastIds::NONE,
+        )
+        .await;
+        client.write_all(&BROADCAST_REQUEST).await.unwrap();
+        let mut rsp = [0; 9];
+        client.read_exact(&mut rsp).await.unwrap();
+        assert_eq!(rsp, [0x00, 0x01, 0x00, 0x00, 0x00, 0x03, 0x00, 0x86, 0x01]);
+    }
 }
@@ -0,0 +1,572 @@
+// SPDX-FileCopyrightText: Copyright (c) 2017-2022 slowtec GmbH <post@slowtec.de>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Modbus/TCP Security server skeleton
+//!
+//! Terminates mutually authenticated TLS connections and serves
+//! the regular Modbus TCP messages that are transferred through them.
+//!
+//! The role of a client is read from the role extension of its
+//! certificate. Both are handed to the service with each [`SecureRequest`]
+//! and an optional [`Authorizer`] may reject requests per role before
+//! they reach the service.
+
+use crate::{
+    codec,
+    frame::*,
+    server::{
+        service::NewService,
+        tcp::{process, ConnectionConfig, ViolationCounter, ViolationPolicy},
+    },
+};
+
+pub use crate::client::tls::{load_certs, load_private_key, rustls, DEFAULT_PORT};
+
+use crate::client::tls::invalid_input;
+
+use futures::{self, Future};
+use futures_util::future::FutureExt as _;
+use log::{debug, error, trace, warn};
+use std::{fmt, io::Error, net::SocketAddr, sync::Arc};
+use tokio::net::TcpListener;
+use tokio_rustls::{
+    rustls::{
+        crypto::ring::default_provider,
+        pki_types::{CertificateDer, PrivateKeyDer},
+        server::WebPkiClientVerifier,
+        RootCertStore, ServerConfig,
+    },
+    TlsAcceptor,
+};
+use tokio_util::codec::Framed;
+
+/// The OID of the role extension in client certificates.
+pub const ROLE_OID: &str = "1.3.6.1.4.1.50316.802.1";
+
+/// The identity of a client as presented during the TLS handshake.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerIdentity {
+    /// The end-entity certificate of the client.
+    ///
+    /// Only missing if the server configuration does not
+    /// require clients to authenticate themselves.
+    pub certificate: Option<CertificateDer<'static>>,
+
+    /// The role from the role extension of the certificate.
+    pub role: Option<String>,
+}
+
+impl PeerIdentity {
+    #[must_use]
+    pub fn new(certificate: Option<CertificateDer<'static>>) -> Self {
+        let role = certificate.as_ref().and_then(parse_role);
+        Self { certificate, role }
+    }
+}
+
+/// Read the role from the role extension of a DER encoded certificate.
+fn parse_role(certificate: &CertificateDer<'_>) -> Option<String> {
+    let (_, certificate) = x509_parser::parse_x509_certificate(certificate).ok()?;
+    let extension = certificate
+        .extensions()
+        .iter()
+        .find(|extension| extension.oid.to_id_string() == ROLE_OID)?;
+    let role = x509_parser::der_parser::parse_der(extension.value)
+        .ok()
+        .and_then(|(_, role)| role.as_str().ok().map(ToOwned::to_owned));
+    if role.is_none() {
+        warn!("Ignoring malformed role extension");
+    }
+    role
+}
+
+/// A request of an authenticated client.
+///
+/// Services that are not interested in the identity of the
+/// client can still use [`Request`] for their requests.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SecureRequest {
+    pub request: Request,
+    pub peer: Arc<PeerIdentity>,
+}
+
+impl SecureRequest {
+    /// The role of the client, if any.
+    #[must_use]
+    pub fn role(&self) -> Option<&str> {
+        self.peer.role.as_deref()
+    }
+
+    /// The certificate of the client, if any.
+    #[must_use]
+    pub fn peer_certificate(&self) -> Option<&CertificateDer<'static>> {
+        self.peer.certificate.as_ref()
+    }
+
+    /// The function code of the request.
+    #[must_use]
+    pub fn function_code(&self) -> u8 {
+        codec::req_to_fn_code(&self.request)
+    }
+}
+
+impl From<SecureRequest> for Request {
+    fn from(from: SecureRequest) -> Self {
+        from.request
+    }
+}
+
+/// Decides which requests clients are allowed to perform.
+///
+/// Rejected requests are answered with an *Illegal Function*
+/// exception without invoking the service.
+pub trait Authorizer: Send + Sync {
+    /// Return `true` if the request is permitted.
+    fn authorize(&self, request: &SecureRequest) -> bool;
+}
+
+impl<F> Authorizer for F
+where
+    F: Fn(&SecureRequest) -> bool + Send + Sync,
+{
+    fn authorize(&self, request: &SecureRequest) -> bool {
+        self(request)
+    }
+}
+
+#[derive(Clone)]
+pub struct Server {
+    socket_addr: SocketAddr,
+    config: Arc<ServerConfig>,
+    authorizer: Option<Arc<dyn Authorizer>>,
+    connection: ConnectionConfig,
+}
+
+impl fmt::Debug for Server {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Server")
+            .field("socket_addr", &self.socket_addr)
+            .field("config", &self.config)
+            .field("authorizer", &self.authorizer.is_some())
+            .field("connection", &self.connection)
+            .finish()
+    }
+}
+
+impl Server {
+    /// Set the address and the TLS configuration for the server (mandatory).
+    ///
+    /// See [`server_config()`] for a configuration that requires
+    /// clients to authenticate themselves with a certificate.
+    #[must_use]
+    pub fn new(socket_addr: SocketAddr, config: Arc<ServerConfig>) -> Self {
+        Self {
+            socket_addr,
+            config,
+            authorizer: None,
+            connection: ConnectionConfig::default(),
+        }
+    }
+
+    /// Authorize each request before it is passed to the service (optional).
+    #[must_use]
+    pub fn with_authorizer(self, authorizer: impl Authorizer + 'static) -> Self {
+        Self {
+            authorizer: Some(Arc::new(authorizer)),
+            ..self
+        }
+    }
+
+    /// Handle frames that violate the protocol according to the policy
+    /// (optional). The connection is closed by default.
+    #[must_use]
+    pub fn with_violation_policy(self, violation_policy: ViolationPolicy) -> Self {
+        Self {
+            connection: ConnectionConfig {
+                violation_policy,
+                ..self.connection
+            },
+            ..self
+        }
+    }
+
+    /// The counter of frames that violated the protocol. It can be
+    /// read while the server is running.
+    #[must_use]
+    pub fn violation_counter(&self) -> ViolationCounter {
+        self.connection.violation_counter.clone()
+    }
+
+    /// Start an async Modbus/TCP Security server task.
+    pub async fn serve<S>(&self, service: S) -> Result<(), Error>
+    where
+        S: NewService<Response = Response> + Send + Sync + 'static,
+        S::Request: From<SecureRequest>,
+        S::Response: Into<Response>,
+        S::Error: Into<crate::Error>,
+        S::Instance: Send + Sync + 'static,
+    {
+        let service = Arc::new(service);
+        let acceptor = TlsAcceptor::from(Arc::clone(&self.config));
+        let listener = TcpListener::bind(self.socket_addr).await?;
+
+        loop {
+            let (stream, peer_addr) = listener.accept().await?;
+            let acceptor = acceptor.clone();
+            let authorizer = self.authorizer.clone();
+            let connection = self.connection.clone();
+            let new_service = service.clone();
+
+            tokio::spawn(Box::pin(async move {
+                // The handshake is performed by the connection task
+                // to not block other clients while it is pending.
+                let stream = match acceptor.accept(stream).await {
+                    Ok(stream) => stream,
+                    Err(err) => {
+                        warn!("TLS handshake with {} failed: {}", peer_addr, err);
+                        return;
+                    }
+                };
+                let peer_certificate = stream
+                    .get_ref()
+                    .1
+                    .peer_certificates()
+                    .and_then(|certs| certs.first())
+                    .cloned();
+                let peer = Arc::new(PeerIdentity::new(peer_certificate));
+                debug!("Accepted client {} with role {:?}", peer_addr, peer.role);
+                let framed = Framed::new(stream, codec::tcp::ServerCodec::default());
+                let service = new_service.new_service().unwrap();
+                let accept_request = |request| authorize(&peer, authorizer.as_deref(), request);
+                if let Err(err) =
+                    process(framed, peer_addr, &connection, service, accept_request).await
+                {
+                    error!("{:?}", err);
+                }
+            }));
+        }
+    }
+
+    /// Start a Modbus/TCP Security server that blocks the current thread until a shutdown is requested
+    pub fn serve_until<S, Sd>(self, service: S, shutdown_signal: Sd)
+    where
+        S: NewService<Response = Response> + Send + Sync + 'static,
+        Sd: Future<Output = ()> + Sync + Send + Unpin + 'static,
+        S::Request: From<SecureRequest>,
+        S::Response: Into<Response>,
+        S::Error: Into<crate::Error>,
+        S::Instance: Send + Sync + 'static,
+    {
+        let shutdown_signal = shutdown_signal.fuse();
+        let rt = tokio::runtime::Builder::new_multi_thread()
+            .enable_io()
+            .build()
+            .unwrap();
+
+        rt.block_on(async {
+            tokio::select! {
+                res = self.serve(service) => if let Err(e) = res { error!("error: {}", e) },
+                _ = shutdown_signal => trace!("Shutdown signal received")
+            }
+        })
+    }
+
+    pub fn serve_forever<S>(self, service: S)
+    where
+        S: NewService<Response = Response> + Send + Sync + 'static,
+        S::Request: From<SecureRequest>,
+        S::Response: Into<Response>,
+        S::Error: Into<crate::Error>,
+        S::Instance: Send + Sync + 'static,
+    {
+        self.serve_until(service, futures::future::pending())
+    }
+}
+
+/// Pass the identity of the client with each request to the service
+/// unless the authorizer rejects the request.
+fn authorize<R>(
+    peer: &Arc<PeerIdentity>,
+    authorizer: Option<&dyn Authorizer>,
+    request: Request,
+) -> Result<R, ExceptionResponse>
+where
+    R: From<SecureRequest>,
+{
+    let request = SecureRequest {
+        request,
+        peer: Arc::clone(peer),
+    };
+    if authorizer.is_some_and(|authorizer| !authorizer.authorize(&request)) {
+        debug!(
+            "Rejecting function {} for role {:?}",
+            request.function_code(),
+            request.role()
+        );
+        return Err(ExceptionResponse {
+            function: request.function_code(),
+            exception: Exception::IllegalFunction,
+        });
+    }
+    Ok(request.into())
+}
+
+/// Create a server configuration for mutually authenticated connections.
+///
+/// Clients must present a certificate that has been issued by one of
+/// the `client_root_certs`. The server authenticates itself with the
+/// `cert_chain` and its `key`.
+pub fn server_config(
+    client_root_certs: impl IntoIterator<Item = CertificateDer<'static>>,
+    cert_chain: Vec<CertificateDer<'static>>,
+    key: PrivateKeyDer<'static>,
+) -> Result<ServerConfig, Error> {
+    let provider = Arc::new(default_provider());
+    let mut roots = RootCertStore::empty();
+    for cert in client_root_certs {
+        roots.add(cert).map_err(invalid_input)?;
+    }
+    let client_verifier =
+        WebPkiClientVerifier::builder_with_provider(Arc::new(roots), Arc::clone(&provider))
+            .build()
+            .map_err(|err| Error::new(std::io::ErrorKind::InvalidInput, err))?;
+    ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .map_err(invalid_input)?
+        .with_client_cert_verifier(client_verifier)
+        .with_single_cert(cert_chain, key)
+        .map_err(invalid_input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        client::{tls::client_config, Client as _, Context, Reader as _, Writer as _},
+        prelude::Slave,
+        server::Service,
+    };
+
+    use futures::future;
+    use rcgen::{BasicConstraints, Certificate, CertificateParams, CustomExtension, IsCa, KeyPair};
+    use tokio_rustls::rustls::pki_types::ServerName;
+
+    struct DummyService;
+
+    impl Service for DummyService {
+        type Request = Request;
+        type Response = Response;
+        type Error = Error;
+        type Future = future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn call(&self, req: Self::Request) -> Self::Future {
+            match req {
+                Request::ReadHoldingRegisters(addr, cnt) => future::ready(Ok(
+                    Response::ReadHoldingRegisters((addr..addr + cnt).collect()),
+                )),
+                _ => unimplemented!(),
+            }
+        }
+    }
+
+    /// Reveals the role of the client for a custom function code
+    struct RoleService;
+
+    impl Service for RoleService {
+        type Request = SecureRequest;
+        type Response = Response;
+        type Error = Error;
+        type Future = future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn call(&self, req: Self::Request) -> Self::Future {
+            assert!(req.peer_certificate().is_some());
+            match req.request {
+                Request::Custom(0x41, _) => future::ready(Ok(Response::Custom(
+                    0x41,
+                    req.role().unwrap_or_default().as_bytes().to_vec(),
+                ))),
+                Request::WriteSingleRegister(addr, value) => {
+                    future::ready(Ok(Response::WriteSingleRegister(addr, value)))
+                }
+                _ => unimplemented!(),
+            }
+        }
+    }
+
+    struct Pki {
+        ca_cert: Certificate,
+        ca_key: KeyPair,
+    }
+
+    impl Pki {
+        fn new() -> Self {
+            let ca_key = KeyPair::generate().unwrap();
+            let mut params = CertificateParams::new(vec![]).unwrap();
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            let ca_cert = params.self_signed(&ca_key).unwrap();
+            Self { ca_cert, ca_key }
+        }
+
+        fn root_cert(&self) -> CertificateDer<'static> {
+            self.ca_cert.der().clone()
+        }
+
+        fn issue(
+            &self,
+            subject_alt_names: &[&str],
+            role: Option<&str>,
+        ) -> (Vec<CertificateDer<'static>>, PrivateKeyDer<'static>) {
+            let key = KeyPair::generate().unwrap();
+            let subject_alt_names: Vec<_> =
+                subject_alt_names.iter().map(ToString::to_string).collect();
+            let mut params = CertificateParams::new(subject_alt_names).unwrap();
+            if let Some(role) = role {
+                // Role ::= UTF8String
+                let mut content = vec![0x0C, role.len().try_into().unwrap()];
+                content.extend_from_slice(role.as_bytes());
+                params
+                    .custom_extensions
+                    .push(CustomExtension::from_oid_content(
+                        &[1, 3, 6, 1, 4, 1, 50316, 802, 1],
+                        content,
+                    ));
+            }
+            let cert = params.signed_by(&key, &self.ca_cert, &self.ca_key).unwrap();
+            let key = PrivateKeyDer::Pkcs8(key.serialize_der().into());
+            (vec![cert.der().clone()], key)
+        }
+    }
+
+    async fn spawn_server<S>(
+        pki: &Pki,
+        service: S,
+        authorizer: Option<fn(&SecureRequest) -> bool>,
+    ) -> SocketAddr
+    where
+        S: NewService<Response = Response> + Send + Sync + 'static,
+        S::Request: From<SecureRequest>,
+        S::Error: Into<crate::Error>,
+        S::Instance: Send + Sync + 'static,
+    {
+        let (cert_chain, key) = pki.issue(&["localhost"], None);
+        let config = server_config([pki.root_cert()], cert_chain, key).unwrap();
+        // Bind to an ephemeral port before spawning the server
+        let socket_addr = {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            listener.local_addr().unwrap()
+        };
+        let mut server = Server::new(socket_addr, Arc::new(config));
+        if let Some(authorizer) = authorizer {
+            server = server.with_authorizer(authorizer);
+        }
+        tokio::spawn(async move { server.serve(service).await });
+        // Wait until the server is listening
+        while tokio::net::TcpStream::connect(socket_addr).await.is_err() {
+            tokio::task::yield_now().await;
+        }
+        socket_addr
+    }
+
+    async fn connect(
+        socket_addr: SocketAddr,
+        root_cert: CertificateDer<'static>,
+        (cert_chain, key): (Vec<CertificateDer<'static>>, PrivateKeyDer<'static>),
+    ) -> Result<Context, Error> {
+        let config = client_config([root_cert], cert_chain, key).unwrap();
+        let server_name = ServerName::try_from("localhost").unwrap();
+        crate::client::tls::connect_slave(socket_addr, server_name, Arc::new(config), Slave(1))
+            .await
+    }
+
+    #[tokio::test]
+    async fn serve_authenticated_client() {
+        let pki = Pki::new();
+        let socket_addr = spawn_server(&pki, || Ok(DummyService), None).await;
+
+        let mut ctx = connect(socket_addr, pki.root_cert(), pki.issue(&[], None))
+            .await
+            .unwrap();
+        let rsp = ctx.read_holding_registers(0x0100, 2).await.unwrap();
+        assert_eq!(rsp, vec![0x0100, 0x0101]);
+    }
+
+    #[tokio::test]
+    async fn reject_client_with_untrusted_certificate() {
+        let pki = Pki::new();
+        let socket_addr = spawn_server(&pki, || Ok(DummyService), None).await;
+
+        // The client trusts the server but uses a certificate
+        // that has been issued by a different authority.
+        let res = connect(socket_addr, pki.root_cert(), Pki::new().issue(&[], None)).await;
+        // With TLS 1.3 the server rejects the client certificate
+        // only after the client considers the handshake complete.
+        if let Ok(mut ctx) = res {
+            assert!(ctx.read_holding_registers(0x0100, 2).await.is_err());
+        }
+    }
+
+    #[test]
+    fn parse_role_from_certificate() {
+        let pki = Pki::new();
+        let (cert_chain, _) = pki.issue(&[], Some("Operator"));
+        let peer = PeerIdentity::new(cert_chain.into_iter().next());
+        assert_eq!(peer.role.as_deref(), Some("Operator"));
+
+        let (cert_chain, _) = pki.issue(&[], None);
+        let peer = PeerIdentity::new(cert_chain.into_iter().next());
+        assert!(peer.certificate.is_some());
+        assert_eq!(peer.role, None);
+    }
+
+    #[tokio::test]
+    async fn pass_role_to_service() {
+        let pki = Pki::new();
+        let socket_addr = spawn_server(&pki, || Ok(RoleService), None).await;
+
+        let mut ctx = connect(
+            socket_addr,
+            pki.root_cert(),
+            pki.issue(&[], Some("Engineer")),
+        )
+        .await
+        .unwrap();
+        let rsp = ctx.call(Request::Custom(0x41, vec![])).await.unwrap();
+        assert_eq!(rsp, Response::Custom(0x41, b"Engineer".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn reject_unauthorized_requests() {
+        let pki = Pki::new();
+        let read_only_operator =
+            |req: &SecureRequest| req.role() == Some("Engineer") || req.function_code() != 0x06;
+        let socket_addr = spawn_server(&pki, || Ok(RoleService), Some(read_only_operator)).await;
+
+        let mut ctx = connect(
+            socket_addr,
+            pki.root_cert(),
+            pki.issue(&[], Some("Operator")),
+        )
+        .await
+        .unwrap();
+        let rsp = ctx.call(Request::Custom(0x41, vec![])).await.unwrap();
+        assert_eq!(rsp, Response::Custom(0x41, b"Operator".to_vec()));
+        let err = ctx.write_single_register(0x0001, 0x1234).await.unwrap_err();
+        assert_eq!(
+            err.exception(),
+            Some(&ExceptionResponse {
+                function: 0x06,
+                exception: Exception::IllegalFunction,
+            })
+        );
+
+        let mut ctx = connect(
+            socket_addr,
+            pki.root_cert(),
+            pki.issue(&[], Some("Engineer")),
+        )
+        .await
+        .unwrap();
+        ctx.write_single_register(0x0001, 0x1234).await.unwrap();
+    }
+}
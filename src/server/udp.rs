@@ -0,0 +1,338 @@
+// SPDX-FileCopyrightText: Copyright (c) 2017-2022 slowtec GmbH <post@slowtec.de>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Modbus UDP server skeleton
+
+use crate::{
+    codec::{
+        self,
+        tcp::{DecodedRequest, FrameViolation},
+    },
+    frame::*,
+    server::service::{response_pdu, NewService, Service},
+};
+
+pub use crate::server::violation::{ViolationCounter, ViolationPolicy};
+
+use bytes::BytesMut;
+use futures::{self, Future, FutureExt as _};
+use log::{error, trace, warn};
+use std::{io, net::SocketAddr};
+use tokio::net::UdpSocket;
+use tokio_util::codec::{Decoder as _, Encoder as _};
+
+/// The maximum size of an MBAP header (7 bytes) followed by a PDU (253 bytes).
+const MAX_ADU_LEN: usize = 260;
+
+#[derive(Debug, Clone)]
+pub struct Server {
+    socket_addr: SocketAddr,
+    violation_policy: ViolationPolicy,
+    violation_counter: ViolationCounter,
+}
+
+impl Server {
+    /// Set the address for the server (mandatory).
+    #[must_use]
+    pub fn new(socket_addr: SocketAddr) -> Self {
+        Self {
+            socket_addr,
+            violation_policy: ViolationPolicy::default(),
+            violation_counter: ViolationCounter::default(),
+        }
+    }
+
+    /// Handle datagrams that violate the protocol according to the policy
+    /// (optional). Invalid datagrams are discarded by default, because
+    /// there is no connection to close.
+    #[must_use]
+    pub fn with_violation_policy(self, violation_policy: ViolationPolicy) -> Self {
+        Self {
+            violation_policy,
+            ..self
+        }
+    }
+
+    /// The counter of datagrams that violated the protocol. It can be
+    /// read while the server is running.
+    #[must_use]
+    pub fn violation_counter(&self) -> ViolationCounter {
+        self.violation_counter.clone()
+    }
+
+    /// Start an async Modbus UDP server task.
+    pub async fn serve<S>(&self, new_service: S) -> Result<(), std::io::Error>
+    where
+        S: NewService<Request = Request, Response = Response> + Send + Sync + 'static,
+        S::Request: From<Request>,
+        S::Response: Into<Response>,
+        S::Error: Into<crate::Error>,
+        S::Instance: Send + Sync + 'static,
+    {
+        let socket = UdpSocket::bind(self.socket_addr).await?;
+        let service = new_service.new_service()?;
+        process(
+            socket,
+            service,
+            self.violation_policy,
+            self.violation_counter.clone(),
+        )
+        .await
+    }
+
+    /// Start a Modbus UDP server that blocks the current thread until a shutdown is requested
+    pub fn serve_until<S, Sd>(self, service: S, shutdown_signal: Sd)
+    where
+        S: NewService<Request = Request, Response = Response> + Send + Sync + 'static,
+        Sd: Future<Output = ()> + Sync + Send + Unpin + 'static,
+        S::Request: From<Request>,
+        S::Response: Into<Response>,
+        S::Error: Into<crate::Error>,
+        S::Instance: Send + Sync + 'static,
+    {
+        let shutdown_signal = shutdown_signal.fuse();
+        let rt = tokio::runtime::Builder::new_multi_thread()
+            .enable_io()
+            .build()
+            .unwrap();
+
+        rt.block_on(async {
+            tokio::select! {
+                res = self.serve(service) => if let Err(e) = res { error!("error: {}", e) },
+                _ = shutdown_signal => trace!("Shutdown signal received")
+            }
+        })
+    }
+
+    pub fn serve_forever<S>(self, service: S)
+    where
+        S: NewService<Request = Request, Response = Response> + Send + Sync + 'static,
+        S::Request: From<Request>,
+        S::Response: Into<Response>,
+        S::Error: Into<crate::Error>,
+        S::Instance: Send + Sync + 'static,
+    {
+        self.serve_until(service, futures::future::pending())
+    }
+}
+
+/// The request-response loop that answers each datagram to its sender
+async fn process<S>(
+    socket: UdpSocket,
+    service: S,
+    violation_policy: ViolationPolicy,
+    violation_counter: ViolationCounter,
+) -> io::Result<()>
+where
+    S: Service<Request = Request, Response = Response> + Send + Sync + 'static,
+    S::Error: Into<crate::Error>,
+{
+    let mut codec = codec::tcp::ServerCodec::default();
+    let mut datagram = [0; MAX_ADU_LEN];
+
+    loop {
+        let (len, peer) = socket.recv_from(&mut datagram).await?;
+
+        // Each datagram must contain exactly one request. A fresh codec
+        // prevents a malformed datagram from affecting the next one.
+        let request = match codec::tcp::ServerCodec::default()
+            .decode(&mut BytesMut::from(&datagram[..len]))
+        {
+            Ok(Some(DecodedRequest::Request(request))) => request,
+            Ok(Some(DecodedRequest::Violation(violation))) => {
+                violation_counter.increment();
+                warn!(
+                    "Discarding invalid datagram from {}: {}",
+                    peer, violation.error
+                );
+                if violation_policy == ViolationPolicy::SendException {
+                    send_exception(&socket, &mut codec, violation, peer).await;
+                }
+                continue;
+            }
+            Ok(None) => {
+                violation_counter.increment();
+                warn!("Discarding incomplete datagram from {}", peer);
+                continue;
+            }
+            Err(err) => {
+                violation_counter.increment();
+                warn!("Discarding invalid datagram from {}: {}", peer, err);
+                continue;
+            }
+        };
+
+        // Failures only affect the current datagram and its sender
+        let hdr = request.hdr;
+        let expects_response = request.pdu.0.expects_response();
+        let pdu = match response_pdu(service.call(request.pdu.0).await) {
+            Ok(pdu) => pdu,
+            Err(err) => {
+                error!("Failed to process request from {}: {}", peer, err);
+                continue;
+            }
+        };
+        if !expects_response {
+            continue;
+        }
+
+        let mut buf = BytesMut::with_capacity(MAX_ADU_LEN);
+        if let Err(err) = codec.encode(tcp::ResponseAdu { hdr, pdu }, &mut buf) {
+            error!("Failed to encode response for {}: {}", peer, err);
+            continue;
+        }
+        if let Err(err) = socket.send_to(&buf, peer).await {
+            warn!("Failed to send response to {}: {}", peer, err);
+        }
+    }
+}
+
+/// Respond to a datagram that violates the protocol with an
+/// `IllegalDataValue` exception if the function code is known.
+async fn send_exception(
+    socket: &UdpSocket,
+    codec: &mut codec::tcp::ServerCodec,
+    violation: FrameViolation,
+    peer: SocketAddr,
+) {
+    let Some(function) = violation.exception_function() else {
+        return;
+    };
+    let exception = ExceptionResponse {
+        function,
+        exception: Exception::IllegalDataValue,
+    };
+    let adu = tcp::ResponseAdu {
+        hdr: violation.hdr,
+        pdu: exception.into(),
+    };
+    let mut buf = BytesMut::with_capacity(MAX_ADU_LEN);
+    if let Err(err) = codec.encode(adu, &mut buf) {
+        error!("Failed to encode exception for {}: {}", peer, err);
+        return;
+    }
+    if let Err(err) = socket.send_to(&buf, peer).await {
+        warn!("Failed to send exception to {}: {}", peer, err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Error;
+
+    use crate::{client::Reader as _, prelude::Slave};
+
+    use futures::future;
+
+    struct DummyService;
+
+    impl Service for DummyService {
+        type Request = Request;
+        type Response = Response;
+        type Error = Error;
+        type Future = future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn call(&self, req: Self::Request) -> Self::Future {
+            match req {
+                Request::ReadHoldingRegisters(addr, cnt) => future::ready(Ok(
+                    Response::ReadHoldingRegisters((addr..addr + cnt).collect()),
+                )),
+                Request::ReadInputRegisters(_, _) => {
+                    future::ready(Err(Error::other("Service failure")))
+                }
+                _ => unimplemented!(),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn serve_udp_client() {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let socket_addr = socket.local_addr().unwrap();
+        tokio::spawn(process(
+            socket,
+            DummyService,
+            ViolationPolicy::default(),
+            ViolationCounter::default(),
+        ));
+
+        let mut ctx = crate::client::udp::connect_slave(socket_addr, Slave(0x01))
+            .await
+            .unwrap();
+        for addr in [0x0000, 0x1000] {
+            let rsp = ctx.read_holding_registers(addr, 2).await.unwrap();
+            assert_eq!(rsp, vec![addr, addr + 1]);
+        }
+    }
+
+    #[tokio::test]
+    async fn keep_serving_after_failed_request() {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let socket_addr = socket.local_addr().unwrap();
+        let server = tokio::spawn(process(
+            socket,
+            DummyService,
+            ViolationPolicy::default(),
+            ViolationCounter::default(),
+        ));
+
+        let mut ctx = crate::client::udp::connect_slave(socket_addr, Slave(0x01))
+            .await
+            .unwrap();
+        let res = tokio::time::timeout(
+            std::time::Duration::from_millis(100),
+            ctx.read_input_registers(0x0000, 1),
+        )
+        .await;
+        assert!(res.is_err());
+        assert!(!server.is_finished());
+        let rsp = ctx.read_holding_registers(0x0000, 2).await.unwrap();
+        assert_eq!(rsp, vec![0x0000, 0x0001]);
+    }
+
+    #[tokio::test]
+    async fn count_and_answer_violations() {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let socket_addr = socket.local_addr().unwrap();
+        let violation_counter = ViolationCounter::default();
+        tokio::spawn(process(
+            socket,
+            DummyService,
+            ViolationPolicy::SendException,
+            violation_counter.clone(),
+        ));
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.connect(socket_addr).await.unwrap();
+        // Invalid protocol id, which is not answered
+        client
+            .send(&[
+                0x00, 0x01, 0x00, 0x01, 0x00, 0x06, 0x01, 0x03, 0x00, 0x00, 0x00, 0x01,
+            ])
+            .await
+            .unwrap();
+        // Incomplete datagram without a function code
+        client.send(&[0x00, 0x02, 0x00, 0x00]).await.unwrap();
+        // Malformed request without quantity
+        client
+            .send(&[0x00, 0x03, 0x00, 0x00, 0x00, 0x04, 0x01, 0x03, 0x00, 0x00])
+            .await
+            .unwrap();
+        let mut buf = [0; MAX_ADU_LEN];
+        let len = client.recv(&mut buf).await.unwrap();
+        assert_eq!(
+            &buf[..len],
+            &[0x00, 0x03, 0x00, 0x00, 0x00, 0x03, 0x01, 0x83, 0x03]
+        );
+
+        // Valid requests are still answered
+        let mut ctx = crate::client::udp::connect_slave(socket_addr, Slave(0x01))
+            .await
+            .unwrap();
+        let rsp = ctx.read_holding_registers(0x0000, 2).await.unwrap();
+        assert_eq!(rsp, vec![0x0000, 0x0001]);
+        assert_eq!(violation_counter.get(), 3);
+    }
+}
@@ -0,0 +1,48 @@
+// SPDX-FileCopyrightText: Copyright (c) 2017-2022 slowtec GmbH <post@slowtec.de>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Handling of frames that violate the protocol
+
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+/// The handling of frames that violate the protocol, e.g. frames
+/// with an invalid MBAP header or a malformed PDU.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ViolationPolicy {
+    /// Close the connection.
+    ///
+    /// Datagrams of the connectionless UDP transport are discarded.
+    #[default]
+    Close,
+
+    /// Respond with an `IllegalDataValue` exception if the function
+    /// code is known and continue with the next frame.
+    ///
+    /// Only frames with the Modbus protocol identifier 0 and a function
+    /// code below `0x80` are answered. All other frames are skipped.
+    SendException,
+
+    /// Silently skip the frame and continue with the next frame.
+    Skip,
+}
+
+/// Counts the frames that violate the protocol.
+///
+/// All clones share the same count.
+#[derive(Debug, Clone, Default)]
+pub struct ViolationCounter(Arc<AtomicU64>);
+
+impl ViolationCounter {
+    /// The number of frames that violated the protocol.
+    #[must_use]
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn increment(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+}
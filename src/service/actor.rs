@@ -0,0 +1,59 @@
+// SPDX-FileCopyrightText: Copyright (c) 2017-2022 slowtec GmbH <post@slowtec.de>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Handles that pass requests to a client owned by a background task
+
+use std::io::{self, ErrorKind};
+
+use tokio::sync::{mpsc, oneshot};
+
+use crate::{
+    frame::{Request, Response},
+    Error,
+};
+
+/// The number of requests that are queued before callers
+/// have to wait.
+pub(crate) const REQUEST_QUEUE_LEN: usize = 32;
+
+/// A request that is passed from a handle to the background task.
+///
+/// The target tells the task how to execute the request, e.g. which
+/// slave device is addressed.
+#[derive(Debug)]
+pub(crate) struct Command<T> {
+    pub(crate) target: T,
+    pub(crate) request: Request,
+    pub(crate) response_tx: oneshot::Sender<Result<Response, Error>>,
+}
+
+impl<T> Command<T> {
+    /// The caller is no longer interested in the response.
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.response_tx.is_closed()
+    }
+}
+
+/// Pass a request to the background task and wait for the response.
+pub(crate) async fn call<T>(
+    command_tx: &mpsc::Sender<Command<T>>,
+    target: T,
+    request: Request,
+) -> Result<Response, Error> {
+    let (response_tx, response_rx) = oneshot::channel();
+    let command = Command {
+        target,
+        request,
+        response_tx,
+    };
+    command_tx.send(command).await.map_err(|_| closed())?;
+    response_rx.await.map_err(|_| closed())?
+}
+
+/// The background task has been terminated.
+pub(crate) fn closed() -> Error {
+    Error::Transport(io::Error::new(
+        ErrorKind::NotConnected,
+        "Client task has been terminated",
+    ))
+}
@@ -0,0 +1,166 @@
+// SPDX-FileCopyrightText: Copyright (c) 2017-2022 slowtec GmbH <post@slowtec.de>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use crate::{
+    client::{Broadcast, Client},
+    codec,
+    frame::{ascii::*, *},
+    slave::*,
+    Error,
+};
+
+use futures_util::{future, sink::SinkExt as _, stream::StreamExt as _};
+use std::{
+    fmt::Debug,
+    future::Future,
+    io::{self, ErrorKind},
+    time::Duration,
+};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_util::codec::Framed;
+
+pub(crate) fn connect_slave<T>(
+    transport: T,
+    slave: Slave,
+) -> impl Future<Output = Result<Context<T>, io::Error>>
+where
+    T: AsyncRead + AsyncWrite + Debug + Unpin + 'static,
+{
+    let framed = Framed::new(transport, codec::ascii::ClientCodec::default());
+
+    let slave_id = slave.into();
+    future::ok(Context {
+        service: framed,
+        slave_id,
+        response_pending: false,
+        broadcast: Broadcast::Enabled {
+            broadcast_ids: BroadcastIds::SERIAL_LINE,
+            turnaround_delay: Duration::ZERO,
+        },
+    })
+}
+
+/// Modbus ASCII client
+#[derive(Debug)]
+pub(crate) struct Context<T: AsyncRead + AsyncWrite + Debug + Unpin + 'static> {
+    service: Framed<T, codec::ascii::ClientCodec>,
+    slave_id: SlaveId,
+    /// The response of the previous request has not been received
+    response_pending: bool,
+    broadcast: Broadcast,
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Debug + 'static> Context<T> {
+    fn next_request_adu<R>(&self, req: R, disconnect: bool) -> RequestAdu
+    where
+        R: Into<RequestPdu>,
+    {
+        let slave_id = self.slave_id;
+        let hdr = Header { slave_id };
+        let pdu = req.into();
+        RequestAdu {
+            hdr,
+            pdu,
+            disconnect,
+        }
+    }
+
+    async fn call(&mut self, req: Request) -> Result<Response, Error> {
+        let broadcast_response =
+            super::broadcast_response(self.broadcast, Slave(self.slave_id), &req)?;
+        let disconnect = req == Request::Disconnect;
+        let implicit_response = req.implicit_response();
+        let function = (!disconnect).then(|| codec::req_to_fn_code(&req));
+        let req_adu = self.next_request_adu(req, disconnect);
+        let req_hdr = req_adu.hdr;
+
+        if self.response_pending {
+            super::discard_late_response(&mut self.service);
+        }
+        self.response_pending = implicit_response.is_none() && broadcast_response.is_none();
+        self.service.send(req_adu).await?;
+        if let Some((res, turnaround_delay)) = broadcast_response {
+            tokio::time::sleep(turnaround_delay).await;
+            return Ok(res);
+        }
+        if let Some(res) = implicit_response {
+            return Ok(res);
+        }
+        // Encoding fails for requests without a function code
+        let function = function.ok_or_else(|| io::Error::from(ErrorKind::InvalidInput))?;
+        // Late responses of previous requests that have been cancelled,
+        // e.g. after a timeout, are skipped.
+        let res_adu = loop {
+            let res_adu = self.service.next().await;
+            let res_adu = res_adu
+                .unwrap_or_else(|| Err(io::Error::from(ErrorKind::BrokenPipe)))
+                .inspect_err(|_| self.response_pending = false)?;
+            if super::is_response_to(
+                req_hdr.slave_id,
+                function,
+                res_adu.hdr.slave_id,
+                &res_adu.pdu,
+            ) {
+                self.response_pending = false;
+                break res_adu;
+            }
+        };
+
+        match res_adu.pdu {
+            ResponsePdu(Ok(res)) => Ok(res),
+            ResponsePdu(Err(err)) => Err(Error::Exception(err)),
+        }
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite + Debug + Unpin + 'static> SlaveContext for Context<T> {
+    fn set_slave(&mut self, slave: Slave) {
+        self.slave_id = slave.into();
+    }
+}
+
+#[async_trait::async_trait]
+impl<T: AsyncRead + AsyncWrite + Debug + Unpin + Send + 'static> Client for Context<T> {
+    async fn call(&mut self, req: Request) -> Result<Response, Error> {
+        self.call(req).await
+    }
+
+    fn set_broadcast(&mut self, broadcast: Broadcast) {
+        self.broadcast = broadcast;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tokio::io::{AsyncBufReadExt as _, AsyncWriteExt as _, BufReader};
+
+    #[tokio::test(start_paused = true)]
+    async fn skip_late_response_after_next_request() {
+        let (client_io, server_io) = tokio::io::duplex(1024);
+        tokio::spawn(async move {
+            let mut server_io = BufReader::new(server_io);
+            let mut request = Vec::new();
+            server_io.read_until(b'\n', &mut request).await.unwrap();
+            server_io.read_until(b'\n', &mut request).await.unwrap();
+            server_io
+                .write_all(b":0103021234B4\r\n:010600010000F8\r\n")
+                .await
+                .unwrap();
+        });
+
+        let mut ctx = connect_slave(client_io, Slave(0x01)).await.unwrap();
+        let res = tokio::time::timeout(
+            Duration::from_secs(1),
+            ctx.call(Request::ReadHoldingRegisters(0x00, 1)),
+        )
+        .await;
+        assert!(res.is_err());
+        let rsp = ctx
+            .call(Request::WriteSingleRegister(0x01, 0x00))
+            .await
+            .unwrap();
+        assert_eq!(rsp, Response::WriteSingleRegister(0x01, 0x00));
+    }
+}
@@ -1,8 +1,98 @@
 // SPDX-FileCopyrightText: Copyright (c) 2017-2022 slowtec GmbH <post@slowtec.de>
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
+pub(crate) mod actor;
+
+#[cfg(feature = "ascii")]
+pub(crate) mod ascii;
+
 #[cfg(feature = "rtu")]
 pub(crate) mod rtu;
 
 #[cfg(feature = "tcp")]
 pub(crate) mod tcp;
+
+#[cfg(feature = "udp")]
+pub(crate) mod udp;
+
+/// The response to a request that is broadcast and not answered,
+/// or `None` if a response is expected.
+///
+/// Requests that read data could not be broadcast.
+#[cfg(any(feature = "rtu", feature = "ascii", feature = "tcp"))]
+fn broadcast_response(
+    broadcast: crate::client::Broadcast,
+    slave: crate::slave::Slave,
+    req: &crate::frame::Request,
+) -> Result<Option<(crate::frame::Response, std::time::Duration)>, crate::Error> {
+    use crate::{client::Broadcast, frame::Request, Error};
+
+    let Broadcast::Enabled {
+        broadcast_ids,
+        turnaround_delay,
+    } = broadcast
+    else {
+        return Ok(None);
+    };
+    if !broadcast_ids.contains(slave) || *req == Request::Disconnect {
+        return Ok(None);
+    }
+    let Some(res) = req.broadcast_response() else {
+        return Err(Error::InvalidRequest(format!(
+            "Broadcast of {:?} is not answered",
+            req
+        )));
+    };
+    Ok(Some((res, turnaround_delay)))
+}
+
+/// Discard the response of a previous request that has been cancelled,
+/// e.g. after a timeout.
+///
+/// Only responses that have already been received can be discarded.
+/// Responses that arrive after the next request has been sent are
+/// skipped by [`is_response_to()`].
+#[cfg(any(feature = "rtu", feature = "ascii"))]
+fn discard_late_response<T, C>(framed: &mut tokio_util::codec::Framed<T, C>)
+where
+    T: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+    C: tokio_util::codec::Decoder,
+{
+    use futures_util::{future::FutureExt as _, stream::StreamExt as _};
+
+    while let Some(Some(_)) = framed.next().now_or_never() {
+        log::debug!("Discarding late response");
+    }
+    framed.read_buffer_mut().clear();
+}
+
+/// Check if a response on a serial line answers the outstanding request,
+/// i.e. if it has been sent by the addressed slave for the function of
+/// the request.
+///
+/// Serial line protocols have no transaction ids. Late responses of
+/// cancelled requests are only recognized if they have been sent by
+/// another slave or for another function.
+#[cfg(any(feature = "rtu", feature = "ascii"))]
+fn is_response_to(
+    slave_id: crate::slave::SlaveId,
+    function: crate::frame::FunctionCode,
+    rsp_slave_id: crate::slave::SlaveId,
+    rsp_pdu: &crate::frame::ResponsePdu,
+) -> bool {
+    let rsp_function = match &rsp_pdu.0 {
+        Ok(rsp) => crate::codec::rsp_to_fn_code(rsp),
+        Err(exception) => exception.function,
+    };
+    if rsp_slave_id == slave_id && rsp_function == function {
+        return true;
+    }
+    log::debug!(
+        "Discarding response of slave {} for function 0x{:0>2X}: expected slave {} and function 0x{:0>2X}",
+        rsp_slave_id,
+        rsp_function,
+        slave_id,
+        function
+    );
+    false
+}
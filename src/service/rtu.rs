@@ -2,17 +2,19 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
 use crate::{
-    client::Client,
+    client::{Broadcast, Client},
     codec,
     frame::{rtu::*, *},
     slave::*,
+    Error,
 };
 
 use futures_util::{future, sink::SinkExt as _, stream::StreamExt as _};
 use std::{
     fmt::Debug,
     future::Future,
-    io::{Error, ErrorKind},
+    io::{self, ErrorKind},
+    time::Duration,
 };
 use tokio::io::{AsyncRead, AsyncWrite};
 use tokio_util::codec::Framed;
@@ -20,16 +22,32 @@ use tokio_util::codec::Framed;
 pub(crate) fn connect_slave<T>(
     transport: T,
     slave: Slave,
-) -> impl Future<Output = Result<Context<T>, Error>>
+) -> impl Future<Output = Result<Context<T>, io::Error>>
 where
     T: AsyncRead + AsyncWrite + Debug + Unpin + 'static,
 {
-    let framed = Framed::new(transport, codec::rtu::ClientCodec::default());
+    connect_slave_with_codec(transport, slave, codec::rtu::ClientCodec::default())
+}
+
+pub(crate) fn connect_slave_with_codec<T>(
+    transport: T,
+    slave: Slave,
+    codec: codec::rtu::ClientCodec,
+) -> impl Future<Output = Result<Context<T>, io::Error>>
+where
+    T: AsyncRead + AsyncWrite + Debug + Unpin + 'static,
+{
+    let framed = Framed::new(transport, codec);
 
     let slave_id = slave.into();
     future::ok(Context {
         service: framed,
         slave_id,
+        response_pending: false,
+        broadcast: Broadcast::Enabled {
+            broadcast_ids: BroadcastIds::SERIAL_LINE,
+            turnaround_delay: Duration::ZERO,
+        },
     })
 }
 
@@ -38,6 +56,9 @@ where
 pub(crate) struct Context<T: AsyncRead + AsyncWrite + Debug + Unpin + 'static> {
     service: Framed<T, codec::rtu::ClientCodec>,
     slave_id: SlaveId,
+    /// The response of the previous request has not been received
+    response_pending: bool,
+    broadcast: Broadcast,
 }
 
 impl<T: AsyncRead + AsyncWrite + Unpin + Debug + 'static> Context<T> {
@@ -56,35 +77,62 @@ impl<T: AsyncRead + AsyncWrite + Unpin + Debug + 'static> Context<T> {
     }
 
     async fn call(&mut self, req: Request) -> Result<Response, Error> {
+        let broadcast_response =
+            super::broadcast_response(self.broadcast, Slave(self.slave_id), &req)?;
         let disconnect = req == Request::Disconnect;
+        let implicit_response = req.implicit_response();
+        let function = (!disconnect).then(|| codec::req_to_fn_code(&req));
         let req_adu = self.next_request_adu(req, disconnect);
         let req_hdr = req_adu.hdr;
 
+        if self.response_pending {
+            super::discard_late_response(&mut self.service);
+        }
+        self.response_pending = implicit_response.is_none() && broadcast_response.is_none();
         self.service.send(req_adu).await?;
-        let res_adu = self
-            .service
-            .next()
-            .await
-            .unwrap_or_else(|| Err(Error::from(ErrorKind::BrokenPipe)))?;
+        if let Some((res, turnaround_delay)) = broadcast_response {
+            tokio::time::sleep(turnaround_delay).await;
+            return Ok(res);
+        }
+        if let Some(res) = implicit_response {
+            return Ok(res);
+        }
+        // Encoding fails for requests without a function code
+        let function = function.ok_or_else(|| io::Error::from(ErrorKind::InvalidInput))?;
+        // Late responses of previous requests that have been cancelled,
+        // e.g. after a timeout, are skipped.
+        let res_adu = loop {
+            let res_adu = self.service.next().await;
+            let res_adu = res_adu
+                .unwrap_or_else(|| Err(io::Error::from(ErrorKind::BrokenPipe)))
+                .inspect_err(|_| self.response_pending = false)?;
+            if super::is_response_to(
+                req_hdr.slave_id,
+                function,
+                res_adu.hdr.slave_id,
+                &res_adu.pdu,
+            ) {
+                self.response_pending = false;
+                break res_adu;
+            }
+        };
 
         match res_adu.pdu {
-            ResponsePdu(Ok(res)) => verify_response_header(req_hdr, res_adu.hdr).and(Ok(res)),
-            ResponsePdu(Err(err)) => Err(Error::new(ErrorKind::Other, err)),
+            ResponsePdu(Ok(res)) => Ok(res),
+            ResponsePdu(Err(err)) => Err(Error::Exception(err)),
         }
     }
-}
 
-fn verify_response_header(req_hdr: Header, rsp_hdr: Header) -> Result<(), Error> {
-    if req_hdr != rsp_hdr {
-        return Err(Error::new(
-            ErrorKind::InvalidData,
-            format!(
-                "Invalid response header: expected/request = {:?}, actual/response = {:?}",
-                req_hdr, rsp_hdr
-            ),
-        ));
+    /// Send a request without waiting for a response, e.g. a broadcast.
+    pub(crate) async fn send(&mut self, req: Request) -> Result<(), Error> {
+        let req_adu = self.next_request_adu(req, false);
+        if self.response_pending {
+            super::discard_late_response(&mut self.service);
+            self.response_pending = false;
+        }
+        self.service.send(req_adu).await?;
+        Ok(())
     }
-    Ok(())
 }
 
 impl<T: AsyncRead + AsyncWrite + Debug + Unpin + 'static> SlaveContext for Context<T> {
@@ -98,6 +146,14 @@ impl<T: AsyncRead + AsyncWrite + Debug + Unpin + Send + 'static> Client for Cont
     async fn call(&mut self, req: Request) -> Result<Response, Error> {
         self.call(req).await
     }
+
+    fn set_broadcast(&mut self, broadcast: Broadcast) {
+        self.broadcast = broadcast;
+    }
+
+    fn rtu_error_counters(&self) -> Option<codec::rtu::ErrorCounters> {
+        Some(self.service.codec().error_counters().clone())
+    }
 }
 
 #[cfg(test)]
@@ -115,6 +171,7 @@ mod tests {
     impl Unpin for MockTransport {}
 
     #[tokio::test]
+    #[allow(non_local_definitions)]
     async fn handle_broken_pipe() {
         impl AsyncRead for MockTransport {
             fn poll_read(
@@ -146,7 +203,7 @@ mod tests {
 
         let transport = MockTransport {};
         let mut ctx =
-            crate::service::rtu::connect_slave(transport, crate::service::rtu::Slave::broadcast())
+            crate::service::rtu::connect_slave(transport, crate::service::rtu::Slave(0x01))
                 .await
                 .unwrap();
         let res = ctx
@@ -154,6 +211,129 @@ mod tests {
             .await;
         assert!(res.is_err());
         let err = res.err().unwrap();
-        assert_eq!(err.kind(), std::io::ErrorKind::BrokenPipe);
+        assert!(
+            matches!(err, crate::Error::Transport(err) if err.kind() == std::io::ErrorKind::BrokenPipe)
+        );
+    }
+
+    #[tokio::test]
+    async fn skip_malformed_response() {
+        use crate::{client::Client as _, frame::Response};
+        use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+
+        let (client_io, mut server_io) = tokio::io::duplex(1024);
+        tokio::spawn(async move {
+            let mut request = [0; 8];
+            server_io.read_exact(&mut request).await.unwrap();
+            server_io
+                .write_all(&[
+                    // Byte count does not match FIFO count
+                    0x01, 0x18, 0x00, 0x04, 0x00, 0x02, 0x01, 0xB8, 0x48, 0x24,
+                    // Valid response
+                    0x01, 0x06, 0x00, 0x01, 0x00, 0x03, 0x98, 0x0B,
+                ])
+                .await
+                .unwrap();
+        });
+
+        let mut ctx = crate::service::rtu::connect_slave(client_io, crate::slave::Slave(0x01))
+            .await
+            .unwrap();
+        let rsp = ctx
+            .call(crate::service::rtu::Request::WriteSingleRegister(
+                0x01, 0x03,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(rsp, Response::WriteSingleRegister(0x01, 0x03));
+        let error_counters = ctx.rtu_error_counters().unwrap();
+        assert_eq!(error_counters.malformed_pdus(), 1);
+        assert_eq!(error_counters.crc_errors(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn discard_late_response_after_timeout() {
+        use crate::frame::Response;
+        use std::time::Duration;
+        use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+
+        let (client_io, mut server_io) = tokio::io::duplex(1024);
+        tokio::spawn(async move {
+            let mut request = [0; 8];
+            server_io.read_exact(&mut request).await.unwrap();
+            tokio::time::sleep(Duration::from_secs(2)).await;
+            // Late response
+            server_io
+                .write_all(&[0x01, 0x06, 0x00, 0x01, 0x00, 0x03, 0x98, 0x0B])
+                .await
+                .unwrap();
+            server_io.read_exact(&mut request).await.unwrap();
+            server_io
+                .write_all(&[0x01, 0x06, 0x00, 0x01, 0x00, 0x00, 0xD8, 0x0A])
+                .await
+                .unwrap();
+        });
+
+        let mut ctx = crate::service::rtu::connect_slave(client_io, crate::slave::Slave(0x01))
+            .await
+            .unwrap();
+        let res = tokio::time::timeout(
+            Duration::from_secs(1),
+            ctx.call(crate::service::rtu::Request::WriteSingleRegister(
+                0x01, 0x03,
+            )),
+        )
+        .await;
+        assert!(res.is_err());
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        let rsp = ctx
+            .call(crate::service::rtu::Request::WriteSingleRegister(
+                0x01, 0x00,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(rsp, Response::WriteSingleRegister(0x01, 0x00));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn skip_late_response_after_next_request() {
+        use crate::frame::Response;
+        use std::time::Duration;
+        use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+
+        let (client_io, mut server_io) = tokio::io::duplex(1024);
+        tokio::spawn(async move {
+            let mut request = [0; 8];
+            server_io.read_exact(&mut request).await.unwrap();
+            server_io.read_exact(&mut request).await.unwrap();
+            server_io
+                .write_all(&[
+                    // Late response to the first request
+                    0x01, 0x03, 0x02, 0x12, 0x34, 0xB5, 0x33,
+                    // Response of another slave
+                    0x02, 0x06, 0x00, 0x01, 0x00, 0x00, 0xD8, 0x39,
+                    // Response to the second request
+                    0x01, 0x06, 0x00, 0x01, 0x00, 0x00, 0xD8, 0x0A,
+                ])
+                .await
+                .unwrap();
+        });
+
+        let mut ctx = crate::service::rtu::connect_slave(client_io, crate::slave::Slave(0x01))
+            .await
+            .unwrap();
+        let res = tokio::time::timeout(
+            Duration::from_secs(1),
+            ctx.call(crate::service::rtu::Request::ReadHoldingRegisters(0x00, 1)),
+        )
+        .await;
+        assert!(res.is_err());
+        let rsp = ctx
+            .call(crate::service::rtu::Request::WriteSingleRegister(
+                0x01, 0x00,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(rsp, Response::WriteSingleRegister(0x01, 0x00));
     }
 }
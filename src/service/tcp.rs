@@ -2,53 +2,64 @@
 // SPDX-License-Identifier: MIT OR Apache-2.0
 
 use crate::{
-    client::Client,
+    client::{Broadcast, Client},
     codec,
     frame::{tcp::*, *},
     slave::*,
+    Error,
 };
 
 use futures_util::{sink::SinkExt as _, stream::StreamExt as _};
 use std::{
-    future::Future,
-    io::{Error, ErrorKind},
+    fmt::Debug,
+    io,
     net::SocketAddr,
     sync::atomic::{AtomicU16, Ordering},
 };
-use tokio::net::TcpStream;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+};
 use tokio_util::codec::Framed;
 
-pub(crate) fn connect_slave(
+pub(crate) mod pipeline;
+
+pub(crate) async fn connect_slave(
     socket_addr: SocketAddr,
     slave: Slave,
-) -> impl Future<Output = Result<Context, Error>> + 'static {
-    let unit_id: UnitId = slave.into();
-    async move {
-        let service = TcpStream::connect(socket_addr).await?;
-        let framed = Framed::new(service, codec::tcp::ClientCodec::default());
-
-        let context: Context = Context::new(framed, unit_id);
+) -> Result<Context<TcpStream>, io::Error> {
+    let transport = TcpStream::connect(socket_addr).await?;
+    Ok(attach_slave(transport, slave))
+}
 
-        Ok(context)
-    }
+/// Attach a Modbus TCP client to an already established connection,
+/// e.g. a TLS stream on top of a TCP connection.
+pub(crate) fn attach_slave<T>(transport: T, slave: Slave) -> Context<T>
+where
+    T: AsyncRead + AsyncWrite + Debug + Unpin + 'static,
+{
+    let framed = Framed::new(transport, codec::tcp::ClientCodec::default());
+    Context::new(framed, slave.into())
 }
 
 const INITIAL_TRANSACTION_ID: TransactionId = 0;
 
 /// Modbus TCP client
 #[derive(Debug)]
-pub(crate) struct Context {
-    service: Framed<TcpStream, codec::tcp::ClientCodec>,
+pub(crate) struct Context<T: AsyncRead + AsyncWrite + Debug + Unpin + 'static> {
+    service: Framed<T, codec::tcp::ClientCodec>,
     unit_id: UnitId,
     transaction_id: AtomicU16,
+    broadcast: Broadcast,
 }
 
-impl Context {
-    fn new(service: Framed<TcpStream, codec::tcp::ClientCodec>, unit_id: UnitId) -> Self {
+impl<T: AsyncRead + AsyncWrite + Debug + Unpin + 'static> Context<T> {
+    fn new(service: Framed<T, codec::tcp::ClientCodec>, unit_id: UnitId) -> Self {
         Self {
             service,
             unit_id,
             transaction_id: AtomicU16::new(INITIAL_TRANSACTION_ID),
+            broadcast: Broadcast::Disabled,
         }
     }
 
@@ -80,46 +91,133 @@ impl Context {
 
     pub(crate) async fn call(&mut self, req: Request) -> Result<Response, Error> {
         log::debug!("Call {:?}", req);
+        let broadcast_response =
+            super::broadcast_response(self.broadcast, Slave(self.unit_id), &req)?;
         let disconnect = req == Request::Disconnect;
+        let implicit_response = req.implicit_response();
         let req_adu = self.next_request_adu(req, disconnect);
         let req_hdr = req_adu.hdr;
 
         self.service.send(req_adu).await?;
-        let res_adu = self
-            .service
-            .next()
-            .await
-            .ok_or_else(Error::last_os_error)??;
+        if let Some((res, turnaround_delay)) = broadcast_response {
+            tokio::time::sleep(turnaround_delay).await;
+            return Ok(res);
+        }
+        if let Some(res) = implicit_response {
+            return Ok(res);
+        }
+        // Late responses of previous requests that have been cancelled,
+        // e.g. after a timeout, are discarded.
+        let res_adu = loop {
+            let res_adu = self
+                .service
+                .next()
+                .await
+                .ok_or_else(io::Error::last_os_error)??;
+            if res_adu.hdr.transaction_id == req_hdr.transaction_id {
+                break res_adu;
+            }
+            log::debug!(
+                "Discarding response with unexpected transaction id: expected = {}, actual = {}",
+                req_hdr.transaction_id,
+                res_adu.hdr.transaction_id
+            );
+        };
 
         match res_adu.pdu {
             ResponsePdu(Ok(res)) => verify_response_header(req_hdr, res_adu.hdr).and(Ok(res)),
-            ResponsePdu(Err(err)) => Err(Error::new(ErrorKind::Other, err)),
+            ResponsePdu(Err(err)) => Err(Error::Exception(err)),
         }
     }
 }
 
 fn verify_response_header(req_hdr: Header, rsp_hdr: Header) -> Result<(), Error> {
     if req_hdr != rsp_hdr {
-        return Err(Error::new(
-            ErrorKind::InvalidData,
-            format!(
-                "Invalid response header: expected/request = {:?}, actual/response = {:?}",
-                req_hdr, rsp_hdr
-            ),
-        ));
+        return Err(Error::UnexpectedResponse(format!(
+            "Invalid response header: expected/request = {:?}, actual/response = {:?}",
+            req_hdr, rsp_hdr
+        )));
     }
     Ok(())
 }
 
-impl SlaveContext for Context {
+impl<T: AsyncRead + AsyncWrite + Debug + Unpin + 'static> SlaveContext for Context<T> {
     fn set_slave(&mut self, slave: Slave) {
         self.unit_id = slave.into();
     }
 }
 
 #[async_trait::async_trait]
-impl Client for Context {
+impl<T: AsyncRead + AsyncWrite + Debug + Unpin + Send + 'static> Client for Context<T> {
     async fn call(&mut self, req: Request) -> Result<Response, Error> {
         Context::call(self, req).await
     }
+
+    fn set_broadcast(&mut self, broadcast: Broadcast) {
+        self.broadcast = broadcast;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+
+    #[tokio::test(start_paused = true)]
+    async fn discard_late_response_after_timeout() {
+        let (client_io, mut server_io) = tokio::io::duplex(1024);
+        let mut ctx = attach_slave(client_io, Slave(0x11));
+        tokio::spawn(async move {
+            let mut request = [0; 12];
+            server_io.read_exact(&mut request).await.unwrap();
+            server_io.read_exact(&mut request).await.unwrap();
+            // The late response of the first request precedes the second response
+            for (transaction_id, value) in [(0x0000_u16, 0xDEAD_u16), (0x0001, 0x0000)] {
+                let mut rsp = transaction_id.to_be_bytes().to_vec();
+                rsp.extend_from_slice(&[0x00, 0x00, 0x00, 0x06, 0x11, 0x06, 0x00, 0x01]);
+                rsp.extend_from_slice(&value.to_be_bytes());
+                server_io.write_all(&rsp).await.unwrap();
+            }
+        });
+
+        let res = tokio::time::timeout(
+            Duration::from_secs(1),
+            ctx.call(Request::WriteSingleRegister(0x01, 0xDEAD)),
+        )
+        .await;
+        assert!(res.is_err());
+        let rsp = ctx.call(Request::WriteSingleRegister(0x01, 0x00)).await;
+        assert_eq!(rsp.unwrap(), Response::WriteSingleRegister(0x01, 0x00));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn do_not_wait_for_response_to_broadcast_if_enabled() {
+        let (client_io, mut server_io) = tokio::io::duplex(1024);
+        let mut ctx = attach_slave(client_io, Slave::broadcast());
+        ctx.set_broadcast(Broadcast::Enabled {
+            broadcast_ids: BroadcastIds::NONE
+                .with(Slave::broadcast())
+                .with(Slave::tcp_device()),
+            turnaround_delay: Duration::from_millis(100),
+        });
+
+        let start = tokio::time::Instant::now();
+        let rsp = ctx.call(Request::WriteSingleRegister(0x01, 0x03)).await;
+        assert_eq!(rsp.unwrap(), Response::WriteSingleRegister(0x01, 0x03));
+        assert_eq!(start.elapsed(), Duration::from_millis(100));
+        let mut request = [0; 12];
+        server_io.read_exact(&mut request).await.unwrap();
+        assert_eq!(request[6], 0x00);
+
+        ctx.set_slave(Slave::tcp_device());
+        let rsp = ctx.call(Request::WriteSingleRegister(0x01, 0x03)).await;
+        assert_eq!(rsp.unwrap(), Response::WriteSingleRegister(0x01, 0x03));
+        server_io.read_exact(&mut request).await.unwrap();
+        assert_eq!(request[6], 0xFF);
+
+        let res = ctx.call(Request::ReadHoldingRegisters(0x01, 1)).await;
+        assert!(matches!(res, Err(Error::InvalidRequest(_))));
+    }
 }
@@ -0,0 +1,373 @@
+// SPDX-FileCopyrightText: Copyright (c) 2017-2022 slowtec GmbH <post@slowtec.de>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+//! Pipelining of multiple outstanding transactions on a single connection
+
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    future::poll_fn,
+    io::{self, ErrorKind},
+    sync::Arc,
+    task::Poll,
+};
+
+use futures_util::{sink::SinkExt as _, stream::StreamExt as _};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    sync::{mpsc, oneshot, OwnedSemaphorePermit, Semaphore},
+};
+use tokio_util::codec::Framed;
+
+use crate::{
+    client::Client,
+    codec,
+    frame::{tcp::*, *},
+    service::actor::{self, closed},
+    slave::*,
+    Error,
+};
+
+use super::{verify_response_header, INITIAL_TRANSACTION_ID};
+
+type ResponseSender = oneshot::Sender<Result<Response, Error>>;
+
+/// The addressed unit of a request that is passed to the connection task.
+#[derive(Debug)]
+struct Target {
+    unit_id: UnitId,
+    /// Released after the response has been received, i.e. it limits
+    /// the number of outstanding requests even if callers give up.
+    permit: OwnedSemaphorePermit,
+}
+
+type Transaction = actor::Command<Target>;
+
+/// A request that has been sent and awaits its response.
+///
+/// Requests that have been cancelled by the caller are kept until
+/// their late response arrives, i.e. they still occupy their
+/// transaction id and permit.
+#[derive(Debug)]
+struct Pending {
+    hdr: Header,
+    response_tx: ResponseSender,
+    _permit: OwnedSemaphorePermit,
+}
+
+/// An event of the connection task.
+enum Event {
+    Transaction(Option<Transaction>),
+    Response(Option<io::Result<ResponseAdu>>),
+}
+
+/// Modbus TCP client that sends requests without waiting for
+/// the responses of previous requests.
+///
+/// Transaction ids are assigned to all requests and responses are
+/// matched by their transaction id, i.e. the server might respond
+/// in any order. Handles are cheap to clone and could be used
+/// concurrently from many tasks. Each handle addresses its own
+/// slave device.
+///
+/// The connection is closed after the last handle has been dropped.
+#[derive(Debug, Clone)]
+pub struct Pipeline {
+    transaction_tx: mpsc::Sender<Transaction>,
+    permits: Arc<Semaphore>,
+    unit_id: UnitId,
+}
+
+/// Attach a pipelined Modbus TCP client to an established connection.
+///
+/// At most `max_outstanding` requests are awaiting their responses at
+/// the same time. Further requests wait until a response has been
+/// received.
+pub(crate) fn attach_slave<T>(transport: T, slave: Slave, max_outstanding: usize) -> Pipeline
+where
+    T: AsyncRead + AsyncWrite + Debug + Unpin + Send + 'static,
+{
+    let max_outstanding = max_outstanding.max(1);
+    let framed = Framed::new(transport, codec::tcp::ClientCodec::default());
+    let (transaction_tx, transaction_rx) = mpsc::channel(max_outstanding);
+    tokio::spawn(run(framed, transaction_rx));
+    Pipeline {
+        transaction_tx,
+        permits: Arc::new(Semaphore::new(max_outstanding)),
+        unit_id: slave.into(),
+    }
+}
+
+impl Pipeline {
+    /// Invoke a Modbus function.
+    ///
+    /// Unlike [`Client::call`] this does not require exclusive access
+    /// to the handle.
+    pub async fn call(&self, request: Request) -> Result<Response, Error> {
+        log::debug!("Call {:?}", request);
+        let permit = Arc::clone(&self.permits)
+            .acquire_owned()
+            .await
+            .map_err(|_| closed())?;
+        let target = Target {
+            unit_id: self.unit_id,
+            permit,
+        };
+        actor::call(&self.transaction_tx, target, request).await
+    }
+
+    /// A new handle for the same connection that addresses
+    /// another slave device.
+    #[must_use]
+    pub fn with_slave(&self, slave: Slave) -> Self {
+        Self {
+            unit_id: slave.into(),
+            ..self.clone()
+        }
+    }
+
+    /// Check if the connection has been closed.
+    #[must_use]
+    pub fn is_closed(&self) -> bool {
+        self.transaction_tx.is_closed()
+    }
+}
+
+impl SlaveContext for Pipeline {
+    fn set_slave(&mut self, slave: Slave) {
+        self.unit_id = slave.into();
+    }
+}
+
+#[async_trait::async_trait]
+impl Client for Pipeline {
+    async fn call(&mut self, request: Request) -> Result<Response, Error> {
+        Pipeline::call(self, request).await
+    }
+}
+
+/// Send requests and route the responses until either the
+/// connection breaks or all handles have been dropped.
+async fn run<T>(
+    mut framed: Framed<T, codec::tcp::ClientCodec>,
+    mut transaction_rx: mpsc::Receiver<Transaction>,
+) where
+    T: AsyncRead + AsyncWrite + Debug + Unpin,
+{
+    let mut pending: HashMap<TransactionId, Pending> = HashMap::new();
+    let mut next_transaction_id = INITIAL_TRANSACTION_ID;
+    let err = loop {
+        let event = poll_fn(|cx| {
+            if let Poll::Ready(transaction) = transaction_rx.poll_recv(cx) {
+                return Poll::Ready(Event::Transaction(transaction));
+            }
+            framed.poll_next_unpin(cx).map(Event::Response)
+        })
+        .await;
+        match event {
+            Event::Transaction(transaction) => {
+                let Some(Transaction {
+                    target: Target { unit_id, permit },
+                    request,
+                    response_tx,
+                }) = transaction
+                else {
+                    log::debug!("Closing connection after the last handle has been dropped");
+                    return;
+                };
+                while pending.contains_key(&next_transaction_id) {
+                    next_transaction_id = next_transaction_id.wrapping_add(1);
+                }
+                let hdr = Header {
+                    transaction_id: next_transaction_id,
+                    unit_id,
+                };
+                next_transaction_id = next_transaction_id.wrapping_add(1);
+                let disconnect = request == Request::Disconnect;
+                let implicit_response = request.implicit_response();
+                let req_adu = RequestAdu {
+                    hdr,
+                    pdu: request.into(),
+                    disconnect,
+                };
+                if let Err(err) = framed.send(req_adu).await {
+                    let kind = err.kind();
+                    let _ = response_tx.send(Err(err.into()));
+                    break io::Error::new(kind, "Failed to send request");
+                }
+                if let Some(res) = implicit_response {
+                    let _ = response_tx.send(Ok(res));
+                    continue;
+                }
+                pending.insert(
+                    hdr.transaction_id,
+                    Pending {
+                        hdr,
+                        response_tx,
+                        _permit: permit,
+                    },
+                );
+            }
+            Event::Response(res_adu) => {
+                let res_adu = match res_adu {
+                    Some(Ok(res_adu)) => res_adu,
+                    Some(Err(err)) => break err,
+                    None => {
+                        break io::Error::new(ErrorKind::BrokenPipe, "Connection closed by peer");
+                    }
+                };
+                let transaction_id = res_adu.hdr.transaction_id;
+                let Some(Pending {
+                    hdr, response_tx, ..
+                }) = pending.remove(&transaction_id)
+                else {
+                    log::debug!(
+                        "Discarding response with unknown transaction id {}",
+                        transaction_id
+                    );
+                    continue;
+                };
+                let res = match res_adu.pdu {
+                    ResponsePdu(Ok(res)) => verify_response_header(hdr, res_adu.hdr).and(Ok(res)),
+                    ResponsePdu(Err(err)) => Err(Error::Exception(err)),
+                };
+                let _ = response_tx.send(res);
+            }
+        }
+    };
+    log::debug!("Closing connection: {}", err);
+    drop(transaction_rx);
+    for (_, Pending { response_tx, .. }) in pending {
+        let _ = response_tx.send(Err(Error::Transport(io::Error::new(
+            err.kind(),
+            err.to_string(),
+        ))));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _, DuplexStream};
+
+    use super::*;
+
+    async fn read_request(server_io: &mut DuplexStream) -> [u8; 12] {
+        let mut request = [0; 12];
+        server_io.read_exact(&mut request).await.unwrap();
+        request
+    }
+
+    /// Respond to a Read Holding Registers request with the transaction id
+    async fn write_response(server_io: &mut DuplexStream, request: [u8; 12]) {
+        let mut response = request[..4].to_vec();
+        response.extend_from_slice(&[0x00, 0x05, request[6], 0x03, 0x02]);
+        response.extend_from_slice(&request[..2]);
+        server_io.write_all(&response).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn route_responses_by_transaction_id() {
+        let (client_io, mut server_io) = tokio::io::duplex(1024);
+        let pipeline = attach_slave(client_io, Slave(0x11), 2);
+        tokio::spawn(async move {
+            let first = read_request(&mut server_io).await;
+            let second = read_request(&mut server_io).await;
+            // Respond in reverse order
+            write_response(&mut server_io, second).await;
+            write_response(&mut server_io, first).await;
+        });
+
+        let other = pipeline.with_slave(Slave(0x22));
+        let (first, second) = tokio::join!(
+            pipeline.call(Request::ReadHoldingRegisters(0x00, 1)),
+            other.call(Request::ReadHoldingRegisters(0x00, 1)),
+        );
+        assert_eq!(first.unwrap(), Response::ReadHoldingRegisters(vec![0x0000]));
+        assert_eq!(
+            second.unwrap(),
+            Response::ReadHoldingRegisters(vec![0x0001])
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn limit_outstanding_requests() {
+        let (client_io, mut server_io) = tokio::io::duplex(1024);
+        let pipeline = attach_slave(client_io, Slave(0x11), 1);
+        let server = tokio::spawn(async move {
+            let request = read_request(&mut server_io).await;
+            // The second request is not sent before the first response
+            let mut buf = [0; 1];
+            let res = tokio::time::timeout(Duration::from_secs(1), server_io.read(&mut buf)).await;
+            assert!(res.is_err());
+            write_response(&mut server_io, request).await;
+            let request = read_request(&mut server_io).await;
+            write_response(&mut server_io, request).await;
+        });
+
+        let (first, second) = tokio::join!(
+            pipeline.call(Request::ReadHoldingRegisters(0x00, 1)),
+            pipeline.call(Request::ReadHoldingRegisters(0x00, 1)),
+        );
+        assert!(first.is_ok());
+        assert!(second.is_ok());
+        server.await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn hold_permit_of_cancelled_request_until_response() {
+        let (client_io, mut server_io) = tokio::io::duplex(1024);
+        let pipeline = attach_slave(client_io, Slave(0x11), 1);
+        let server = tokio::spawn(async move {
+            let first = read_request(&mut server_io).await;
+            // The second request is not sent before the late response
+            // to the cancelled request
+            let mut buf = [0; 1];
+            let res = tokio::time::timeout(Duration::from_secs(5), server_io.read(&mut buf)).await;
+            assert!(res.is_err());
+            write_response(&mut server_io, first).await;
+            let second = read_request(&mut server_io).await;
+            write_response(&mut server_io, second).await;
+        });
+
+        let res = tokio::time::timeout(
+            Duration::from_secs(1),
+            pipeline.call(Request::ReadHoldingRegisters(0x00, 1)),
+        )
+        .await;
+        assert!(res.is_err());
+        let res = pipeline.call(Request::ReadHoldingRegisters(0x00, 1)).await;
+        assert_eq!(res.unwrap(), Response::ReadHoldingRegisters(vec![0x0001]));
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn fail_outstanding_requests_if_connection_breaks() {
+        let (client_io, mut server_io) = tokio::io::duplex(1024);
+        let pipeline = attach_slave(client_io, Slave(0x11), 2);
+        tokio::spawn(async move {
+            read_request(&mut server_io).await;
+            read_request(&mut server_io).await;
+        });
+
+        let (first, second) = tokio::join!(
+            pipeline.call(Request::ReadHoldingRegisters(0x00, 1)),
+            pipeline.call(Request::ReadHoldingRegisters(0x00, 1)),
+        );
+        assert_eq!(first.unwrap_err().class(), crate::ErrorClass::Transport);
+        assert_eq!(second.unwrap_err().class(), crate::ErrorClass::Transport);
+        assert!(pipeline.is_closed());
+    }
+
+    #[tokio::test]
+    async fn close_connection_after_last_handle_dropped() {
+        let (client_io, mut server_io) = tokio::io::duplex(1024);
+        let pipeline = attach_slave(client_io, Slave(0x11), 1);
+        let other = pipeline.clone();
+        drop(pipeline);
+        drop(other);
+        let mut buf = [0; 1];
+        assert_eq!(server_io.read(&mut buf).await.unwrap(), 0);
+    }
+}
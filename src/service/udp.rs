@@ -0,0 +1,206 @@
+// SPDX-FileCopyrightText: Copyright (c) 2017-2022 slowtec GmbH <post@slowtec.de>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+
+use crate::{
+    client::Client,
+    codec,
+    frame::{tcp::*, *},
+    slave::*,
+    Error,
+};
+
+use bytes::BytesMut;
+use log::{debug, warn};
+use std::{
+    io::{self, ErrorKind},
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+use tokio::{net::UdpSocket, time::Instant};
+use tokio_util::codec::{Decoder as _, Encoder as _};
+
+/// The maximum size of an MBAP header (7 bytes) followed by a PDU (253 bytes).
+const MAX_ADU_LEN: usize = 260;
+
+pub(crate) async fn connect_slave(
+    socket_addr: SocketAddr,
+    slave: Slave,
+    timeout: Duration,
+) -> Result<Context, io::Error> {
+    let local_addr: SocketAddr = match socket_addr {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(local_addr).await?;
+    socket.connect(socket_addr).await?;
+    Ok(Context::new(socket, slave.into(), timeout))
+}
+
+const INITIAL_TRANSACTION_ID: TransactionId = 0;
+
+/// Modbus UDP client
+#[derive(Debug)]
+pub(crate) struct Context {
+    socket: UdpSocket,
+    codec: codec::tcp::ClientCodec,
+    unit_id: UnitId,
+    transaction_id: TransactionId,
+    timeout: Duration,
+}
+
+impl Context {
+    fn new(socket: UdpSocket, unit_id: UnitId, timeout: Duration) -> Self {
+        Self {
+            socket,
+            codec: codec::tcp::ClientCodec::default(),
+            unit_id,
+            transaction_id: INITIAL_TRANSACTION_ID,
+            timeout,
+        }
+    }
+
+    fn next_request_adu<R>(&mut self, req: R) -> RequestAdu
+    where
+        R: Into<RequestPdu>,
+    {
+        let transaction_id = self.transaction_id;
+        self.transaction_id = transaction_id.wrapping_add(1);
+        RequestAdu {
+            hdr: Header {
+                transaction_id,
+                unit_id: self.unit_id,
+            },
+            pdu: req.into(),
+            disconnect: false,
+        }
+    }
+
+    pub(crate) async fn call(&mut self, req: Request) -> Result<Response, Error> {
+        log::debug!("Call {:?}", req);
+        if req == Request::Disconnect {
+            // There is no connection that could be closed
+            return Err(Error::Transport(io::Error::new(
+                ErrorKind::NotConnected,
+                "Disconnecting - not an error",
+            )));
+        }
+        let implicit_response = req.implicit_response();
+        let req_adu = self.next_request_adu(req);
+        let req_hdr = req_adu.hdr;
+
+        let mut buf = BytesMut::with_capacity(MAX_ADU_LEN);
+        self.codec.encode(req_adu, &mut buf)?;
+        self.socket.send(&buf).await?;
+        if let Some(res) = implicit_response {
+            return Ok(res);
+        }
+
+        // Datagrams might get lost, duplicated, or arrive late. Only the
+        // response with a matching transaction id is accepted until the
+        // deadline expires.
+        let deadline = Instant::now() + self.timeout;
+        let mut datagram = [0; MAX_ADU_LEN];
+        loop {
+            let len = tokio::time::timeout_at(deadline, self.socket.recv(&mut datagram))
+                .await
+                .map_err(|_| Error::Timeout)??;
+            let mut buf = BytesMut::from(&datagram[..len]);
+            let res_adu = match self.codec.decode(&mut buf) {
+                Ok(Some(res_adu)) => res_adu,
+                Ok(None) => {
+                    warn!("Discarding incomplete datagram: {:X?}", &datagram[..len]);
+                    continue;
+                }
+                Err(err) => {
+                    warn!("Discarding invalid datagram: {}", err);
+                    continue;
+                }
+            };
+            if res_adu.hdr.transaction_id != req_hdr.transaction_id {
+                debug!(
+                    "Discarding response with unexpected transaction id: expected = {}, actual = {}",
+                    req_hdr.transaction_id, res_adu.hdr.transaction_id
+                );
+                continue;
+            }
+            return match res_adu.pdu {
+                ResponsePdu(Ok(res)) => verify_response_header(req_hdr, res_adu.hdr).and(Ok(res)),
+                ResponsePdu(Err(err)) => Err(Error::Exception(err)),
+            };
+        }
+    }
+}
+
+fn verify_response_header(req_hdr: Header, rsp_hdr: Header) -> Result<(), Error> {
+    if req_hdr != rsp_hdr {
+        return Err(Error::UnexpectedResponse(format!(
+            "Invalid response header: expected/request = {:?}, actual/response = {:?}",
+            req_hdr, rsp_hdr
+        )));
+    }
+    Ok(())
+}
+
+impl SlaveContext for Context {
+    fn set_slave(&mut self, slave: Slave) {
+        self.unit_id = slave.into();
+    }
+}
+
+#[async_trait::async_trait]
+impl Client for Context {
+    async fn call(&mut self, req: Request) -> Result<Response, Error> {
+        Context::call(self, req).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bytes::BufMut as _;
+
+    #[tokio::test]
+    async fn timeout_without_response() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut ctx = connect_slave(
+            server.local_addr().unwrap(),
+            Slave::tcp_device(),
+            Duration::from_millis(10),
+        )
+        .await
+        .unwrap();
+        let err = ctx.call(Request::ReadCoils(0x00, 5)).await.unwrap_err();
+        assert!(matches!(err, Error::Timeout));
+    }
+
+    #[tokio::test]
+    async fn discard_response_with_unexpected_transaction_id() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut ctx = connect_slave(
+            server.local_addr().unwrap(),
+            Slave(0x11),
+            Duration::from_secs(5),
+        )
+        .await
+        .unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0; MAX_ADU_LEN];
+            let (_, peer) = server.recv_from(&mut buf).await.unwrap();
+            let transaction_id = u16::from_be_bytes([buf[0], buf[1]]);
+            // A late response of a previous request precedes the expected response
+            for (transaction_id, value) in [
+                (transaction_id.wrapping_sub(1), 0xDEAD),
+                (transaction_id, 0x0000),
+            ] {
+                let mut rsp = BytesMut::new();
+                rsp.put_u16(transaction_id);
+                rsp.put_slice(&[0x00, 0x00, 0x00, 0x06, 0x11, 0x06, 0x00, 0x01]);
+                rsp.put_u16(value);
+                server.send_to(&rsp, peer).await.unwrap();
+            }
+        });
+        let rsp = ctx.call(Request::WriteSingleRegister(0x01, 0x00)).await;
+        assert_eq!(rsp.unwrap(), Response::WriteSingleRegister(0x01, 0x00));
+    }
+}
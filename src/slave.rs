@@ -104,6 +104,36 @@ impl fmt::Display for Slave {
     }
 }
 
+/// A set of addresses for sending broadcast messages, see
+/// [`Slave::broadcast()`].
+///
+/// Serial lines only use the address 0. Modbus TCP gateways might
+/// also treat other unit ids like [`Slave::tcp_device()`] as broadcasts.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BroadcastIds([u128; 2]);
+
+impl BroadcastIds {
+    /// The empty set, i.e. no broadcasts.
+    pub const NONE: Self = Self([0; 2]);
+
+    /// Only [`Slave::broadcast()`] as used on serial lines.
+    pub const SERIAL_LINE: Self = Self::NONE.with(Slave::broadcast());
+
+    /// Add an address to the set.
+    #[must_use]
+    pub const fn with(self, slave: Slave) -> Self {
+        let Self(mut bits) = self;
+        bits[(slave.0 / 128) as usize] |= 1 << (slave.0 % 128);
+        Self(bits)
+    }
+
+    /// Check if the address is contained in the set.
+    #[must_use]
+    pub const fn contains(self, slave: Slave) -> bool {
+        self.0[(slave.0 / 128) as usize] & (1 << (slave.0 % 128)) != 0
+    }
+}
+
 /// Stateful management of the currently active device.
 ///
 /// RTU devices are addressed by their assigned *slave id*.
@@ -124,6 +154,20 @@ pub trait SlaveContext {
 mod tests {
     use super::*;
 
+    #[test]
+    fn broadcast_ids() {
+        assert!(BroadcastIds::SERIAL_LINE.contains(Slave::broadcast()));
+        assert!(!BroadcastIds::SERIAL_LINE.contains(Slave::tcp_device()));
+        let ids = BroadcastIds::NONE
+            .with(Slave::broadcast())
+            .with(Slave::tcp_device());
+        assert!(ids.contains(Slave(0)));
+        assert!(ids.contains(Slave(255)));
+        assert!(!ids.contains(Slave(1)));
+        assert!(!ids.contains(Slave(127)));
+        assert_eq!(BroadcastIds::default(), BroadcastIds::NONE);
+    }
+
     #[test]
     fn parse_dec() {
         assert_eq!(Slave(0), Slave::from_str("0").unwrap());